@@ -1,61 +1,191 @@
+use crate::arpeggiator::ArpParams;
 use crate::audio::AudioManager;
-use crate::keyboard::OnScreenKeyboard;
-use crate::midi::MidiReader;
+use crate::effects::{gain_to_db, EffectChain, LimiterParams, MasterChain};
+use crate::keyboard::{self, OnScreenKeyboard};
+use crate::meter::Meters;
+use crate::metronome::{Metronome, MetronomeParams};
+use crate::midi::{MidiReader, MidiWriter};
+use crate::midi_map::{CcMode, MidiMap};
+use crate::mixer::Mixer;
+use crate::monitor::MidiMonitor;
+use crate::param::ParamSet;
+use crate::patch::Patch;
 use crate::periodic_updater::PeriodicUpdater;
-use crate::synth::{Synth, Params};
+use crate::presets::{BankSelect, Presets};
+use crate::program_loader::ProgramLoader;
+use crate::rack::{gain_reduction_meter, param_widget, EffectRack};
+use crate::scope::Oscilloscope;
+use crate::sequencer::{
+    PatternsEditor, SequencerParams, MAX_STEP_NOTES, NUM_PATTERNS, STEP_COUNTS,
+};
+use crate::spectrogram::Spectrogram;
+use crate::spectrum::SpectrumAnalyzer;
+use crate::synth::{Params, Programs, Synth, NUM_PARTS};
+use crate::transport::TransportParams;
+use crate::zones::{Zone, Zones, MAX_ZONES};
 use cpal::traits::DeviceTrait;
 use crossbeam::channel;
 use eframe::{
     egui,
     epi::{self, App},
 };
+use log::warn;
 use parking_lot::Mutex;
 use std::{collections::VecDeque, sync::Arc};
 
 const NAME: &str = "Wayfärer";
-const VIS_SIZE: usize = 512;
+const MIDI_MAP_KEY: &str = "midi map";
+const PRESETS_KEY: &str = "presets";
+const ZONES_KEY: &str = "zones";
+const METER_MIN_DB: f32 = -60.;
+/// enough for the longest oscilloscope timebase and a trigger search before it
+const VIS_HISTORY_SIZE: usize = 0x10000;
 
 pub struct Data {
-    audio: AudioManager<Synth>,
+    audio: AudioManager<Metronome<MasterChain<Synth>>>,
     midi: Arc<MidiReader>,
+    midi_out: Arc<MidiWriter>,
     status_text: Arc<Mutex<String>>,
     keyboard: OnScreenKeyboard,
+    monitor: MidiMonitor,
     forced_buffer_size: Option<u32>,
-    left_vis_buffer: VecDeque<f32>,
-    synth_params: Arc<Params>,
+    vis_buffer: VecDeque<[f32; 2]>,
+    scope: Oscilloscope,
+    spectrum: SpectrumAnalyzer,
+    spectrogram: Spectrogram,
+    /// one per part
+    synth_params: Vec<Arc<Params>>,
+    mixer: Mixer,
+    midi_map: Arc<Mutex<MidiMap>>,
+    zones: Arc<Mutex<Zones>>,
+    transport_params: Arc<TransportParams>,
+    metronome_params: Arc<MetronomeParams>,
+    arp_params: Arc<ArpParams>,
+    sequencer_params: Arc<SequencerParams>,
+    patterns: Arc<Mutex<PatternsEditor>>,
+    sequencer_view: SequencerView,
+    rack: EffectRack,
+    presets: Arc<Mutex<Presets>>,
+    programs: Arc<Programs>,
+    /// how many programs the synth had switched to when the rack last looked
+    programs_switched: u64,
+    program_loader: ProgramLoader,
+    /// where the store button puts the current patch
+    preset_bank: u16,
+    preset_program: u8,
+    limiter_params: Arc<LimiterParams>,
+    meters: Arc<Meters>,
+    patch_text: String,
     periodic_updater: Option<PeriodicUpdater>,
 }
 
+/// Settings from storage, kept as text until there is a synth to load them into.
+#[derive(Clone, Default)]
+pub struct SavedSettings {
+    midi_map: Option<String>,
+    presets: Option<String>,
+    zones: Option<String>,
+}
+
 pub enum Wayfarer {
     Initialized(Data),
-    Uninitialized,
-}    
+    Uninitialized { saved: SavedSettings },
+}
 
 impl Wayfarer {
     pub fn init(&mut self) {
         let (midi_tx, midi_rx) = channel::bounded(256);
-        let midi = MidiReader::new(midi_tx.clone());
-        let synth = Synth::new(midi_rx);
+        let monitor = MidiMonitor::new();
+        let midi = MidiReader::new(monitor.sender(midi_tx.clone(), "midi"));
+        let midi_out = MidiWriter::new();
+        let mut synth = Synth::new(midi_rx);
+        synth.set_clock_output(midi_out.get_queue());
         let status_text = Arc::new(Mutex::new("".to_string()));
         let synth_params = synth.get_params();
+        let midi_map = synth.get_midi_map();
+        let zones = synth.get_zones();
+        let transport_params = synth.get_transport_params();
+        let arp_params = synth.get_arp_params();
+        let sequencer_params = synth.get_sequencer_params();
+        let patterns = synth.get_patterns();
+        let mixer = Mixer::new(synth.get_mixer_params());
+        let programs = synth.get_programs();
+        let saved = match self {
+            Self::Uninitialized { saved } => saved.clone(),
+            Self::Initialized(_) => SavedSettings::default(),
+        };
+        if let Some(text) = &saved.midi_map {
+            load_midi_map(&midi_map, text, &synth_params);
+        }
+        if let Some(text) = &saved.zones {
+            load_zones(&zones, text);
+        }
+        let active_notes = synth.get_active_notes();
         let status_clone = status_text.clone();
-        let audio = AudioManager::new(synth, move |e| {
+        let effect_chain = EffectChain::new();
+        synth.set_effect_chain(effect_chain.clone());
+        let presets = Arc::new(Mutex::new(
+            saved
+                .presets
+                .as_deref()
+                .map_or_else(Presets::new, load_presets),
+        ));
+        let program_loader = ProgramLoader::new(
+            programs.clone(),
+            synth_params.clone(),
+            patterns.clone(),
+            presets.clone(),
+            status_text.clone(),
+        );
+        let master = MasterChain::new(synth, effect_chain.clone());
+        // after the effects so the click stays dry
+        let metronome = Metronome::new(master);
+        let metronome_params = metronome.get_params();
+        let audio = AudioManager::new(metronome, move |e| {
             *status_clone.lock() = e;
         });
+        let limiter_params = audio.get_limiter_params();
+        let meters = audio.get_meters();
         *self = Self::Initialized(Data {
             audio,
             midi,
+            midi_out,
             status_text,
-            keyboard: OnScreenKeyboard::new(midi_tx),
+            keyboard: OnScreenKeyboard::new(monitor.sender(midi_tx, "keyboard"), active_notes),
+            monitor,
             forced_buffer_size: None,
-            left_vis_buffer: VecDeque::with_capacity(VIS_SIZE * 2),
+            vis_buffer: VecDeque::with_capacity(VIS_HISTORY_SIZE * 2),
+            scope: Oscilloscope::new(),
+            spectrum: SpectrumAnalyzer::new(),
+            spectrogram: Spectrogram::new(),
             synth_params,
+            mixer,
+            midi_map,
+            zones,
+            transport_params,
+            metronome_params,
+            arp_params,
+            sequencer_params,
+            patterns,
+            sequencer_view: SequencerView::new(),
+            rack: EffectRack::new(effect_chain),
+            presets,
+            programs,
+            programs_switched: 0,
+            program_loader,
+            preset_bank: 0,
+            preset_program: 0,
+            limiter_params,
+            meters,
+            patch_text: String::new(),
             periodic_updater: None,
         });
     }
 
     pub fn new() -> Self {
-        let mut s = Self::Uninitialized;
+        let mut s = Self::Uninitialized {
+            saved: SavedSettings::default(),
+        };
         // need to defer initializion in wasm due to chrome's autoplay blocking and such
         if cfg!(not(target_arch = "wasm32")) {
             s.init();
@@ -69,12 +199,66 @@ impl App for Wayfarer {
         NAME
     }
 
+    fn setup(
+        &mut self,
+        _ctx: &egui::CtxRef,
+        _frame: &mut epi::Frame<'_>,
+        storage: Option<&dyn epi::Storage>,
+    ) {
+        let storage = match storage {
+            Some(storage) => storage,
+            None => return,
+        };
+        let saved = SavedSettings {
+            midi_map: storage.get_string(MIDI_MAP_KEY),
+            presets: storage.get_string(PRESETS_KEY),
+            zones: storage.get_string(ZONES_KEY),
+        };
+        match self {
+            Self::Initialized(data) => {
+                if let Some(text) = &saved.midi_map {
+                    load_midi_map(&data.midi_map, text, &data.synth_params);
+                }
+                if let Some(text) = &saved.presets {
+                    *data.presets.lock() = load_presets(text);
+                }
+                if let Some(text) = &saved.zones {
+                    load_zones(&data.zones, text);
+                }
+            }
+            Self::Uninitialized { saved: old } => *old = saved,
+        }
+    }
+
+    fn save(&mut self, storage: &mut dyn epi::Storage) {
+        let saved = match self {
+            Self::Initialized(data) => SavedSettings {
+                midi_map: Some(data.midi_map.lock().to_string()),
+                presets: Some(data.presets.lock().to_string()),
+                zones: Some(data.zones.lock().to_string()),
+            },
+            Self::Uninitialized { saved } => saved.clone(),
+        };
+        if let Some(text) = saved.midi_map {
+            storage.set_string(MIDI_MAP_KEY, text);
+        }
+        if let Some(text) = saved.presets {
+            storage.set_string(PRESETS_KEY, text);
+        }
+        if let Some(text) = saved.zones {
+            storage.set_string(ZONES_KEY, text);
+        }
+    }
+
     fn on_exit(&mut self) {
         if let Self::Initialized(Data {
-            periodic_updater, ..
+            periodic_updater,
+            keyboard,
+            ..
         }) = self
         {
             periodic_updater.take();
+            keyboard.release_all();
         }
     }
 
@@ -82,7 +266,7 @@ impl App for Wayfarer {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading(NAME);
             match self {
-                Self::Uninitialized => {
+                Self::Uninitialized { .. } => {
                     if ui.button("start").clicked() {
                         self.init();
                     }
@@ -96,16 +280,63 @@ impl App for Wayfarer {
                     }
                     let audio = &mut data.audio;
                     let midi = &data.midi;
-                    let left_vis_buffer = &mut data.left_vis_buffer;
+                    let midi_out = &data.midi_out;
+                    let vis_buffer = &mut data.vis_buffer;
+                    let scope = &mut data.scope;
+                    let spectrum = &mut data.spectrum;
+                    let spectrogram = &mut data.spectrogram;
                     let forced_buffer_size = &mut data.forced_buffer_size;
                     let status_text = &data.status_text;
                     let keyboard = &mut data.keyboard;
-                    let params = data.synth_params.as_ref();
+                    let monitor = &mut data.monitor;
+                    let mixer = &mut data.mixer;
+                    let part = mixer.selected();
+                    let params = data.synth_params[part].as_ref();
+                    let midi_map = data.midi_map.as_ref();
+                    // a copy to draw from, so the audio thread doesn't find the map locked for long
+                    let map_snapshot = midi_map.lock().clone();
+                    let zones = data.zones.as_ref();
+                    let transport_params = data.transport_params.as_ref();
+                    let metronome_params = data.metronome_params.as_ref();
+                    let arp_params = data.arp_params.as_ref();
+                    let sequencer_params = data.sequencer_params.as_ref();
+                    let patterns = data.patterns.as_ref();
+                    let sequencer_view = &mut data.sequencer_view;
+                    let rack = &mut data.rack;
+                    let patch_text = &mut data.patch_text;
+                    let presets = data.presets.as_ref();
+                    let program_loader = &data.program_loader;
+                    let preset_bank = &mut data.preset_bank;
+                    let preset_program = &mut data.preset_program;
+                    {
+                        let mut patterns = patterns.lock();
+                        while let Some(recorded) = sequencer_params.recorded.pop() {
+                            patterns.get_mut().record(&recorded);
+                        }
+                    }
+                    let switched = data.programs.switched.load();
+                    if switched != data.programs_switched {
+                        data.programs_switched = switched;
+                        rack.reload();
+                    }
+                    let limiter_params = data.limiter_params.as_ref();
+                    let meters = data.meters.as_ref();
                     ui.group(|ui| {
                         ui.horizontal(|ui| {
                             ui.label("midi:");
                             ui.label(midi.get_name());
                         });
+                        ui.horizontal(|ui| {
+                            ui.label("midi out:");
+                            ui.label(midi_out.get_name());
+                        });
+                        ui.collapsing("midi mappings", |ui| {
+                            midi_mappings(ui, midi_map, &map_snapshot);
+                        });
+                        monitor.update();
+                        ui.collapsing("input monitor", |ui| {
+                            monitor.show(ui);
+                        });
                     });
 
                     ui.group(|ui| {
@@ -166,57 +397,583 @@ impl App for Wayfarer {
                             });
                         });
 
-                        audio.pop_each_left_vis_buffer(|value| {
-                            left_vis_buffer.push_back(value);
+                        ui.horizontal(|ui| {
+                            ui.label("limiter:");
+                            let mut ceiling = limiter_params.ceiling.get();
+                            ui.add(
+                                egui::Slider::new(
+                                    &mut ceiling,
+                                    limiter_params.ceiling.range.clone(),
+                                )
+                                .suffix(" dB"),
+                            );
+                            limiter_params.ceiling.set(ceiling);
+                            gain_reduction_meter(ui, audio.get_limiter_reduction());
                         });
+                        level_meters(ui, meters);
 
-                        let mut prev = None;
-                        let mut it = left_vis_buffer.iter().copied().rev();
-                        it.nth(VIS_SIZE / 2 - 1);
-                        for value in &mut it {
-                            if let Some(prev) = prev {
-                                if prev >= 0. && value < 0. {
-                                    break;
-                                }
-                            }
-                            prev = Some(value);
+                        let mut new_frames = 0;
+                        audio.pop_each_vis_buffer(|frame| {
+                            vis_buffer.push_back(frame);
+                            new_frames += 1;
+                        });
+                        if let Some(sample_rate) = audio.get_sample_rate() {
+                            scope.update(vis_buffer, new_frames, sample_rate);
                         }
-                        ui.add(
-                            egui::plot::Plot::new("waveform")
-                                .include_y(-1.)
-                                .include_y(1.)
-                                .include_x(0.)
-                                .include_x((VIS_SIZE / 2) as f32)
-                                .line(egui::plot::Line::new(egui::plot::Values::from_values_iter(
-                                    it.take(VIS_SIZE / 2).enumerate().map(|(x, y)| {
-                                        egui::plot::Value {
-                                            x: x as f64,
-                                            y: y as f64,
-                                        }
-                                    }),
-                                )))
-                                .width(ui.available_width().min(300.))
-                                .view_aspect(2.0),
-                        );
-                        if left_vis_buffer.len() > VIS_SIZE {
-                            drop(left_vis_buffer.drain(0..left_vis_buffer.len() - VIS_SIZE));
+                        scope.show(ui);
+                        ui.collapsing("spectrum", |ui| {
+                            spectrum.update(vis_buffer);
+                            spectrum.show(ui, audio.get_sample_rate());
+                        });
+                        ui.collapsing("spectrogram", |ui| {
+                            if let Some(sample_rate) = audio.get_sample_rate() {
+                                spectrogram.update(vis_buffer, new_frames, sample_rate);
+                            }
+                            spectrogram.show(ui, frame.tex_allocator());
+                        });
+                        if vis_buffer.len() > VIS_HISTORY_SIZE {
+                            drop(vis_buffer.drain(0..vis_buffer.len() - VIS_HISTORY_SIZE));
                         }
                         ui.label(&*status_text.lock());
                     });
                     ui.group(|ui| {
+                        transport_controls(ui, transport_params, metronome_params);
+                    });
+                    ui.collapsing("mixer", |ui| {
+                        mixer.show(ui);
+                    });
+                    ui.collapsing("zones", |ui| {
+                        key_zones(ui, zones);
+                    });
+                    ui.collapsing("arpeggiator", |ui| {
+                        for param in arp_params.params() {
+                            param_widget(ui, "arpeggiator", param);
+                        }
+                    });
+                    ui.collapsing("sequencer", |ui| {
+                        for param in sequencer_params.params() {
+                            param_widget(ui, "sequencer", param);
+                        }
+                        sequencer_editor(ui, sequencer_view, sequencer_params, patterns);
+                    });
+                    ui.group(|ui| {
+                        ui.label(format!("part {}:", part + 1));
+                        for param in params.params() {
+                            ui.horizontal(|ui| {
+                                if param_widget(ui, "synth", param)
+                                    .on_hover_text("right click to learn a midi controller")
+                                    .secondary_clicked()
+                                {
+                                    midi_map.lock().learn(part, param.name);
+                                }
+                                let learning = map_snapshot.learning() == Some((part, param.name));
+                                let cc = map_snapshot
+                                    .binding(part, param.name)
+                                    .map(|binding| binding.cc);
+                                if learning {
+                                    ui.label("move a controller");
+                                    if ui.small_button("cancel").clicked() {
+                                        midi_map.lock().cancel_learn();
+                                    }
+                                } else if let Some(cc) = cc {
+                                    ui.small(format!("cc {}", cc));
+                                }
+                            });
+                        }
+                    });
+                    ui.collapsing("effects", |ui| {
+                        rack.show(ui);
+                    });
+                    ui.collapsing("patch", |ui| {
+                        ui.horizontal(|ui| {
+                            if ui.button("capture").clicked() {
+                                *patch_text = capture_patch(params, rack, patterns).to_string();
+                            }
+                            if ui.button("apply").clicked() {
+                                let r = patch_text
+                                    .parse()
+                                    .and_then(|patch| program_loader.load(part, &patch, true));
+                                if let Err(e) = r {
+                                    *status_text.lock() = format!("error loading patch: {}", e);
+                                }
+                            }
+                        });
+                        ui.add(egui::TextEdit::multiline(patch_text).code_editor());
+                    });
+                    ui.collapsing("presets", |ui| {
+                        let mut presets = presets.lock();
                         ui.horizontal(|ui| {
-                            ui.label("gain:");
-                            let mut gain = params.gain.load();
-                            ui.add(egui::Slider::new(&mut gain, 0f32..=1f32));
-                            params.gain.store(gain);
+                            ui.label("bank select:");
+                            egui::ComboBox::from_id_source("bank select combo box")
+                                .selected_text(presets.bank_select.name())
+                                .show_ui(ui, |ui| {
+                                    for mode in BankSelect::ALL.iter() {
+                                        ui.selectable_value(
+                                            &mut presets.bank_select,
+                                            *mode,
+                                            mode.name(),
+                                        );
+                                    }
+                                });
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("bank:");
+                            ui.add(egui::DragValue::new(preset_bank).clamp_range(0..=0x3fff));
+                            ui.label("program:");
+                            ui.add(egui::DragValue::new(preset_program).clamp_range(0..=127));
+                            if ui.button("store current").clicked() {
+                                presets.store(
+                                    *preset_bank,
+                                    *preset_program,
+                                    capture_patch(params, rack, patterns),
+                                );
+                            }
                         });
+                        let mut load = None;
+                        let mut remove = None;
+                        for (bank, program, patch) in presets.iter() {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("bank {} program {}", bank, program));
+                                if ui.small_button("load").clicked() {
+                                    load = Some(patch.clone());
+                                }
+                                if ui.small_button("remove").clicked() {
+                                    remove = Some((bank, program));
+                                }
+                            });
+                        }
+                        if let Some(patch) = load {
+                            if let Err(e) = program_loader.load(part, &patch, true) {
+                                *status_text.lock() = format!("error loading preset: {}", e);
+                            }
+                        }
+                        if let Some((bank, program)) = remove {
+                            presets.remove(bank, program);
+                        }
                     });
                     // put onscreen keyboard at bottom of window
                     let height = ui.available_size().y;
-                    ui.add_space(height - 20f32);
+                    ui.add_space((height - keyboard::HEIGHT).max(0.));
                     keyboard.show(ui);
+                    // after this frame's edits, so they go to the sequencer together
+                    patterns.lock().publish();
                 }
             }
         });
     }
 }
+
+fn level_meters(ui: &mut egui::Ui, meters: &Meters) {
+    for (channel, name) in ["L", "R"].iter().enumerate() {
+        ui.horizontal(|ui| {
+            ui.label(*name);
+            let peak = gain_to_db(meters.peak[channel].load());
+            let rms = gain_to_db(meters.rms[channel].load());
+            ui.add(
+                egui::ProgressBar::new(1. - peak.max(METER_MIN_DB) / METER_MIN_DB)
+                    .text(format!("peak {:.1} dB, rms {:.1} dB", peak, rms)),
+            );
+            if meters.clip[channel].load() {
+                ui.colored_label(egui::Color32::RED, "clip");
+            }
+        });
+    }
+    let lufs = |value: f32| {
+        if value.is_finite() {
+            format!("{:.1} LUFS", value)
+        } else {
+            "- LUFS".to_string()
+        }
+    };
+    ui.horizontal(|ui| {
+        ui.label(format!(
+            "short-term: {}, integrated: {}",
+            lufs(meters.short_term.load()),
+            lufs(meters.integrated.load())
+        ));
+        if ui.small_button("reset").clicked() {
+            meters.reset();
+        }
+    });
+}
+
+fn capture_patch(
+    params: &dyn ParamSet,
+    rack: &EffectRack,
+    patterns: &Mutex<PatternsEditor>,
+) -> Patch {
+    let mut patch = Patch::capture(params, rack.slots());
+    patch.sequencer = patterns.lock().get().to_values();
+    patch
+}
+
+fn load_presets(text: &str) -> Presets {
+    text.parse().unwrap_or_else(|e| {
+        warn!("error loading presets: {}", e);
+        Presets::new()
+    })
+}
+
+/// Play, stop and tempo, with the song position in bars and beats.
+fn transport_controls(
+    ui: &mut egui::Ui,
+    params: &TransportParams,
+    metronome_params: &MetronomeParams,
+) {
+    let state = params.state.load();
+    let follow = params.clock_sync();
+    ui.horizontal(|ui| {
+        ui.label("transport:");
+        let playing = params.playing.is_on();
+        // the midi clock decides when following it
+        if ui
+            .add(egui::Button::new(if playing { "stop" } else { "play" }).enabled(!follow))
+            .clicked()
+        {
+            params.playing.set(if playing { 0. } else { 1. });
+        }
+        if ui
+            .add(egui::Button::new("rewind").enabled(!follow))
+            .clicked()
+        {
+            params.locate.store(Some(0.));
+        }
+        if state.count_in {
+            ui.label("count in");
+        } else {
+            let bar = (state.position / state.bar_length()).floor();
+            let beat = ((state.position - bar * state.bar_length()) / state.beat_length()).floor();
+            ui.label(format!("{}.{}", bar as i64 + 1, beat as i64 + 1));
+        }
+        if follow {
+            ui.label(format!("{:.1} bpm", state.tempo));
+        } else {
+            let mut tempo = params.tempo.get();
+            ui.add(
+                egui::DragValue::new(&mut tempo)
+                    .clamp_range(params.tempo.range.clone())
+                    .speed(0.1)
+                    .suffix(" bpm"),
+            );
+            params.tempo.set(tempo);
+        }
+    });
+    param_widget(ui, "transport", &params.sync);
+    if !follow {
+        param_widget(ui, "transport", &params.send_clock);
+    }
+    param_widget(ui, "transport", &params.time_signature);
+    ui.horizontal(|ui| {
+        param_widget(ui, "transport", &params.record);
+        if !follow {
+            param_widget(ui, "transport", &params.count_in);
+        }
+    });
+    for param in metronome_params.params() {
+        param_widget(ui, "metronome", param);
+    }
+}
+
+fn load_midi_map(midi_map: &Mutex<MidiMap>, text: &str, synth_params: &[Arc<Params>]) {
+    let parts: Vec<_> = synth_params.iter().map(|params| params.params()).collect();
+    match MidiMap::load(text, &parts) {
+        Ok(map) => *midi_map.lock() = map,
+        Err(e) => warn!("error loading midi map: {}", e),
+    }
+}
+
+/// What the sequencer editor is showing.
+pub struct SequencerView {
+    pattern: usize,
+    /// the step whose settings are shown
+    step: usize,
+    /// the grid shows an octave from C of this one
+    octave: u8,
+}
+
+impl SequencerView {
+    fn new() -> Self {
+        Self {
+            pattern: 0,
+            step: 0,
+            octave: 4,
+        }
+    }
+}
+
+/// Grid of notes against steps, with the settings of the selected step below.
+fn sequencer_editor(
+    ui: &mut egui::Ui,
+    view: &mut SequencerView,
+    params: &SequencerParams,
+    patterns: &Mutex<PatternsEditor>,
+) {
+    ui.horizontal(|ui| {
+        ui.label("edit pattern:");
+        egui::ComboBox::from_id_source("sequencer pattern combo box").show_index(
+            ui,
+            &mut view.pattern,
+            NUM_PATTERNS,
+            |i| (i + 1).to_string(),
+        );
+        ui.label("octave:");
+        ui.add(egui::DragValue::new(&mut view.octave).clamp_range(0..=8));
+    });
+    let mut pattern = patterns.lock().get().get(view.pattern).clone();
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        ui.label("steps:");
+        let mut count_index = STEP_COUNTS
+            .iter()
+            .position(|count| *count == pattern.steps.len())
+            .unwrap_or(0);
+        egui::ComboBox::from_id_source("sequencer steps combo box").show_index(
+            ui,
+            &mut count_index,
+            STEP_COUNTS.len(),
+            |i| STEP_COUNTS[i].to_string(),
+        );
+        if STEP_COUNTS[count_index] != pattern.steps.len() {
+            pattern
+                .steps
+                .resize(STEP_COUNTS[count_index], Default::default());
+            changed = true;
+        }
+        let mut channel = pattern.channel.number();
+        ui.label("channel:");
+        changed |= ui
+            .add(egui::DragValue::new(&mut channel).clamp_range(1..=16))
+            .changed();
+        pattern.channel = wmidi::Channel::from_index(channel - 1).unwrap_or(pattern.channel);
+        changed |= ui.checkbox(&mut pattern.polyphonic, "poly").changed();
+    });
+    view.step = view.step.min(pattern.steps.len() - 1);
+
+    let playhead = params.playhead.load();
+    egui::Grid::new("sequencer grid")
+        .spacing([2., 2.])
+        .show(ui, |ui| {
+            ui.label("");
+            for index in 0..pattern.steps.len() {
+                let text = if playhead == Some((view.pattern, index)) {
+                    ">".to_string()
+                } else {
+                    (index + 1).to_string()
+                };
+                ui.selectable_value(&mut view.step, index, text);
+            }
+            ui.end_row();
+            // high notes on top, like a piano roll
+            for row in (0..12).rev() {
+                let note = wmidi::Note::from_u8_lossy((view.octave + 1) * 12 + row);
+                ui.label(note.to_str());
+                for (index, step) in pattern.steps.iter_mut().enumerate() {
+                    let on = step.notes.contains(&note);
+                    if ui.selectable_label(on, "  ").clicked() {
+                        if on {
+                            step.notes.retain(|n| *n != note);
+                        } else if !pattern.polyphonic {
+                            step.notes = vec![note];
+                        } else if step.notes.len() < MAX_STEP_NOTES {
+                            step.notes.push(note);
+                        }
+                        view.step = index;
+                        changed = true;
+                    }
+                }
+                ui.end_row();
+            }
+        });
+
+    let step = &mut pattern.steps[view.step];
+    ui.horizontal(|ui| {
+        ui.label(format!("step {}:", view.step + 1));
+        ui.label("velocity:");
+        changed |= ui
+            .add(egui::DragValue::new(&mut step.velocity).clamp_range(1..=127))
+            .changed();
+        ui.label("gate:");
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut step.gate)
+                    .clamp_range(0.05f32..=1f32)
+                    .speed(0.01),
+            )
+            .changed();
+        changed |= ui.checkbox(&mut step.tie, "tie").changed();
+        ui.label("probability:");
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut step.probability)
+                    .clamp_range(0f32..=1f32)
+                    .speed(0.01),
+            )
+            .changed();
+    });
+    if changed {
+        *patterns.lock().get_mut().get_mut(view.pattern) = pattern;
+    }
+}
+
+fn load_zones(zones: &Mutex<Zones>, text: &str) {
+    match text.parse() {
+        Ok(loaded) => *zones.lock() = loaded,
+        Err(e) => warn!("error loading zones: {}", e),
+    }
+}
+
+/// Editor for the key splits and layers.
+///
+/// Works on a copy like the midi mappings, only swapping it in when something changed.
+fn key_zones(ui: &mut egui::Ui, zones: &Mutex<Zones>) {
+    let mut edited = zones.lock().clone();
+    let mut changed = false;
+    let mut removed = None;
+    let note_name = |key: u8| wmidi::Note::from_u8_lossy(key).to_str();
+    for (index, zone) in edited.zones_mut().iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            let mut channel = zone.channel.number();
+            ui.label("channel:");
+            changed |= ui
+                .add(egui::DragValue::new(&mut channel).clamp_range(1..=16))
+                .changed();
+            zone.channel = wmidi::Channel::from_index(channel - 1).unwrap_or(zone.channel);
+            let mut part = zone.part + 1;
+            ui.label("part:");
+            changed |= ui
+                .add(egui::DragValue::new(&mut part).clamp_range(1..=NUM_PARTS))
+                .changed();
+            zone.part = part - 1;
+            ui.label("transpose:");
+            changed |= ui
+                .add(egui::DragValue::new(&mut zone.transpose).clamp_range(-48..=48))
+                .changed();
+            if ui.small_button("remove").clicked() {
+                removed = Some(index);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("keys:");
+            changed |= ui
+                .add(egui::DragValue::new(&mut zone.low_key).clamp_range(0..=zone.high_key))
+                .changed();
+            ui.label(note_name(zone.low_key));
+            changed |= ui
+                .add(egui::DragValue::new(&mut zone.high_key).clamp_range(zone.low_key..=127))
+                .changed();
+            ui.label(note_name(zone.high_key));
+            ui.label("fade:");
+            changed |= ui
+                .add(egui::DragValue::new(&mut zone.key_fade).clamp_range(0..=127))
+                .changed();
+        });
+        ui.horizontal(|ui| {
+            ui.label("velocities:");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut zone.low_velocity)
+                        .clamp_range(1..=zone.high_velocity),
+                )
+                .changed();
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut zone.high_velocity)
+                        .clamp_range(zone.low_velocity..=127),
+                )
+                .changed();
+            ui.label("fade:");
+            changed |= ui
+                .add(egui::DragValue::new(&mut zone.velocity_fade).clamp_range(0..=127))
+                .changed();
+        });
+        ui.separator();
+    }
+    if edited.zones_mut().len() < MAX_ZONES && ui.button("add zone").clicked() {
+        edited.zones_mut().push(Zone::new(wmidi::Channel::Ch1, 0));
+        changed = true;
+    }
+    if let Some(index) = removed {
+        edited.zones_mut().remove(index);
+        changed = true;
+    }
+    if changed {
+        *zones.lock() = edited;
+    }
+}
+
+/// Editor for the learned controllers.
+///
+/// Works on a copy so the audio thread isn't kept waiting while drawing.
+fn midi_mappings(ui: &mut egui::Ui, midi_map: &Mutex<MidiMap>, snapshot: &MidiMap) {
+    let mut bindings = snapshot.bindings().to_vec();
+    if bindings.is_empty() {
+        ui.label("right click a synth control to learn a midi controller for it");
+        return;
+    }
+    let mut changed = false;
+    let mut removed = None;
+    for (index, binding) in bindings.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "part {} {}: channel {} cc {}",
+                binding.part + 1,
+                binding.param,
+                binding.channel.number(),
+                binding.cc
+            ));
+            let mut mode = binding.mode;
+            egui::ComboBox::from_id_source(("midi mapping mode", index))
+                .selected_text(mode.name())
+                .show_ui(ui, |ui| {
+                    for m in CcMode::ALL.iter() {
+                        ui.selectable_value(&mut mode, *m, m.name());
+                    }
+                });
+            changed |= mode != binding.mode;
+            binding.mode = mode;
+            ui.label("min:");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut binding.min)
+                        .clamp_range(0f32..=1f32)
+                        .speed(0.01),
+                )
+                .changed();
+            ui.label("max:");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut binding.max)
+                        .clamp_range(0f32..=1f32)
+                        .speed(0.01),
+                )
+                .changed();
+            changed |= ui.checkbox(&mut binding.invert, "invert").changed();
+            if ui.small_button("remove").clicked() {
+                removed = Some(index);
+            }
+        });
+    }
+    if changed || removed.is_some() {
+        let mut map = midi_map.lock();
+        // the audio thread may have learned something new meanwhile, only touch what was shown
+        for (edited, binding) in bindings.iter().zip(map.bindings_mut().iter_mut()) {
+            if edited.part == binding.part && edited.param == binding.param {
+                binding.mode = edited.mode;
+                binding.min = edited.min;
+                binding.max = edited.max;
+                binding.invert = edited.invert;
+            }
+        }
+        if let Some(index) = removed {
+            let shown = &bindings[index];
+            if map
+                .bindings_mut()
+                .get(index)
+                .map(|binding| (binding.part, binding.param))
+                == Some((shown.part, shown.param))
+            {
+                map.bindings_mut().remove(index);
+            }
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use crate::param::{Param, ParamSet};
+use crate::transport::TransportState;
+use std::sync::Arc;
+use wmidi::MidiMessage;
+
+/// enough for every key, so holding notes never allocates on the audio thread
+const MAX_NOTES: usize = 128;
+
+const MODE_NAMES: &[&str] = &["up", "down", "up down", "random", "as played"];
+const RATE_NAMES: &[&str] = &["1/4", "1/8", "1/8t", "1/16", "1/16t", "1/32"];
+/// length of each of the above as a fraction of a whole note
+const RATE_LENGTHS: &[f64] = &[1. / 4., 1. / 8., 1. / 12., 1. / 16., 1. / 24., 1. / 32.];
+/// free running patterns start when a chord is played, otherwise they follow the song position
+const SYNC_NAMES: &[&str] = &["free", "transport"];
+
+pub struct ArpParams {
+    pub enabled: Param,
+    pub mode: Param,
+    pub octaves: Param,
+    pub rate: Param,
+    /// fraction of the time until the next step that each note is held
+    pub gate: Param,
+    /// how far every second step is pushed back, as a fraction of a step
+    pub swing: Param,
+    /// keep playing after the keys are let go, until a new chord is played
+    pub latch: Param,
+    pub sync: Param,
+}
+
+impl ParamSet for ArpParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![
+            &self.enabled,
+            &self.mode,
+            &self.octaves,
+            &self.rate,
+            &self.gate,
+            &self.swing,
+            &self.latch,
+            &self.sync,
+        ]
+    }
+}
+
+impl ArpParams {
+    fn new() -> Self {
+        Self {
+            enabled: Param::toggle("arpeggiator", false),
+            mode: Param::choice("mode", 0, MODE_NAMES),
+            octaves: Param::choice("octaves", 0, &["1", "2", "3", "4"]),
+            rate: Param::choice("rate", 3, RATE_NAMES),
+            gate: Param::new("gate", "", 0.5, 0.05f32..=1f32),
+            swing: Param::new("swing", "", 0., 0f32..=0.5f32),
+            latch: Param::toggle("latch", false),
+            sync: Param::choice("sync", 0, SYNC_NAMES),
+        }
+    }
+}
+
+/// Turns held notes into a pattern, between the midi inputs and the synth's parts.
+pub struct Arpeggiator {
+    params: Arc<ArpParams>,
+    /// held notes in the order they were played
+    played: Vec<(wmidi::Note, wmidi::U7)>,
+    /// the same from low to high
+    sorted: Vec<(wmidi::Note, wmidi::U7)>,
+    /// keys physically down, as opposed to latched
+    down: [bool; 128],
+    channel: wmidi::Channel,
+    /// in steps since the pattern started
+    position: f64,
+    next_step: u64,
+    /// note playing, on the channel it started on, and the position it ends at
+    sounding: Option<(wmidi::Channel, wmidi::Note, f64)>,
+    random: u32,
+}
+
+// derived clone would lose the room reserved for the notes, and the synth is cloned for each stream
+impl Clone for Arpeggiator {
+    fn clone(&self) -> Self {
+        let mut played = Vec::with_capacity(MAX_NOTES);
+        played.extend_from_slice(&self.played);
+        let mut sorted = Vec::with_capacity(MAX_NOTES);
+        sorted.extend_from_slice(&self.sorted);
+        Self {
+            params: self.params.clone(),
+            played,
+            sorted,
+            down: self.down,
+            channel: self.channel,
+            position: self.position,
+            next_step: self.next_step,
+            sounding: self.sounding,
+            random: self.random,
+        }
+    }
+}
+
+impl Arpeggiator {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(ArpParams::new()),
+            played: Vec::with_capacity(MAX_NOTES),
+            sorted: Vec::with_capacity(MAX_NOTES),
+            down: [false; 128],
+            channel: wmidi::Channel::Ch1,
+            position: 0.,
+            next_step: 0,
+            sounding: None,
+            random: 0x1234_5678,
+        }
+    }
+
+    pub fn get_params(&self) -> Arc<ArpParams> {
+        self.params.clone()
+    }
+
+    fn transport_sync(&self) -> bool {
+        self.params.sync.get_index() == 1
+    }
+
+    fn steps_per_quarter(&self) -> f64 {
+        1. / 4. / RATE_LENGTHS[self.params.rate.get_index()]
+    }
+
+    /// Takes the notes when enabled. Everything else is passed on, including releases of keys
+    /// held since before it was enabled so their notes don't hang.
+    pub fn process(&mut self, message: MidiMessage<'static>) -> Option<MidiMessage<'static>> {
+        match message {
+            _ if !self.params.enabled.is_on() => Some(message),
+            MidiMessage::NoteOn(channel, note, velocity) if u8::from(velocity) > 0 => {
+                self.press(channel, note, velocity);
+                None
+            }
+            MidiMessage::NoteOn(_, note, _) | MidiMessage::NoteOff(_, note, _)
+                if self.down[u8::from(note) as usize] =>
+            {
+                self.down[u8::from(note) as usize] = false;
+                None
+            }
+            _ => Some(message),
+        }
+    }
+
+    fn press(&mut self, channel: wmidi::Channel, note: wmidi::Note, velocity: wmidi::U7) {
+        // a new chord replaces the pattern, which only still has notes in it when latched
+        if !self.down.iter().any(|down| *down) {
+            if self.played.is_empty() && !self.transport_sync() {
+                // start right away rather than waiting for the next step
+                self.position = 0.;
+                self.next_step = 0;
+            }
+            self.played.clear();
+            self.sorted.clear();
+        }
+        self.down[u8::from(note) as usize] = true;
+        self.channel = channel;
+        if self.played.iter().any(|(n, _)| *n == note) || self.played.len() == MAX_NOTES {
+            return;
+        }
+        self.played.push((note, velocity));
+        let index = self.sorted.iter().position(|(n, _)| *n > note);
+        self.sorted
+            .insert(index.unwrap_or(self.sorted.len()), (note, velocity));
+    }
+
+    /// Where a step starts, with every second one pushed back by the swing.
+    fn step_position(&self, step: u64) -> f64 {
+        step as f64 + (step % 2) as f64 * self.params.swing.get() as f64
+    }
+
+    fn next_random(&mut self) -> u32 {
+        // xorshift
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+        self.random
+    }
+
+    /// The note and velocity for a step of the pattern.
+    fn pick(&mut self, step: u64) -> (wmidi::Note, wmidi::U7) {
+        let notes = self.sorted.len() as u64;
+        let length = notes * (self.params.octaves.get_index() as u64 + 1);
+        let mode = MODE_NAMES[self.params.mode.get_index()];
+        let index = match mode {
+            "down" => length - 1 - step % length,
+            "up down" if length > 1 => {
+                let cycle = 2 * length - 2;
+                let i = step % cycle;
+                if i < length {
+                    i
+                } else {
+                    cycle - i
+                }
+            }
+            "random" => self.next_random() as u64 % length,
+            _ => step % length,
+        };
+        let list = if mode == "as played" {
+            &self.played
+        } else {
+            &self.sorted
+        };
+        let (note, velocity) = list[(index % notes) as usize];
+        let octave = (index / notes) as i8 * 12;
+        (note.step(octave).unwrap_or(note), velocity)
+    }
+
+    /// Plays the pattern for the next `frames`, calling `emit` with the frame offset of each message.
+    /// Called from the audio thread, so doesn't allocate.
+    pub fn run(
+        &mut self,
+        transport: &TransportState,
+        sample_rate: u32,
+        frames: usize,
+        mut emit: impl FnMut(usize, MidiMessage<'static>),
+    ) {
+        let transport_sync = self.transport_sync();
+        if !self.params.enabled.is_on() || (transport_sync && !transport.playing) {
+            if let Some((channel, note, _)) = self.sounding.take() {
+                emit(0, MidiMessage::NoteOff(channel, note, wmidi::U7::MIN));
+            }
+            if !self.params.enabled.is_on() {
+                self.played.clear();
+                self.sorted.clear();
+                self.down = [false; 128];
+            }
+            return;
+        }
+        if !self.params.latch.is_on() {
+            let down = &self.down;
+            self.played
+                .retain(|(note, _)| down[u8::from(*note) as usize]);
+            self.sorted
+                .retain(|(note, _)| down[u8::from(*note) as usize]);
+        }
+
+        let steps_per_quarter = self.steps_per_quarter();
+        let (start, end) = if transport_sync {
+            let start = transport.position * steps_per_quarter;
+            if start != self.position {
+                // the song position jumped, pick the pattern up from there
+                self.next_step = start.ceil() as u64;
+            }
+            (start, transport.end * steps_per_quarter)
+        } else {
+            let steps_per_sample = transport.quarters_per_sample(sample_rate) * steps_per_quarter;
+            (
+                self.position,
+                self.position + frames as f64 * steps_per_sample,
+            )
+        };
+        // can be 0 while waiting for the midi clock, in which case nothing plays
+        let steps_per_frame = (end - start) / frames as f64;
+        let offset = |position: f64| {
+            (((position - start) / steps_per_frame).round().max(0.) as usize)
+                .min(frames.saturating_sub(1))
+        };
+
+        loop {
+            let step_position = self.step_position(self.next_step);
+            if let Some((channel, note, off)) = self.sounding {
+                if off <= step_position && off < end {
+                    emit(
+                        offset(off),
+                        MidiMessage::NoteOff(channel, note, wmidi::U7::MIN),
+                    );
+                    self.sounding = None;
+                    continue;
+                }
+            }
+            if step_position >= end {
+                break;
+            }
+            if let Some((channel, note, _)) = self.sounding.take() {
+                emit(
+                    offset(step_position),
+                    MidiMessage::NoteOff(channel, note, wmidi::U7::MIN),
+                );
+            }
+            if !self.sorted.is_empty() {
+                let (note, velocity) = self.pick(self.next_step);
+                let gate = self.params.gate.get() as f64;
+                let next = self.step_position(self.next_step + 1);
+                emit(
+                    offset(step_position),
+                    MidiMessage::NoteOn(self.channel, note, velocity),
+                );
+                self.sounding = Some((
+                    self.channel,
+                    note,
+                    step_position + gate * (next - step_position),
+                ));
+            } else if !transport_sync {
+                // nothing held, wait for the next press to start over
+                break;
+            }
+            self.next_step += 1;
+        }
+        self.position = end;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Arpeggiator;
+    use crate::transport::TransportState;
+    use wmidi::{Channel, MidiMessage, Note, U7};
+
+    fn run(arp: &mut Arpeggiator, frames: usize) -> Vec<(usize, MidiMessage<'static>)> {
+        let mut events = vec![];
+        let transport = TransportState::default();
+        arp.run(&transport, 48000, frames, |offset, message| {
+            events.push((offset, message))
+        });
+        events
+    }
+
+    #[test]
+    fn up_with_gate_and_latch() {
+        let mut arp = Arpeggiator::new();
+        let params = arp.get_params();
+        params.enabled.set(1.);
+        let velocity = U7::from_u8_lossy(100);
+        for note in [Note::E4, Note::C4].iter() {
+            assert!(arp
+                .process(MidiMessage::NoteOn(Channel::Ch1, *note, velocity))
+                .is_none());
+        }
+        // 1/16 at 120 bpm is 6000 samples, half of that gated
+        let off = |note| MidiMessage::NoteOff(Channel::Ch1, note, U7::MIN);
+        let on = |note| MidiMessage::NoteOn(Channel::Ch1, note, velocity);
+        assert_eq!(
+            vec![
+                (0, on(Note::C4)),
+                (3000, off(Note::C4)),
+                (6000, on(Note::E4)),
+                (9000, off(Note::E4)),
+            ],
+            run(&mut arp, 12000)
+        );
+
+        // latched notes keep going after the keys are let go
+        params.latch.set(1.);
+        arp.process(off(Note::C4));
+        arp.process(off(Note::E4));
+        assert_eq!(
+            vec![(0, on(Note::C4)), (3000, off(Note::C4))],
+            run(&mut arp, 6000)
+        );
+        params.latch.set(0.);
+        assert_eq!(
+            Vec::<(usize, MidiMessage<'static>)>::new(),
+            run(&mut arp, 12000)
+        );
+    }
+
+    #[test]
+    fn release_of_key_held_before_enabling() {
+        let mut arp = Arpeggiator::new();
+        let params = arp.get_params();
+        let on = MidiMessage::NoteOn(Channel::Ch1, Note::C4, U7::from_u8_lossy(100));
+        let off = MidiMessage::NoteOff(Channel::Ch1, Note::C4, U7::MIN);
+        assert_eq!(Some(on.clone()), arp.process(on.clone()));
+        params.enabled.set(1.);
+        run(&mut arp, 480);
+        // the synth is still playing the key, so it needs to hear it let go
+        assert_eq!(Some(off.clone()), arp.process(off.clone()));
+
+        // keys pressed while enabled are the arpeggiator's to let go
+        assert!(arp.process(on).is_none());
+        assert!(arp.process(off).is_none());
+    }
+    #[test]
+    fn note_off_on_the_channel_of_its_note_on() {
+        let mut arp = Arpeggiator::new();
+        arp.get_params().enabled.set(1.);
+        let velocity = U7::from_u8_lossy(100);
+        arp.process(MidiMessage::NoteOn(Channel::Ch1, Note::C4, velocity));
+        assert_eq!(
+            vec![(0, MidiMessage::NoteOn(Channel::Ch1, Note::C4, velocity))],
+            run(&mut arp, 1000)
+        );
+        // the next notes go to the channel pressed last, but the one sounding ends where it started
+        arp.process(MidiMessage::NoteOn(Channel::Ch2, Note::E4, velocity));
+        assert_eq!(
+            vec![
+                (2000, MidiMessage::NoteOff(Channel::Ch1, Note::C4, U7::MIN)),
+                (5000, MidiMessage::NoteOn(Channel::Ch2, Note::E4, velocity)),
+                (8000, MidiMessage::NoteOff(Channel::Ch2, Note::E4, U7::MIN)),
+            ],
+            run(&mut arp, 11000)
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use std::sync::Arc;
 
+use crate::effects::{Effect, Limiter, LimiterParams};
+use crate::meter::{LevelMeter, Meters};
 use crate::synth::SynthPlayer;
 use anyhow::{anyhow, Result};
 use cpal::{
@@ -17,11 +19,16 @@ pub struct AudioManager<T> {
     device: Option<Device>,
     config_range: Option<SupportedStreamConfigRange>,
     buffer_size: Arc<AtomicCell<u32>>,
+    sample_rate: Option<u32>,
     forced_buffer_size: Option<u32>,
     stream: Option<Stream>,
     error_callback: Arc<Box<dyn Fn(String) + Send + Sync>>,
     synth: T,
-    left_visualization_consumer: Option<ringbuf::Consumer<f32>>,
+    limiter_params: Arc<LimiterParams>,
+    limiter_reduction: Option<Arc<AtomicCell<f32>>>,
+    meters: Arc<Meters>,
+    /// stereo frames of the final output
+    visualization_consumer: Option<ringbuf::Consumer<[f32; 2]>>,
 }
 
 impl<T> AudioManager<T>
@@ -36,11 +43,15 @@ where
             device: None,
             config_range: None,
             buffer_size: Arc::new(AtomicCell::new(0)),
+            sample_rate: None,
             forced_buffer_size: None,
             stream: None,
             error_callback: Arc::new(Box::new(error_callback)),
             synth,
-            left_visualization_consumer: None,
+            limiter_params: Arc::new(LimiterParams::new()),
+            limiter_reduction: None,
+            meters: Arc::new(Meters::new()),
+            visualization_consumer: None,
         };
         s.setup();
         s
@@ -65,6 +76,7 @@ where
 
     fn setup(&mut self) {
         self.stream = None;
+        self.sample_rate = None;
         let r = (|| -> Result<_> {
             if self.device.is_none() {
                 let host = cpal::default_host();
@@ -108,18 +120,25 @@ where
                     let sample_rate = sample_rate.0;
                     let channels = config.channels.into();
                     let mut synth = self.synth.clone();
+                    // keeps cpal from hard clipping whatever the synth and effects throw at it
+                    let mut limiter = Limiter::with_params(self.limiter_params.clone());
+                    self.limiter_reduction = limiter.gain_reduction();
+                    let mut meter = LevelMeter::with_meters(self.meters.clone());
                     let error_callback = self.error_callback.clone();
                     let buffer_size = self.buffer_size.clone();
-                    let (mut left_vis_prod, left_vis_cons) =
+                    let (mut vis_prod, vis_cons) =
                         ringbuf::RingBuffer::new(VISUALIZATION_BUFFER_SIZE).split();
-                    self.left_visualization_consumer = Some(left_vis_cons);
+                    self.visualization_consumer = Some(vis_cons);
                     let stream = device.build_output_stream(
                         &config,
                         move |data: &mut [f32], _: &OutputCallbackInfo| {
                             buffer_size.store((data.len() / channels) as u32);
                             synth.play(sample_rate, channels, data);
+                            // metered before the limiter so the clip indicators show what it's catching
+                            meter.process(sample_rate, data);
+                            limiter.process(sample_rate, data);
                             for chunk in data.chunks_exact(NUM_CHANNELS) {
-                                let _ignore = left_vis_prod.push(chunk[0]);
+                                let _ignore = vis_prod.push([chunk[0], chunk[1]]);
                             }
                         },
                         move |error| {
@@ -128,6 +147,7 @@ where
                     )?;
                     stream.play()?;
                     self.stream = Some(stream);
+                    self.sample_rate = Some(sample_rate);
                 }
             } else {
                 warn!("no output device found");
@@ -150,6 +170,10 @@ where
         }
     }
 
+    pub fn get_sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
     pub fn get_buffer_size_range(&self) -> Option<(u32, u32)> {
         match self.config_range.as_ref()?.buffer_size() {
             SupportedBufferSize::Range { min, max } => Some((*min, *max)),
@@ -164,11 +188,26 @@ where
         }
     }
 
-    pub fn pop_each_left_vis_buffer<F>(&mut self, mut f: F)
+    pub fn get_limiter_params(&self) -> Arc<LimiterParams> {
+        self.limiter_params.clone()
+    }
+
+    /// Current gain reduction of the output limiter in dB.
+    pub fn get_limiter_reduction(&self) -> f32 {
+        self.limiter_reduction
+            .as_ref()
+            .map_or(0., |reduction| reduction.load())
+    }
+
+    pub fn get_meters(&self) -> Arc<Meters> {
+        self.meters.clone()
+    }
+
+    pub fn pop_each_vis_buffer<F>(&mut self, mut f: F)
     where
-        F: FnMut(f32),
+        F: FnMut([f32; 2]),
     {
-        if let Some(ref mut cons) = self.left_visualization_consumer {
+        if let Some(ref mut cons) = self.visualization_consumer {
             cons.pop_each(
                 |a| {
                     f(a);
@@ -0,0 +1,312 @@
+use super::delay_line::DelayLine;
+use super::{Effect, EffectKind};
+use crate::param::ParamSet;
+use crate::synth::SynthPlayer;
+use crate::transport::TransportState;
+use crossbeam::atomic::AtomicCell;
+use parking_lot::{Mutex, MutexGuard};
+use std::sync::Arc;
+
+// enough to not have to allocate for any sensible buffer size
+const DRY_BUFFER_SIZE: usize = 16384 * 2;
+/// room reserved up front so adding slots never allocates while the chain is locked
+pub const MAX_SLOTS: usize = 32;
+/// in frames, more than any effect reports at `MAX_SAMPLE_RATE`
+const MAX_LATENCY: usize = 2048;
+
+/// The parts of an insert slot that the ui can touch.
+pub struct SlotState {
+    pub kind: EffectKind,
+    pub bypass: AtomicCell<bool>,
+    /// 0 is fully dry, 1 fully wet
+    pub mix: AtomicCell<f32>,
+    /// in frames, updated by the audio thread
+    pub latency: AtomicCell<u32>,
+    pub params: Arc<dyn ParamSet>,
+    pub gain_reduction: Option<Arc<AtomicCell<f32>>>,
+}
+
+/// An effect together with its shared state, ready to be put in an `EffectChain`.
+pub struct Slot {
+    effect: Box<dyn Effect>,
+    state: Arc<SlotState>,
+    was_bypassed: bool,
+    /// lines the dry signal up with the effect's output so partial mixes don't comb filter
+    dry_delay: [DelayLine; 2],
+}
+
+impl Slot {
+    /// Allocates the effect, so don't call this from the audio thread.
+    pub fn new(kind: EffectKind) -> Self {
+        let effect = kind.create();
+        let state = Arc::new(SlotState {
+            kind,
+            bypass: false.into(),
+            mix: kind.default_mix().into(),
+            latency: effect.latency().into(),
+            params: effect.params(),
+            gain_reduction: effect.gain_reduction(),
+        });
+        Self {
+            effect,
+            state,
+            was_bypassed: false,
+            dry_delay: [DelayLine::new(MAX_LATENCY), DelayLine::new(MAX_LATENCY)],
+        }
+    }
+
+    pub fn state(&self) -> &Arc<SlotState> {
+        &self.state
+    }
+}
+
+/// Insert slots shared between the ui and the audio thread.
+///
+/// The ui only takes the lock when adding, removing or reordering slots, and only for as long as
+/// it takes to move them around. Effects are created and dropped outside the lock and there's
+/// always room for `MAX_SLOTS`, so nothing allocates while it's held.
+/// The audio thread never waits for it, it leaves the effects out for a block instead.
+#[derive(Clone)]
+pub struct EffectChain {
+    slots: Arc<Mutex<Vec<Slot>>>,
+}
+
+impl Default for EffectChain {
+    fn default() -> Self {
+        Self {
+            slots: Arc::new(Mutex::new(Vec::with_capacity(MAX_SLOTS))),
+        }
+    }
+}
+
+impl EffectChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ignored when the chain is full.
+    pub fn insert(&self, index: usize, slot: Slot) {
+        let mut slots = self.slots.lock();
+        if slots.len() < MAX_SLOTS {
+            let index = index.min(slots.len());
+            slots.insert(index, slot);
+        }
+    }
+
+    /// The slots, unless the ui is in the middle of rearranging them.
+    /// For the audio thread to swap in a patch's effects in the same block as the rest of it,
+    /// so whatever goes in needs room for `MAX_SLOTS` already, see `with_room`.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, Vec<Slot>>> {
+        self.slots.try_lock()
+    }
+
+    /// Makes room for `MAX_SLOTS` in slots about to be swapped in, dropping any past that.
+    pub fn with_room(mut slots: Vec<Slot>) -> Vec<Slot> {
+        slots.truncate(MAX_SLOTS);
+        slots.reserve_exact(MAX_SLOTS - slots.len());
+        slots
+    }
+
+    /// The state of each slot, for the ui to catch up after a patch was swapped in.
+    pub fn states(&self) -> Vec<Arc<SlotState>> {
+        self.slots
+            .lock()
+            .iter()
+            .map(|slot| slot.state.clone())
+            .collect()
+    }
+
+    /// Indices past the end are ignored, the ui's copy of the slots can be behind after a
+    /// program switch.
+    pub fn remove(&self, index: usize) {
+        let mut slots = self.slots.lock();
+        if index < slots.len() {
+            let removed = slots.remove(index);
+            drop(slots);
+            drop(removed);
+        }
+    }
+
+    /// Like `remove`, ignores indices past the end.
+    pub fn move_slot(&self, from: usize, to: usize) {
+        let mut slots = self.slots.lock();
+        if from < slots.len() && to < slots.len() {
+            let slot = slots.remove(from);
+            slots.insert(to, slot);
+        }
+    }
+
+    fn process(
+        &self,
+        sample_rate: u32,
+        transport: &TransportState,
+        data: &mut [f32],
+        dry: &mut Vec<f32>,
+    ) {
+        let mut slots = match self.slots.try_lock() {
+            Some(slots) => slots,
+            // the ui is rearranging the slots
+            None => return,
+        };
+        for slot in slots.iter_mut() {
+            let bypass = slot.state.bypass.load();
+            if bypass {
+                slot.was_bypassed = true;
+                continue;
+            }
+            if slot.was_bypassed {
+                // don't let stale tails from before the bypass leak out
+                slot.effect.reset();
+                for line in slot.dry_delay.iter_mut() {
+                    line.clear();
+                }
+                slot.was_bypassed = false;
+            }
+            slot.effect.set_transport(transport);
+            dry.clear();
+            dry.extend_from_slice(data);
+            slot.effect.process(sample_rate, data);
+            // effects can work out their latency while processing
+            let latency = slot.effect.latency();
+            slot.state.latency.store(latency);
+            let latency = (latency as usize).min(MAX_LATENCY - 1);
+            // keep the dry delay running even when fully wet so turning the mix down doesn't click
+            for (index, sample) in dry.iter_mut().enumerate() {
+                let line = &mut slot.dry_delay[index % 2];
+                let delayed = if latency == 0 {
+                    *sample
+                } else {
+                    line.read(latency)
+                };
+                line.write(*sample);
+                *sample = delayed;
+            }
+            let mix = slot.state.mix.load();
+            if mix < 1. {
+                for (wet, dry) in data.iter_mut().zip(dry.iter()) {
+                    *wet = dry + (*wet - dry) * mix;
+                }
+            }
+        }
+    }
+}
+
+/// Runs the output of a `SynthPlayer` through an `EffectChain`.
+pub struct MasterChain<T> {
+    source: T,
+    chain: EffectChain,
+    dry: Vec<f32>,
+}
+
+impl<T> MasterChain<T> {
+    pub fn new(source: T, chain: EffectChain) -> Self {
+        Self {
+            source,
+            chain,
+            dry: Vec::with_capacity(DRY_BUFFER_SIZE),
+        }
+    }
+}
+
+// derived clone would lose the capacity, and the first block would allocate
+impl<T: Clone> Clone for MasterChain<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.source.clone(), self.chain.clone())
+    }
+}
+
+impl<T> SynthPlayer for MasterChain<T>
+where
+    T: SynthPlayer,
+{
+    fn play(&mut self, sample_rate: u32, channels: usize, output: &mut [f32]) {
+        self.source.play(sample_rate, channels, output);
+        // the effects only know about stereo
+        if channels == 2 {
+            let transport = self.source.transport();
+            self.chain
+                .process(sample_rate, &transport, output, &mut self.dry);
+        }
+    }
+
+    fn transport(&self) -> TransportState {
+        self.source.transport()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EffectChain, MasterChain, Slot};
+    use crate::effects::EffectKind;
+    use crate::synth::SynthPlayer;
+    use crate::transport::TransportState;
+
+    #[derive(Clone)]
+    struct Dc;
+
+    impl SynthPlayer for Dc {
+        fn play(&mut self, _sample_rate: u32, _channels: usize, output: &mut [f32]) {
+            output.fill(0.5);
+        }
+    }
+
+    #[test]
+    fn mix_and_bypass() {
+        let chain = EffectChain::new();
+        let slot = Slot::new(EffectKind::Gain);
+        let state = slot.state().clone();
+        chain.insert(0, slot);
+        state.params.params()[0].set(-6.);
+        let mut master = MasterChain::new(Dc, chain);
+        let mut data = [0f32; 64];
+        // let the gain ramp settle
+        master.play(48000, 2, &mut data);
+        master.play(48000, 2, &mut data);
+        assert!(data.iter().all(|v| (v - 0.25).abs() < 0.01));
+        state.mix.store(0.5);
+        master.play(48000, 2, &mut data);
+        assert!(data.iter().all(|v| (v - 0.375).abs() < 0.01));
+        state.bypass.store(true);
+        master.play(48000, 2, &mut data);
+        assert!(data.iter().all(|v| *v == 0.5));
+    }
+    #[test]
+    fn dry_delayed_by_latency() {
+        let chain = EffectChain::new();
+        let slot = Slot::new(EffectKind::Limiter);
+        let state = slot.state().clone();
+        chain.insert(0, slot);
+        state.mix.store(0.5);
+        let transport = TransportState::default();
+        let mut dry = vec![];
+        let mut data = [0f32; 1024];
+        // the limiter works out its lookahead on the first block
+        chain.process(48000, &transport, &mut data, &mut dry);
+        let latency = state.latency.load() as usize;
+        assert!(latency > 0);
+
+        // an impulse quiet enough to pass untouched comes out once, not twice
+        data[0] = 0.5;
+        data[1] = 0.5;
+        chain.process(48000, &transport, &mut data, &mut dry);
+        for (index, sample) in data.iter().enumerate() {
+            let expected = if index / 2 == latency { 0.5 } else { 0. };
+            assert!((sample - expected).abs() < 1e-4, "{} at {}", sample, index);
+        }
+    }
+    #[test]
+    fn edits_past_the_end_are_ignored() {
+        let chain = EffectChain::new();
+        chain.insert(0, Slot::new(EffectKind::Gain));
+        chain.insert(5, Slot::new(EffectKind::Delay));
+        chain.remove(2);
+        chain.move_slot(0, 2);
+        chain.move_slot(3, 0);
+        let names: Vec<_> = chain
+            .states()
+            .iter()
+            .map(|state| state.kind.name())
+            .collect();
+        assert_eq!(names, ["gain", "delay"]);
+    }
+}
@@ -0,0 +1,46 @@
+use super::{db_to_gain, Effect};
+use crate::param::{Param, ParamSet};
+use std::sync::Arc;
+
+pub struct SoftClipperParams {
+    pub drive: Param,
+    pub ceiling: Param,
+}
+
+impl ParamSet for SoftClipperParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![&self.drive, &self.ceiling]
+    }
+}
+
+/// tanh saturation that approaches the ceiling smoothly instead of clipping hard
+pub struct SoftClipper {
+    params: Arc<SoftClipperParams>,
+}
+
+impl SoftClipper {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(SoftClipperParams {
+                drive: Param::new("drive", "dB", 0., 0f32..=36f32),
+                ceiling: Param::new("ceiling", "dB", 0., -24f32..=0f32),
+            }),
+        }
+    }
+}
+
+impl Effect for SoftClipper {
+    fn process(&mut self, _sample_rate: u32, data: &mut [f32]) {
+        let drive = db_to_gain(self.params.drive.get());
+        let ceiling = db_to_gain(self.params.ceiling.get());
+        for sample in data.iter_mut() {
+            *sample = ceiling * (*sample * drive / ceiling).tanh();
+        }
+    }
+
+    fn reset(&mut self) {}
+
+    fn params(&self) -> Arc<dyn ParamSet> {
+        self.params.clone()
+    }
+}
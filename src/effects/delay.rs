@@ -0,0 +1,269 @@
+use super::{delay_line::DelayLine, Effect, MAX_SAMPLE_RATE};
+use crate::param::{Param, ParamSet};
+use crate::transport::TransportState;
+use std::{cmp::Ordering, f32::consts::PI, sync::Arc};
+
+const MAX_TIME_MS: f32 = 2000.;
+const MAX_MOD_DEPTH_MS: f32 = 10.;
+const CROSSFADE_SECONDS: f32 = 0.05;
+/// delays still fading out when the time keeps changing mid crossfade
+const MAX_FADING_TAPS: usize = 4;
+
+const DIVISION_NAMES: &[&str] = &[
+    "off", "1/1", "1/2", "1/2.", "1/2t", "1/4", "1/4.", "1/4t", "1/8", "1/8.", "1/8t", "1/16",
+    "1/16.", "1/16t",
+];
+
+/// length of each of the above as a fraction of a whole note
+const DIVISION_LENGTHS: &[f32] = &[
+    0.,
+    1.,
+    1. / 2.,
+    3. / 4.,
+    1. / 3.,
+    1. / 4.,
+    3. / 8.,
+    1. / 6.,
+    1. / 8.,
+    3. / 16.,
+    1. / 12.,
+    1. / 16.,
+    3. / 32.,
+    1. / 24.,
+];
+
+pub struct DelayParams {
+    pub time: Param,
+    pub sync: Param,
+    pub feedback: Param,
+    pub damping: Param,
+    pub ping_pong: Param,
+    pub mod_rate: Param,
+    pub mod_depth: Param,
+}
+
+impl ParamSet for DelayParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![
+            &self.time,
+            &self.sync,
+            &self.feedback,
+            &self.damping,
+            &self.ping_pong,
+            &self.mod_rate,
+            &self.mod_depth,
+        ]
+    }
+}
+
+impl DelayParams {
+    /// Delay time in seconds, synced to `tempo` if asked to.
+    fn seconds(&self, tempo: f32) -> f32 {
+        match DIVISION_LENGTHS[self.sync.get_index()] {
+            fraction if fraction > 0. => {
+                let whole_note = 4. * 60. / tempo;
+                (whole_note * fraction).min(MAX_TIME_MS / 1000.)
+            }
+            _ => self.time.get() / 1000.,
+        }
+    }
+}
+
+/// Stereo delay with a lowpass in the feedback path. Outputs only the echoes,
+/// the slot's mix blends them with the dry signal.
+///
+/// The delay lines are allocated up front for the highest supported sample rate
+/// so nothing needs to be allocated in the audio callback.
+pub struct Delay {
+    params: Arc<DelayParams>,
+    /// in bpm, from the transport
+    tempo: f32,
+    lines: [DelayLine; 2],
+    /// delay in frames that is currently being read
+    current: f32,
+    /// delays we are fading away from and their share of what fades out, adding up to 1
+    fading: [(f32, f32); MAX_FADING_TAPS],
+    fading_count: usize,
+    fade: f32,
+    damp_state: [f32; 2],
+    lfo_phase: f32,
+}
+
+impl Delay {
+    pub fn new() -> Self {
+        let max_seconds = (MAX_TIME_MS + MAX_MOD_DEPTH_MS) / 1000.;
+        let len = (max_seconds * MAX_SAMPLE_RATE as f32) as usize + 2;
+        Self {
+            params: Arc::new(DelayParams {
+                time: Param::new("time", "ms", 375., 1f32..=MAX_TIME_MS).logarithmic(),
+                sync: Param::choice("sync", 0, DIVISION_NAMES),
+                feedback: Param::new("feedback", "", 0.4, 0f32..=0.95f32),
+                damping: Param::new("damping", "Hz", 6000., 200f32..=20000f32).logarithmic(),
+                ping_pong: Param::toggle("ping pong", false),
+                mod_rate: Param::new("mod rate", "Hz", 0.5, 0.05f32..=5f32).logarithmic(),
+                mod_depth: Param::new("mod depth", "ms", 0., 0f32..=MAX_MOD_DEPTH_MS),
+            }),
+            tempo: TransportState::default().tempo as f32,
+            lines: [DelayLine::new(len), DelayLine::new(len)],
+            current: 0.,
+            fading: [(0., 0.); MAX_FADING_TAPS],
+            fading_count: 0,
+            fade: 1.,
+            damp_state: [0.; 2],
+            lfo_phase: 0.,
+        }
+    }
+
+    /// Starts crossfading to a new delay from wherever the current crossfade has got to.
+    fn retarget(&mut self, target: f32) {
+        let fade = self.fade;
+        let mut taps = [(0f32, 0f32); MAX_FADING_TAPS];
+        let mut count = 0;
+        // what was fading out shrinks by how far the fade got, forget about what's inaudible
+        for &(delay, share) in self.fading[..self.fading_count].iter() {
+            let share = share * (1. - fade);
+            if share > 0.001 {
+                taps[count] = (delay, share);
+                count += 1;
+            }
+        }
+        if count == MAX_FADING_TAPS {
+            // no room left, make some by dropping the quietest
+            if let Some(quietest) = (0..count)
+                .min_by(|&a, &b| taps[a].1.partial_cmp(&taps[b].1).unwrap_or(Ordering::Equal))
+            {
+                taps[quietest] = taps[count - 1];
+                count -= 1;
+            }
+        }
+        taps[count] = (self.current, fade);
+        count += 1;
+        let total: f32 = taps[..count].iter().map(|(_, share)| share).sum();
+        for (_, share) in taps[..count].iter_mut() {
+            *share /= total;
+        }
+        self.fading = taps;
+        self.fading_count = count;
+        self.current = target;
+        self.fade = 0.;
+    }
+}
+
+impl Effect for Delay {
+    fn process(&mut self, sample_rate: u32, data: &mut [f32]) {
+        let sample_rate = sample_rate as f32;
+        let params = &self.params;
+        let target = (params.seconds(self.tempo) * sample_rate).max(1.);
+        let feedback = params.feedback.get();
+        let damping = 1. - (-2. * PI * params.damping.get() / sample_rate).exp();
+        let ping_pong = params.ping_pong.is_on();
+        let lfo_step = params.mod_rate.get() / sample_rate;
+        let depth = params.mod_depth.get() / 1000. * sample_rate;
+        let fade_step = 1. / (CROSSFADE_SECONDS * sample_rate);
+        for frame in data.chunks_exact_mut(2) {
+            // jumping the read position would click, so crossfade between the old and new taps
+            if (target - self.current).abs() >= 1. {
+                self.retarget(target);
+            }
+            let fading = &self.fading[..self.fading_count];
+            let mut delayed = [0f32; 2];
+            for (channel, out) in delayed.iter_mut().enumerate() {
+                // offset the channels by a quarter period to widen the modulation
+                let lfo = (2. * PI * (self.lfo_phase + channel as f32 * 0.25)).sin();
+                let modulation = depth * 0.5 * (1. + lfo);
+                let line = &self.lines[channel];
+                let mut value = line.read_frac(self.current + modulation);
+                if self.fade < 1. {
+                    let old: f32 = fading
+                        .iter()
+                        .map(|(delay, share)| share * line.read_frac(delay + modulation))
+                        .sum();
+                    value = old + (value - old) * self.fade;
+                }
+                *out = value;
+            }
+            if self.fade < 1. {
+                self.fade = (self.fade + fade_step).min(1.);
+            }
+            self.lfo_phase = (self.lfo_phase + lfo_step).fract();
+
+            for (state, value) in self.damp_state.iter_mut().zip(delayed.iter()) {
+                *state += (value - *state) * damping;
+            }
+            let fb_left = self.damp_state[0] * feedback;
+            let fb_right = self.damp_state[1] * feedback;
+            let (write_left, write_right) = if ping_pong {
+                ((frame[0] + frame[1]) * 0.5 + fb_right, fb_left)
+            } else {
+                (frame[0] + fb_left, frame[1] + fb_right)
+            };
+            self.lines[0].write(write_left);
+            self.lines[1].write(write_right);
+
+            frame.copy_from_slice(&delayed);
+        }
+    }
+
+    fn reset(&mut self) {
+        for line in self.lines.iter_mut() {
+            line.clear();
+        }
+        self.damp_state = [0.; 2];
+        self.fade = 1.;
+        self.fading_count = 0;
+    }
+
+    fn set_transport(&mut self, transport: &TransportState) {
+        self.tempo = transport.tempo as f32;
+    }
+
+    fn params(&self) -> Arc<dyn ParamSet> {
+        self.params.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Delay;
+    use crate::effects::Effect;
+
+    #[test]
+    fn echo_at_delay_time() {
+        let mut delay = Delay::new();
+        delay.params.time.set(10.);
+        delay.params.feedback.set(0.);
+        delay.params.damping.set(20000.);
+        // settle on the new delay time before sending anything through
+        let mut data = vec![0f32; 48000 / 10 * 2];
+        delay.process(48000, &mut data);
+        let mut data = vec![0f32; 2048];
+        data[0] = 1.;
+        data[1] = 1.;
+        delay.process(48000, &mut data);
+        let peak = data
+            .chunks_exact(2)
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a[0].abs().partial_cmp(&b[0].abs()).unwrap())
+            .unwrap()
+            .0;
+        assert_eq!(480, peak);
+    }
+
+    #[test]
+    fn retarget_mid_fade() {
+        let mut delay = Delay::new();
+        delay.params.time.set(10.);
+        let mut data = vec![0f32; 48000 / 10 * 2];
+        delay.process(48000, &mut data);
+        // turning the knob again before the crossfade is done still gets there
+        let mut data = vec![0f32; 200];
+        delay.params.time.set(20.);
+        delay.process(48000, &mut data);
+        delay.params.time.set(30.);
+        delay.process(48000, &mut data);
+        assert_eq!(1440., delay.current);
+        assert_eq!(2, delay.fading_count);
+        let shares: f32 = delay.fading[..2].iter().map(|(_, share)| share).sum();
+        assert!((shares - 1.).abs() < 1e-6);
+    }
+}
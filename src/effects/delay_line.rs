@@ -0,0 +1,44 @@
+/// Circular buffer for delay based effects.
+pub struct DelayLine {
+    buffer: Vec<f32>,
+    pos: usize,
+}
+
+impl DelayLine {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            buffer: vec![0.; max_len],
+            pos: 0,
+        }
+    }
+
+    /// Value written `len` samples ago. Call before `write` for the current sample.
+    pub fn read(&self, len: usize) -> f32 {
+        let size = self.buffer.len();
+        self.buffer[(self.pos + size - len) % size]
+    }
+
+    /// Linearly interpolated read for modulated delays.
+    pub fn read_frac(&self, delay: f32) -> f32 {
+        let size = self.buffer.len();
+        let pos = self.pos as f32 + size as f32 - delay;
+        let index = pos.floor();
+        let frac = pos - index;
+        let a = self.buffer[index as usize % size];
+        let b = self.buffer[(index as usize + 1) % size];
+        a + (b - a) * frac
+    }
+
+    pub fn write(&mut self, value: f32) {
+        self.buffer[self.pos] = value;
+        self.pos = (self.pos + 1) % self.buffer.len();
+    }
+
+    pub fn clear(&mut self) {
+        self.fill(0.);
+    }
+
+    pub fn fill(&mut self, value: f32) {
+        self.buffer.fill(value);
+    }
+}
@@ -0,0 +1,374 @@
+use super::{db_to_gain, Effect};
+use crate::param::{Param, ParamSet};
+use std::{f32::consts::PI, sync::Arc};
+
+/// filter length per oversampling phase
+const TAPS_PER_PHASE: usize = 16;
+const OVERSAMPLING_FACTORS: [usize; 3] = [1, 2, 4];
+const DC_BLOCKER_HZ: f32 = 10.;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    Off,
+    Tanh,
+    HardClip,
+    Foldback,
+    Tube,
+}
+
+const SHAPES: [Shape; 5] = [
+    Shape::Off,
+    Shape::Tanh,
+    Shape::HardClip,
+    Shape::Foldback,
+    Shape::Tube,
+];
+
+impl Shape {
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            Shape::Off => x,
+            Shape::Tanh => x.tanh(),
+            Shape::HardClip => x.clamp(-1., 1.),
+            // triangle shaped folding back and forth between -1 and 1
+            Shape::Foldback => 1. - ((x + 1.).rem_euclid(4.) - 2.).abs(),
+            // biased tanh, saturates earlier on one side. the dc is removed afterwards
+            Shape::Tube => {
+                const BIAS: f32 = 0.4;
+                (x + BIAS).tanh() - BIAS.tanh()
+            }
+        }
+    }
+}
+
+pub struct ShaperParams {
+    pub shape: Param,
+    pub drive: Param,
+    pub oversampling: Param,
+}
+
+impl ParamSet for ShaperParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![&self.shape, &self.drive, &self.oversampling]
+    }
+}
+
+impl ShaperParams {
+    pub fn new(shape: Shape, drive: f32) -> Self {
+        Self {
+            shape: Param::choice(
+                "shape",
+                SHAPES.iter().position(|s| *s == shape).unwrap(),
+                &["off", "tanh", "hard clip", "foldback", "tube"],
+            ),
+            drive: Param::new("drive", "dB", drive, 0f32..=48f32),
+            oversampling: Param::choice("oversampling", 1, &["1x", "2x", "4x"]),
+        }
+    }
+
+    /// Snapshot of the current values, to read once per block.
+    pub fn settings(&self) -> ShaperSettings {
+        ShaperSettings {
+            shape: SHAPES[self.shape.get_index()],
+            drive: db_to_gain(self.drive.get()),
+            oversampling: OVERSAMPLING_FACTORS[self.oversampling.get_index()],
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ShaperSettings {
+    shape: Shape,
+    drive: f32,
+    oversampling: usize,
+}
+
+/// Windowed sinc polyphase resampler that runs a function at a multiple of the sample rate.
+#[derive(Clone)]
+struct Oversampler {
+    factor: usize,
+    coefficients: Vec<f32>,
+    input: [f32; TAPS_PER_PHASE],
+    input_pos: usize,
+    output: Vec<f32>,
+    output_pos: usize,
+}
+
+impl Oversampler {
+    fn new(factor: usize) -> Self {
+        let taps = TAPS_PER_PHASE * factor;
+        // cut a bit below the original nyquist
+        let cutoff = 0.45 / factor as f32;
+        let coefficients: Vec<f32> = (0..taps)
+            .map(|i| {
+                let x = i as f32 - (taps - 1) as f32 / 2.;
+                let sinc = if x == 0. {
+                    2. * cutoff
+                } else {
+                    (2. * PI * cutoff * x).sin() / (PI * x)
+                };
+                let blackman = 0.42 - 0.5 * (2. * PI * i as f32 / (taps - 1) as f32).cos()
+                    + 0.08 * (4. * PI * i as f32 / (taps - 1) as f32).cos();
+                sinc * blackman
+            })
+            .collect();
+        let sum: f32 = coefficients.iter().sum();
+        Self {
+            factor,
+            coefficients: coefficients.iter().map(|c| c / sum).collect(),
+            input: [0.; TAPS_PER_PHASE],
+            input_pos: 0,
+            output: vec![0.; taps],
+            output_pos: 0,
+        }
+    }
+
+    /// Latency in frames at the original rate.
+    fn latency(&self) -> u32 {
+        ((self.coefficients.len() - 1) / self.factor) as u32
+    }
+
+    fn process(&mut self, x: f32, mut f: impl FnMut(f32) -> f32) -> f32 {
+        self.input_pos = (self.input_pos + 1) % TAPS_PER_PHASE;
+        self.input[self.input_pos] = x;
+        let taps = self.coefficients.len();
+        for phase in 0..self.factor {
+            // only every factor:th sample of the zero stuffed signal is non zero,
+            // so each phase only needs every factor:th coefficient
+            let mut up = 0.;
+            for k in 0..TAPS_PER_PHASE {
+                let sample = self.input[(self.input_pos + TAPS_PER_PHASE - k) % TAPS_PER_PHASE];
+                up += self.coefficients[phase + k * self.factor] * sample;
+            }
+            self.output_pos = (self.output_pos + 1) % taps;
+            self.output[self.output_pos] = f(up * self.factor as f32);
+        }
+        let mut down = 0.;
+        for (j, coefficient) in self.coefficients.iter().enumerate() {
+            down += coefficient * self.output[(self.output_pos + taps - j) % taps];
+        }
+        down
+    }
+
+    fn reset(&mut self) {
+        self.input = [0.; TAPS_PER_PHASE];
+        self.output.fill(0.);
+    }
+}
+
+/// Single channel waveshaper with optional oversampling.
+/// Used both per voice in the synth and in the distortion effect.
+#[derive(Clone)]
+pub struct Shaper {
+    x2: Oversampler,
+    x4: Oversampler,
+    dc_in: f32,
+    dc_out: f32,
+}
+
+impl Shaper {
+    pub fn new() -> Self {
+        Self {
+            x2: Oversampler::new(2),
+            x4: Oversampler::new(4),
+            dc_in: 0.,
+            dc_out: 0.,
+        }
+    }
+
+    pub fn latency(&self, settings: &ShaperSettings) -> u32 {
+        match settings.oversampling {
+            2 => self.x2.latency(),
+            4 => self.x4.latency(),
+            _ => 0,
+        }
+    }
+
+    pub fn process(&mut self, settings: &ShaperSettings, sample_rate: u32, x: f32) -> f32 {
+        if settings.shape == Shape::Off {
+            return x;
+        }
+        let shape = |x: f32| settings.shape.apply(x * settings.drive);
+        let y = match settings.oversampling {
+            2 => self.x2.process(x, shape),
+            4 => self.x4.process(x, shape),
+            _ => shape(x),
+        };
+        // one pole highpass to get rid of the offset from asymmetric shapes
+        let coefficient = (-2. * PI * DC_BLOCKER_HZ / sample_rate as f32).exp();
+        self.dc_out = y - self.dc_in + coefficient * self.dc_out;
+        self.dc_in = y;
+        self.dc_out
+    }
+
+    pub fn reset(&mut self) {
+        self.x2.reset();
+        self.x4.reset();
+        self.dc_in = 0.;
+        self.dc_out = 0.;
+    }
+}
+
+pub struct DistortionParams {
+    pub shaper: ShaperParams,
+    pub output: Param,
+}
+
+impl ParamSet for DistortionParams {
+    fn params(&self) -> Vec<&Param> {
+        let mut params = self.shaper.params();
+        params.push(&self.output);
+        params
+    }
+}
+
+pub struct Distortion {
+    params: Arc<DistortionParams>,
+    shapers: [Shaper; 2],
+    latency: u32,
+}
+
+impl Distortion {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(DistortionParams {
+                shaper: ShaperParams::new(Shape::Tanh, 12.),
+                output: Param::new("output", "dB", -6., -48f32..=12f32),
+            }),
+            shapers: [Shaper::new(), Shaper::new()],
+            latency: 0,
+        }
+    }
+}
+
+impl Effect for Distortion {
+    fn process(&mut self, sample_rate: u32, data: &mut [f32]) {
+        let settings = self.params.shaper.settings();
+        let latency = self.shapers[0].latency(&settings);
+        if latency != self.latency {
+            // the filters would contain stale audio from the last time the factor was used
+            for shaper in self.shapers.iter_mut() {
+                shaper.reset();
+            }
+            self.latency = latency;
+        }
+        let output = db_to_gain(self.params.output.get());
+        for frame in data.chunks_exact_mut(2) {
+            for (sample, shaper) in frame.iter_mut().zip(self.shapers.iter_mut()) {
+                *sample = shaper.process(&settings, sample_rate, *sample) * output;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for shaper in self.shapers.iter_mut() {
+            shaper.reset();
+        }
+    }
+
+    fn latency(&self) -> u32 {
+        self.latency
+    }
+
+    fn params(&self) -> Arc<dyn ParamSet> {
+        self.params.clone()
+    }
+}
+
+pub struct BitcrusherParams {
+    pub bits: Param,
+    pub rate: Param,
+}
+
+impl ParamSet for BitcrusherParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![&self.bits, &self.rate]
+    }
+}
+
+/// Reduces bit depth and sample rate. Aliasing is the point here, so no oversampling.
+pub struct Bitcrusher {
+    params: Arc<BitcrusherParams>,
+    phase: f32,
+    held: [f32; 2],
+}
+
+impl Bitcrusher {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(BitcrusherParams {
+                bits: Param::new("bits", "", 8., 1f32..=24f32),
+                rate: Param::new("rate", "Hz", 8000., 100f32..=48000f32).logarithmic(),
+            }),
+            phase: 1.,
+            held: [0.; 2],
+        }
+    }
+}
+
+impl Effect for Bitcrusher {
+    fn process(&mut self, sample_rate: u32, data: &mut [f32]) {
+        let steps = 2f32.powf(self.params.bits.get() - 1.);
+        let step = (self.params.rate.get() / sample_rate as f32).min(1.);
+        for frame in data.chunks_exact_mut(2) {
+            if self.phase >= 1. {
+                self.phase -= 1.;
+                for (held, sample) in self.held.iter_mut().zip(frame.iter()) {
+                    *held = (sample * steps).round() / steps;
+                }
+            }
+            self.phase += step;
+            frame.copy_from_slice(&self.held);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.phase = 1.;
+        self.held = [0.; 2];
+    }
+
+    fn params(&self) -> Arc<dyn ParamSet> {
+        self.params.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Shape, Shaper, ShaperParams};
+    use std::f32::consts::PI;
+
+    /// Energy of the signal at `hz`, using a single dft bin.
+    fn energy_at(signal: &[f32], hz: f32, sample_rate: f32) -> f32 {
+        let (mut re, mut im) = (0f32, 0f32);
+        for (i, x) in signal.iter().enumerate() {
+            let phase = 2. * PI * hz * i as f32 / sample_rate;
+            re += x * phase.cos();
+            im += x * phase.sin();
+        }
+        (re * re + im * im).sqrt() / signal.len() as f32
+    }
+
+    #[test]
+    fn oversampling_reduces_aliasing() {
+        let sample_rate = 48000;
+        // harmonics of 5kHz above nyquist fold back to 7kHz (9th) and 3kHz (11th) among others
+        let fundamental = 5000.;
+        let aliasing = |oversampling: usize| {
+            let params = ShaperParams::new(Shape::HardClip, 24.);
+            params.oversampling.set(oversampling as f32);
+            let settings = params.settings();
+            let mut shaper = Shaper::new();
+            let signal: Vec<f32> = (0..4800)
+                .map(|i| {
+                    let x = (2. * PI * fundamental * i as f32 / sample_rate as f32).sin();
+                    shaper.process(&settings, sample_rate, x)
+                })
+                .collect();
+            energy_at(&signal[480..], 7000., sample_rate as f32)
+                + energy_at(&signal[480..], 3000., sample_rate as f32)
+        };
+        let none = aliasing(0);
+        let x4 = aliasing(2);
+        assert!(x4 < none * 0.25, "{} {}", none, x4);
+    }
+}
@@ -0,0 +1,259 @@
+use super::{db_to_gain, delay_line::DelayLine, gain_to_db, Effect, MAX_SAMPLE_RATE};
+use crate::param::{Param, ParamSet};
+use crossbeam::atomic::AtomicCell;
+use std::sync::Arc;
+
+const LOOKAHEAD_MS: f32 = 5.;
+/// gain reduction meters are in dB and only go this far
+pub const MAX_METERED_REDUCTION_DB: f32 = 24.;
+
+/// one pole smoothing coefficient reaching ~63% in `ms`
+fn time_coefficient(ms: f32, sample_rate: f32) -> f32 {
+    1. - (-1000. / (ms * sample_rate)).exp()
+}
+
+pub struct CompressorParams {
+    pub threshold: Param,
+    pub ratio: Param,
+    pub attack: Param,
+    pub release: Param,
+    pub makeup: Param,
+}
+
+impl ParamSet for CompressorParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![
+            &self.threshold,
+            &self.ratio,
+            &self.attack,
+            &self.release,
+            &self.makeup,
+        ]
+    }
+}
+
+/// Feed forward stereo linked peak compressor.
+pub struct Compressor {
+    params: Arc<CompressorParams>,
+    /// current gain reduction in dB
+    reduction: f32,
+    meter: Arc<AtomicCell<f32>>,
+}
+
+impl Compressor {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(CompressorParams {
+                threshold: Param::new("threshold", "dB", -18., -60f32..=0f32),
+                ratio: Param::new("ratio", ":1", 4., 1f32..=20f32).logarithmic(),
+                attack: Param::new("attack", "ms", 10., 0.1f32..=200f32).logarithmic(),
+                release: Param::new("release", "ms", 150., 5f32..=2000f32).logarithmic(),
+                makeup: Param::new("makeup", "dB", 0., 0f32..=24f32),
+            }),
+            reduction: 0.,
+            meter: Arc::new(0f32.into()),
+        }
+    }
+}
+
+impl Effect for Compressor {
+    fn process(&mut self, sample_rate: u32, data: &mut [f32]) {
+        let sample_rate = sample_rate as f32;
+        let params = &self.params;
+        let threshold = params.threshold.get();
+        let slope = 1. - 1. / params.ratio.get();
+        let attack = time_coefficient(params.attack.get(), sample_rate);
+        let release = time_coefficient(params.release.get(), sample_rate);
+        let makeup = params.makeup.get();
+        let mut max_reduction = 0f32;
+        for frame in data.chunks_exact_mut(2) {
+            let level = gain_to_db(frame[0].abs().max(frame[1].abs()));
+            let target = (level - threshold).max(0.) * slope;
+            let coefficient = if target > self.reduction {
+                attack
+            } else {
+                release
+            };
+            self.reduction += (target - self.reduction) * coefficient;
+            max_reduction = max_reduction.max(self.reduction);
+            let gain = db_to_gain(makeup - self.reduction);
+            frame[0] *= gain;
+            frame[1] *= gain;
+        }
+        self.meter.store(max_reduction);
+    }
+
+    fn reset(&mut self) {
+        self.reduction = 0.;
+        self.meter.store(0.);
+    }
+
+    fn params(&self) -> Arc<dyn ParamSet> {
+        self.params.clone()
+    }
+
+    fn gain_reduction(&self) -> Option<Arc<AtomicCell<f32>>> {
+        Some(self.meter.clone())
+    }
+}
+
+pub struct LimiterParams {
+    pub ceiling: Param,
+    pub release: Param,
+}
+
+impl ParamSet for LimiterParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![&self.ceiling, &self.release]
+    }
+}
+
+impl LimiterParams {
+    pub fn new() -> Self {
+        Self {
+            ceiling: Param::new("ceiling", "dB", -0.3, -24f32..=0f32),
+            release: Param::new("release", "ms", 100., 10f32..=1000f32).logarithmic(),
+        }
+    }
+}
+
+/// Brick wall limiter that delays the signal a few milliseconds to see peaks coming.
+///
+/// The required gain is held for the lookahead time and then smoothed with a moving average
+/// of the same length, so the gain has ramped all the way down by the time a peak comes out of the delay.
+pub struct Limiter {
+    params: Arc<LimiterParams>,
+    delay: [DelayLine; 2],
+    /// held gains for the moving average
+    history: DelayLine,
+    sum: f64,
+    held: f32,
+    hold_counter: usize,
+    lookahead: usize,
+    meter: Arc<AtomicCell<f32>>,
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        Self::with_params(Arc::new(LimiterParams::new()))
+    }
+
+    /// Creates a limiter controlled by existing params,
+    /// for when the audio stream is recreated but the settings should stay.
+    pub fn with_params(params: Arc<LimiterParams>) -> Self {
+        let len = (LOOKAHEAD_MS / 1000. * MAX_SAMPLE_RATE as f32) as usize + 1;
+        Self {
+            params,
+            delay: [DelayLine::new(len), DelayLine::new(len)],
+            history: DelayLine::new(len),
+            sum: 0.,
+            held: 1.,
+            hold_counter: 0,
+            lookahead: 0,
+            meter: Arc::new(0f32.into()),
+        }
+    }
+}
+
+impl Effect for Limiter {
+    fn process(&mut self, sample_rate: u32, data: &mut [f32]) {
+        let lookahead = ((LOOKAHEAD_MS / 1000. * sample_rate as f32) as usize).max(1);
+        if lookahead != self.lookahead {
+            self.lookahead = lookahead;
+            self.reset();
+        }
+        let ceiling = db_to_gain(self.params.ceiling.get());
+        let release = time_coefficient(self.params.release.get(), sample_rate as f32);
+        let mut min_gain = 1f32;
+        for frame in data.chunks_exact_mut(2) {
+            let peak = frame[0].abs().max(frame[1].abs());
+            let required = if peak > ceiling { ceiling / peak } else { 1. };
+            if required <= self.held {
+                self.held = required;
+                self.hold_counter = lookahead;
+            } else if self.hold_counter > 0 {
+                self.hold_counter -= 1;
+            } else {
+                self.held += (required - self.held) * release;
+            }
+            self.sum += self.held as f64 - self.history.read(lookahead) as f64;
+            self.history.write(self.held);
+            let gain = (self.sum / lookahead as f64) as f32;
+            min_gain = min_gain.min(gain);
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let delayed = self.delay[channel].read(lookahead);
+                self.delay[channel].write(*sample);
+                // the clamp should only ever catch rounding errors
+                *sample = (delayed * gain).clamp(-ceiling, ceiling);
+            }
+        }
+        self.meter.store(-gain_to_db(min_gain));
+    }
+
+    fn reset(&mut self) {
+        for line in self.delay.iter_mut() {
+            line.clear();
+        }
+        // the history is considered to be full of unity gain
+        self.history.fill(1.);
+        self.sum = self.lookahead as f64;
+        self.held = 1.;
+        self.hold_counter = 0;
+        self.meter.store(0.);
+    }
+
+    fn latency(&self) -> u32 {
+        self.lookahead as u32
+    }
+
+    fn params(&self) -> Arc<dyn ParamSet> {
+        self.params.clone()
+    }
+
+    fn gain_reduction(&self) -> Option<Arc<AtomicCell<f32>>> {
+        Some(self.meter.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Compressor, Limiter};
+    use crate::effects::{db_to_gain, gain_to_db, render_offline, Effect};
+
+    fn sine(amplitude: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|i| {
+                let value = amplitude * (i as f32 * 0.05).sin();
+                vec![value, value]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn limiter_never_exceeds_ceiling() {
+        let mut limiter = Limiter::new();
+        let ceiling = db_to_gain(limiter.params.ceiling.get());
+        let mut input = sine(0.5, 4800);
+        // sudden loud transient
+        input.extend(sine(4., 4800));
+        let output = render_offline(&mut limiter, 48000, &input);
+        assert!(output.iter().all(|v| v.abs() <= ceiling));
+        // quiet parts come through untouched, just delayed
+        let latency = limiter.latency() as usize;
+        assert!((output[1000 * 2] - input[(1000 - latency) * 2]).abs() < 1e-6);
+        assert!(limiter.meter.load() > 12.);
+    }
+
+    #[test]
+    fn compressor_ratio() {
+        let mut compressor = Compressor::new();
+        compressor.params.threshold.set(-20.);
+        compressor.params.ratio.set(4.);
+        // a dc level makes the steady state easy to check
+        let input = vec![db_to_gain(-8.); 48000 * 2];
+        let output = render_offline(&mut compressor, 48000, &input);
+        // 12dB over the threshold should come out 3dB over
+        let level = gain_to_db(*output.last().unwrap());
+        assert!((level - -17.).abs() < 0.1, "{}", level);
+    }
+}
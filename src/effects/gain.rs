@@ -0,0 +1,52 @@
+use super::{db_to_gain, Effect};
+use crate::param::{Param, ParamSet};
+use std::sync::Arc;
+
+pub struct GainParams {
+    pub gain: Param,
+}
+
+impl ParamSet for GainParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![&self.gain]
+    }
+}
+
+pub struct Gain {
+    params: Arc<GainParams>,
+    current: f32,
+}
+
+impl Gain {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(GainParams {
+                gain: Param::new("gain", "dB", 0., -48f32..=24f32),
+            }),
+            current: 1.,
+        }
+    }
+}
+
+impl Effect for Gain {
+    fn process(&mut self, _sample_rate: u32, data: &mut [f32]) {
+        let target = db_to_gain(self.params.gain.get());
+        let frames = data.len() / 2;
+        // ramp over the block to avoid zipper noise
+        let step = (target - self.current) / frames.max(1) as f32;
+        for frame in data.chunks_exact_mut(2) {
+            self.current += step;
+            frame[0] *= self.current;
+            frame[1] *= self.current;
+        }
+        self.current = target;
+    }
+
+    fn reset(&mut self) {
+        self.current = db_to_gain(self.params.gain.get());
+    }
+
+    fn params(&self) -> Arc<dyn ParamSet> {
+        self.params.clone()
+    }
+}
@@ -0,0 +1,149 @@
+use crate::param::ParamSet;
+use crate::transport::TransportState;
+use crossbeam::atomic::AtomicCell;
+use std::sync::Arc;
+
+mod chain;
+mod clipper;
+mod delay;
+mod delay_line;
+mod distortion;
+mod dynamics;
+mod gain;
+mod modulation;
+mod reverb;
+
+pub use chain::{EffectChain, MasterChain, Slot, SlotState, MAX_SLOTS};
+pub use clipper::SoftClipper;
+pub use delay::Delay;
+pub use distortion::{Bitcrusher, Distortion, Shape, Shaper, ShaperParams};
+pub use dynamics::{Compressor, Limiter, LimiterParams, MAX_METERED_REDUCTION_DB};
+pub use gain::Gain;
+pub use modulation::{Chorus, Flanger, Phaser};
+pub use reverb::Reverb;
+
+/// Effects that need memory proportional to the sample rate allocate for this up front.
+pub const MAX_SAMPLE_RATE: u32 = 192000;
+
+/// Something that processes the stereo output of the synth.
+///
+/// Audio is passed as interleaved left/right frames.
+pub trait Effect: Send {
+    fn process(&mut self, sample_rate: u32, data: &mut [f32]);
+
+    /// Called before each `process` with where the transport is, for effects that sync to it.
+    fn set_transport(&mut self, _transport: &TransportState) {}
+
+    /// Clear any internal state such as delay lines or envelopes.
+    fn reset(&mut self);
+
+    /// Latency introduced by the effect, in frames.
+    fn latency(&self) -> u32 {
+        0
+    }
+
+    fn params(&self) -> Arc<dyn ParamSet>;
+
+    /// For dynamics processors, the current gain reduction in dB for metering.
+    fn gain_reduction(&self) -> Option<Arc<AtomicCell<f32>>> {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EffectKind {
+    Gain,
+    SoftClipper,
+    Delay,
+    Reverb,
+    Chorus,
+    Flanger,
+    Phaser,
+    Compressor,
+    Limiter,
+    Distortion,
+    Bitcrusher,
+}
+
+impl EffectKind {
+    pub const ALL: &'static [EffectKind] = &[
+        EffectKind::Gain,
+        EffectKind::SoftClipper,
+        EffectKind::Delay,
+        EffectKind::Reverb,
+        EffectKind::Chorus,
+        EffectKind::Flanger,
+        EffectKind::Phaser,
+        EffectKind::Compressor,
+        EffectKind::Limiter,
+        EffectKind::Distortion,
+        EffectKind::Bitcrusher,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EffectKind::Gain => "gain",
+            EffectKind::SoftClipper => "soft clipper",
+            EffectKind::Delay => "delay",
+            EffectKind::Reverb => "reverb",
+            EffectKind::Chorus => "chorus",
+            EffectKind::Flanger => "flanger",
+            EffectKind::Phaser => "phaser",
+            EffectKind::Compressor => "compressor",
+            EffectKind::Limiter => "limiter",
+            EffectKind::Distortion => "distortion",
+            EffectKind::Bitcrusher => "bitcrusher",
+        }
+    }
+
+    /// Where the slot's mix starts, lower for effects that only output their wet signal.
+    pub fn default_mix(self) -> f32 {
+        match self {
+            EffectKind::Delay => 0.3,
+            EffectKind::Reverb => 0.25,
+            EffectKind::Chorus | EffectKind::Flanger | EffectKind::Phaser => 0.5,
+            _ => 1.,
+        }
+    }
+
+    /// Creates a new instance of the effect.
+    /// Any buffers are allocated here, so don't call this from the audio thread.
+    pub fn create(self) -> Box<dyn Effect> {
+        match self {
+            EffectKind::Gain => Box::new(Gain::new()),
+            EffectKind::SoftClipper => Box::new(SoftClipper::new()),
+            EffectKind::Delay => Box::new(Delay::new()),
+            EffectKind::Reverb => Box::new(Reverb::new()),
+            EffectKind::Chorus => Box::new(Chorus::new()),
+            EffectKind::Flanger => Box::new(Flanger::new()),
+            EffectKind::Phaser => Box::new(Phaser::new()),
+            EffectKind::Compressor => Box::new(Compressor::new()),
+            EffectKind::Limiter => Box::new(Limiter::new()),
+            EffectKind::Distortion => Box::new(Distortion::new()),
+            EffectKind::Bitcrusher => Box::new(Bitcrusher::new()),
+        }
+    }
+}
+
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.)
+}
+
+pub fn gain_to_db(gain: f32) -> f32 {
+    20. * gain.max(1e-6).log10()
+}
+
+/// Runs an effect over a whole interleaved buffer in callback sized blocks.
+#[cfg(test)]
+pub fn render_offline(effect: &mut dyn Effect, sample_rate: u32, input: &[f32]) -> Vec<f32> {
+    const BLOCK_FRAMES: usize = 256;
+    let mut output = input.to_vec();
+    for block in output.chunks_mut(BLOCK_FRAMES * 2) {
+        effect.process(sample_rate, block);
+    }
+    output
+}
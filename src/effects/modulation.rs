@@ -0,0 +1,401 @@
+use super::{delay_line::DelayLine, Effect, MAX_SAMPLE_RATE};
+use crate::param::{Param, ParamSet};
+use crate::transport::TransportState;
+use std::{f32::consts::PI, sync::Arc};
+
+const MAX_CHORUS_VOICES: usize = 4;
+const CHORUS_BASE_MS: f32 = 12.;
+const CHORUS_MAX_DEPTH_MS: f32 = 8.;
+const FLANGER_MAX_MS: f32 = 10.;
+const MAX_PHASER_STAGES: usize = 12;
+const PHASER_MIN_HZ: f32 = 200.;
+const PHASER_MAX_HZ: f32 = 5000.;
+
+const SYNC_NAMES: &[&str] = &["off", "4/1", "2/1", "1/1", "1/2", "1/4", "1/8", "1/16"];
+/// length of a cycle for each of the above, in quarter notes
+const SYNC_QUARTERS: &[f64] = &[0., 16., 8., 4., 2., 1., 0.5, 0.25];
+
+fn max_len(ms: f32) -> usize {
+    (ms / 1000. * MAX_SAMPLE_RATE as f32) as usize + 2
+}
+
+/// The params all the modulation effects have in common.
+pub struct ModulationParams {
+    pub rate: Param,
+    /// lfo cycle length in notes instead of the rate
+    pub sync: Param,
+    pub depth: Param,
+    pub feedback: Param,
+    pub spread: Param,
+    /// chorus voices or phaser stages
+    pub count: Option<Param>,
+}
+
+impl ParamSet for ModulationParams {
+    fn params(&self) -> Vec<&Param> {
+        let mut params = vec![
+            &self.rate,
+            &self.sync,
+            &self.depth,
+            &self.feedback,
+            &self.spread,
+        ];
+        params.extend(self.count.as_ref());
+        params
+    }
+}
+
+impl ModulationParams {
+    fn new(rate: f32, feedback: f32, min_feedback: f32, count: Option<Param>) -> Self {
+        Self {
+            rate: Param::new("rate", "Hz", rate, 0.01f32..=10f32).logarithmic(),
+            sync: Param::choice("sync", 0, SYNC_NAMES),
+            depth: Param::new("depth", "", 0.5, 0f32..=1f32),
+            feedback: Param::new("feedback", "", feedback, min_feedback..=0.95f32),
+            spread: Param::new("spread", "°", 90., 0f32..=180f32),
+            count,
+        }
+    }
+}
+
+/// Sine lfo that can be read at different phase offsets.
+struct Lfo {
+    phase: f32,
+}
+
+impl Lfo {
+    fn new() -> Self {
+        Self { phase: 0. }
+    }
+
+    /// The rate in Hz. When synced this follows the tempo, and the song position while playing.
+    fn follow(&mut self, params: &ModulationParams, transport: &TransportState) -> f32 {
+        let quarters = SYNC_QUARTERS[params.sync.get_index()];
+        if quarters <= 0. {
+            return params.rate.get();
+        }
+        if transport.playing {
+            self.phase = (transport.position / quarters).fract() as f32;
+        }
+        (transport.tempo / 60. / quarters) as f32
+    }
+
+    fn advance(&mut self, rate: f32, sample_rate: f32) {
+        self.phase = (self.phase + rate / sample_rate).fract();
+    }
+
+    /// In the range 0 to 1. `offset` is in periods.
+    fn unipolar(&self, offset: f32) -> f32 {
+        0.5 * (1. + (2. * PI * (self.phase + offset)).sin())
+    }
+}
+
+/// Several modulated delay taps per channel, spread out in phase.
+pub struct Chorus {
+    params: Arc<ModulationParams>,
+    lines: [DelayLine; 2],
+    feedback: [f32; 2],
+    lfo: Lfo,
+    transport: TransportState,
+}
+
+impl Chorus {
+    pub fn new() -> Self {
+        let len = max_len(CHORUS_BASE_MS + CHORUS_MAX_DEPTH_MS);
+        Self {
+            params: Arc::new(ModulationParams::new(
+                0.8,
+                0.,
+                0.,
+                Some(Param::choice("voices", 2, &["1", "2", "3", "4"])),
+            )),
+            lines: [DelayLine::new(len), DelayLine::new(len)],
+            feedback: [0.; 2],
+            lfo: Lfo::new(),
+            transport: TransportState::default(),
+        }
+    }
+}
+
+impl Effect for Chorus {
+    fn process(&mut self, sample_rate: u32, data: &mut [f32]) {
+        let sample_rate = sample_rate as f32;
+        let params = &self.params;
+        let rate = self.lfo.follow(params, &self.transport);
+        let depth = params.depth.get() * CHORUS_MAX_DEPTH_MS / 1000. * sample_rate;
+        let base = CHORUS_BASE_MS / 1000. * sample_rate;
+        let feedback = params.feedback.get();
+        let spread = params.spread.get() / 360.;
+        let voices = params
+            .count
+            .as_ref()
+            .map_or(MAX_CHORUS_VOICES, |count| count.get_index() + 1);
+        for frame in data.chunks_exact_mut(2) {
+            let mut wet = [0f32; 2];
+            for (channel, wet) in wet.iter_mut().enumerate() {
+                let line = &mut self.lines[channel];
+                for voice in 0..voices {
+                    let offset = voice as f32 / voices as f32 + channel as f32 * spread;
+                    *wet += line.read_frac(base + depth * self.lfo.unipolar(offset));
+                }
+                *wet /= voices as f32;
+                line.write(frame[channel] + self.feedback[channel] * feedback);
+                self.feedback[channel] = *wet;
+            }
+            self.lfo.advance(rate, sample_rate);
+            frame.copy_from_slice(&wet);
+        }
+    }
+
+    fn reset(&mut self) {
+        for line in self.lines.iter_mut() {
+            line.clear();
+        }
+        self.feedback = [0.; 2];
+    }
+
+    fn set_transport(&mut self, transport: &TransportState) {
+        self.transport = *transport;
+    }
+
+    fn params(&self) -> Arc<dyn ParamSet> {
+        self.params.clone()
+    }
+}
+
+/// Short modulated delay with feedback, swept from 0 up to the max delay.
+pub struct Flanger {
+    params: Arc<ModulationParams>,
+    lines: [DelayLine; 2],
+    feedback: [f32; 2],
+    lfo: Lfo,
+    transport: TransportState,
+}
+
+impl Flanger {
+    pub fn new() -> Self {
+        let len = max_len(FLANGER_MAX_MS);
+        Self {
+            params: Arc::new(ModulationParams::new(0.2, 0.6, -0.95, None)),
+            lines: [DelayLine::new(len), DelayLine::new(len)],
+            feedback: [0.; 2],
+            lfo: Lfo::new(),
+            transport: TransportState::default(),
+        }
+    }
+}
+
+impl Effect for Flanger {
+    fn process(&mut self, sample_rate: u32, data: &mut [f32]) {
+        let sample_rate = sample_rate as f32;
+        let params = &self.params;
+        let rate = self.lfo.follow(params, &self.transport);
+        let depth = params.depth.get() * FLANGER_MAX_MS / 1000. * sample_rate;
+        let feedback = params.feedback.get();
+        let spread = params.spread.get() / 360.;
+        for frame in data.chunks_exact_mut(2) {
+            let mut wet = [0f32; 2];
+            for (channel, wet) in wet.iter_mut().enumerate() {
+                let line = &mut self.lines[channel];
+                // keep at least a sample of delay so the feedback path stays causal
+                let delay = 1. + depth * self.lfo.unipolar(channel as f32 * spread);
+                *wet = line.read_frac(delay);
+                line.write(frame[channel] + self.feedback[channel] * feedback);
+                self.feedback[channel] = *wet;
+            }
+            self.lfo.advance(rate, sample_rate);
+            frame.copy_from_slice(&wet);
+        }
+    }
+
+    fn reset(&mut self) {
+        for line in self.lines.iter_mut() {
+            line.clear();
+        }
+        self.feedback = [0.; 2];
+    }
+
+    fn set_transport(&mut self, transport: &TransportState) {
+        self.transport = *transport;
+    }
+
+    fn params(&self) -> Arc<dyn ParamSet> {
+        self.params.clone()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct AllPass {
+    x1: f32,
+    y1: f32,
+}
+
+impl AllPass {
+    fn process(&mut self, coefficient: f32, x: f32) -> f32 {
+        let y = coefficient * x + self.x1 - coefficient * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+}
+
+/// Chain of first order allpass filters with swept break frequency.
+pub struct Phaser {
+    params: Arc<ModulationParams>,
+    stages: [[AllPass; MAX_PHASER_STAGES]; 2],
+    feedback: [f32; 2],
+    lfo: Lfo,
+    transport: TransportState,
+}
+
+impl Phaser {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(ModulationParams::new(
+                0.3,
+                0.5,
+                -0.95,
+                Some(Param::choice(
+                    "stages",
+                    1,
+                    &["2", "4", "6", "8", "10", "12"],
+                )),
+            )),
+            stages: [[AllPass::default(); MAX_PHASER_STAGES]; 2],
+            feedback: [0.; 2],
+            lfo: Lfo::new(),
+            transport: TransportState::default(),
+        }
+    }
+}
+
+impl Effect for Phaser {
+    fn process(&mut self, sample_rate: u32, data: &mut [f32]) {
+        let sample_rate = sample_rate as f32;
+        let params = &self.params;
+        let rate = self.lfo.follow(params, &self.transport);
+        let depth = params.depth.get();
+        let feedback = params.feedback.get();
+        let spread = params.spread.get() / 360.;
+        let num_stages = params
+            .count
+            .as_ref()
+            .map_or(MAX_PHASER_STAGES, |count| (count.get_index() + 1) * 2);
+        let max_hz = PHASER_MIN_HZ * (PHASER_MAX_HZ / PHASER_MIN_HZ).powf(depth);
+        for frame in data.chunks_exact_mut(2) {
+            let mut wet = [0f32; 2];
+            for (channel, wet) in wet.iter_mut().enumerate() {
+                // sweep exponentially so it sounds even
+                let hz = PHASER_MIN_HZ
+                    * (max_hz / PHASER_MIN_HZ).powf(self.lfo.unipolar(channel as f32 * spread));
+                let t = (PI * hz / sample_rate).tan();
+                let coefficient = (t - 1.) / (t + 1.);
+                let mut value = frame[channel] + self.feedback[channel] * feedback;
+                for stage in self.stages[channel].iter_mut().take(num_stages) {
+                    value = stage.process(coefficient, value);
+                }
+                self.feedback[channel] = value;
+                *wet = value;
+            }
+            self.lfo.advance(rate, sample_rate);
+            // the notches come from summing with the dry signal
+            for (sample, wet) in frame.iter_mut().zip(wet.iter()) {
+                *sample = (*sample + wet) * 0.5;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.stages = [[AllPass::default(); MAX_PHASER_STAGES]; 2];
+        self.feedback = [0.; 2];
+    }
+
+    fn set_transport(&mut self, transport: &TransportState) {
+        self.transport = *transport;
+    }
+
+    fn params(&self) -> Arc<dyn ParamSet> {
+        self.params.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Chorus, Flanger, Phaser};
+    use crate::effects::{render_offline, Effect};
+    use std::{env, f32::consts::PI, fs, path::PathBuf};
+
+    const SAMPLE_RATE: u32 = 48000;
+    /// only every nth frame is stored in the golden files, to keep them small
+    const DECIMATION: usize = 16;
+
+    fn input() -> Vec<f32> {
+        // a chord of saws, different on each side
+        (0..SAMPLE_RATE as usize / 4)
+            .flat_map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let saw = |hz: f32| 2. * (t * hz).fract() - 1.;
+                let left = 0.3 * (saw(110.) + saw(164.8));
+                let right = 0.3 * (saw(138.6) + (2. * PI * 55. * t).sin());
+                vec![left, right]
+            })
+            .collect()
+    }
+
+    /// Compares the rendered output with the golden file.
+    /// Run with WAYFARER_BLESS=1 to write new golden files after an intentional change.
+    fn check_golden(name: &str, mut effect: impl Effect) {
+        let output = render_offline(&mut effect, SAMPLE_RATE, &input());
+        let decimated: Vec<f32> = output
+            .chunks_exact(2)
+            .step_by(DECIMATION)
+            .flatten()
+            .copied()
+            .collect();
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "effects",
+            "golden",
+            &format!("{}.txt", name),
+        ]
+        .iter()
+        .collect();
+        if env::var_os("WAYFARER_BLESS").is_some() {
+            let text: Vec<String> = decimated.iter().map(|v| format!("{:.6}", v)).collect();
+            fs::write(&path, text.join("\n")).unwrap();
+            return;
+        }
+        let golden: Vec<f32> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| line.parse().unwrap())
+            .collect();
+        assert_eq!(golden.len(), decimated.len());
+        for (i, (expected, actual)) in golden.iter().zip(decimated.iter()).enumerate() {
+            assert!(
+                (expected - actual).abs() < 1e-4,
+                "{} differs at {}: expected {} got {}",
+                name,
+                i,
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn chorus_golden() {
+        check_golden("chorus", Chorus::new());
+    }
+
+    #[test]
+    fn flanger_golden() {
+        check_golden("flanger", Flanger::new());
+    }
+
+    #[test]
+    fn phaser_golden() {
+        check_golden("phaser", Phaser::new());
+    }
+}
@@ -0,0 +1,221 @@
+use super::{delay_line::DelayLine, Effect, MAX_SAMPLE_RATE};
+use crate::param::{Param, ParamSet};
+use std::sync::Arc;
+
+const NUM_LINES: usize = 8;
+/// lengths of the feedback delay lines at size 1, mutually prime-ish to avoid piling up resonances
+const LINE_MS: [f32; NUM_LINES] = [31.7, 37.1, 41.9, 47.3, 53.9, 59.3, 67.1, 73.7];
+const MAX_SIZE_SCALE: f32 = 2.;
+const MIN_SIZE_SCALE: f32 = 0.25;
+/// input diffusers per channel, (ms, different for left and right)
+const DIFFUSER_MS: [[f32; 2]; 4] = [[4.77, 5.13], [3.59, 3.31], [12.73, 11.87], [9.31, 8.79]];
+const DIFFUSION: f32 = 0.6;
+const MAX_PRE_DELAY_MS: f32 = 200.;
+
+fn ms_to_len(ms: f32, sample_rate: f32) -> usize {
+    (ms / 1000. * sample_rate).round().max(1.) as usize
+}
+
+fn max_len(ms: f32) -> usize {
+    ms_to_len(ms, MAX_SAMPLE_RATE as f32) + 1
+}
+
+pub struct ReverbParams {
+    pub size: Param,
+    pub decay: Param,
+    pub pre_delay: Param,
+    pub damping: Param,
+    pub width: Param,
+}
+
+impl ParamSet for ReverbParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![
+            &self.size,
+            &self.decay,
+            &self.pre_delay,
+            &self.damping,
+            &self.width,
+        ]
+    }
+}
+
+/// Feedback delay network reverb.
+///
+/// Eight delay lines are mixed through a householder matrix, with the gain of each line
+/// chosen so that the tail falls 60dB over the decay time.
+pub struct Reverb {
+    params: Arc<ReverbParams>,
+    pre_delay: [DelayLine; 2],
+    diffusers: [[DelayLine; 2]; 4],
+    lines: [DelayLine; NUM_LINES],
+    damp_state: [f32; NUM_LINES],
+}
+
+impl Reverb {
+    pub fn new() -> Self {
+        let line = |ms| DelayLine::new(max_len(ms));
+        Self {
+            params: Arc::new(ReverbParams {
+                size: Param::new("size", "", 0.5, 0f32..=1f32),
+                decay: Param::new("decay", "s", 2., 0.1f32..=20f32).logarithmic(),
+                pre_delay: Param::new("pre-delay", "ms", 10., 0f32..=MAX_PRE_DELAY_MS),
+                damping: Param::new("damping", "", 0.3, 0f32..=1f32),
+                width: Param::new("width", "", 1., 0f32..=1f32),
+            }),
+            pre_delay: [line(MAX_PRE_DELAY_MS), line(MAX_PRE_DELAY_MS)],
+            diffusers: [
+                [line(DIFFUSER_MS[0][0]), line(DIFFUSER_MS[0][1])],
+                [line(DIFFUSER_MS[1][0]), line(DIFFUSER_MS[1][1])],
+                [line(DIFFUSER_MS[2][0]), line(DIFFUSER_MS[2][1])],
+                [line(DIFFUSER_MS[3][0]), line(DIFFUSER_MS[3][1])],
+            ],
+            lines: [
+                line(LINE_MS[0] * MAX_SIZE_SCALE),
+                line(LINE_MS[1] * MAX_SIZE_SCALE),
+                line(LINE_MS[2] * MAX_SIZE_SCALE),
+                line(LINE_MS[3] * MAX_SIZE_SCALE),
+                line(LINE_MS[4] * MAX_SIZE_SCALE),
+                line(LINE_MS[5] * MAX_SIZE_SCALE),
+                line(LINE_MS[6] * MAX_SIZE_SCALE),
+                line(LINE_MS[7] * MAX_SIZE_SCALE),
+            ],
+            damp_state: [0.; NUM_LINES],
+        }
+    }
+}
+
+impl Effect for Reverb {
+    fn process(&mut self, sample_rate: u32, data: &mut [f32]) {
+        let sample_rate = sample_rate as f32;
+        let params = &self.params;
+        let scale = MIN_SIZE_SCALE + (MAX_SIZE_SCALE - MIN_SIZE_SCALE) * params.size.get();
+        let decay = params.decay.get();
+        let mut lens = [0usize; NUM_LINES];
+        let mut gains = [0f32; NUM_LINES];
+        for i in 0..NUM_LINES {
+            lens[i] = ms_to_len(LINE_MS[i] * scale, sample_rate);
+            // -60dB after `decay` seconds
+            gains[i] = 10f32.powf(-3. * lens[i] as f32 / (decay * sample_rate));
+        }
+        let mut diffuser_lens = [[0usize; 2]; 4];
+        for (lens, ms) in diffuser_lens.iter_mut().zip(DIFFUSER_MS.iter()) {
+            lens[0] = ms_to_len(ms[0], sample_rate);
+            lens[1] = ms_to_len(ms[1], sample_rate);
+        }
+        let pre_delay_len = (params.pre_delay.get() / 1000. * sample_rate).round() as usize;
+        let damping = params.damping.get() * 0.9;
+        let width = params.width.get();
+
+        for frame in data.chunks_exact_mut(2) {
+            let mut input = [0f32; 2];
+            for channel in 0..2 {
+                let pre_delay = &mut self.pre_delay[channel];
+                let mut value = if pre_delay_len > 0 {
+                    pre_delay.read(pre_delay_len)
+                } else {
+                    frame[channel]
+                };
+                pre_delay.write(frame[channel]);
+                for (diffuser, lens) in self.diffusers.iter_mut().zip(diffuser_lens.iter()) {
+                    // schroeder allpass
+                    let line = &mut diffuser[channel];
+                    let delayed = line.read(lens[channel]);
+                    let feed = value + delayed * DIFFUSION;
+                    line.write(feed);
+                    value = delayed - feed * DIFFUSION;
+                }
+                input[channel] = value;
+            }
+
+            let mut outs = [0f32; NUM_LINES];
+            for i in 0..NUM_LINES {
+                let value = self.lines[i].read(lens[i]) * gains[i];
+                self.damp_state[i] = value * (1. - damping) + self.damp_state[i] * damping;
+                outs[i] = self.damp_state[i];
+            }
+            // householder reflection, lossless so the decay is entirely determined by the gains
+            let sum: f32 = outs.iter().sum::<f32>() * (2. / NUM_LINES as f32);
+            for i in 0..NUM_LINES {
+                self.lines[i].write(outs[i] - sum + input[i % 2]);
+            }
+
+            let mut left = 0.;
+            let mut right = 0.;
+            for (i, out) in outs.iter().enumerate() {
+                // alternate signs so the channels decorrelate
+                let sign = if i % 4 < 2 { 1. } else { -1. };
+                if i % 2 == 0 {
+                    left += out * sign;
+                } else {
+                    right += out * sign;
+                }
+            }
+            left *= 0.5;
+            right *= 0.5;
+            let mid = (left + right) * 0.5;
+            let side = (left - right) * 0.5 * width;
+            frame[0] = mid + side;
+            frame[1] = mid - side;
+        }
+    }
+
+    fn reset(&mut self) {
+        for line in self
+            .pre_delay
+            .iter_mut()
+            .chain(self.diffusers.iter_mut().flatten())
+            .chain(self.lines.iter_mut())
+        {
+            line.clear();
+        }
+        self.damp_state = [0.; NUM_LINES];
+    }
+
+    fn params(&self) -> Arc<dyn ParamSet> {
+        self.params.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Reverb;
+    use crate::effects::Effect;
+
+    /// Decay time estimated from the impulse response using schroeder backward integration.
+    /// Fits the -5 to -35dB part of the energy decay curve, like T30.
+    fn measure_decay(response: &[f32], sample_rate: f32) -> f32 {
+        let mut energy: Vec<f32> = response.iter().map(|v| v * v).collect();
+        for i in (0..energy.len() - 1).rev() {
+            energy[i] += energy[i + 1];
+        }
+        let total = energy[0];
+        let db = |i: usize| 10. * (energy[i] / total).log10();
+        let start = (0..energy.len()).find(|&i| db(i) <= -5.).unwrap();
+        let end = (0..energy.len()).find(|&i| db(i) <= -35.).unwrap();
+        (end - start) as f32 / sample_rate * 2.
+    }
+
+    #[test]
+    fn decay_time() {
+        let sample_rate = 48000;
+        for &decay in &[0.5f32, 1.5, 4.] {
+            let mut reverb = Reverb::new();
+            reverb.params.decay.set(decay);
+            reverb.params.damping.set(0.);
+            reverb.params.pre_delay.set(0.);
+            let mut data = vec![0f32; (decay * 2.) as usize * sample_rate as usize * 2];
+            data[0] = 1.;
+            data[1] = 1.;
+            reverb.process(sample_rate, &mut data);
+            let left: Vec<f32> = data.iter().step_by(2).copied().collect();
+            let measured = measure_decay(&left, sample_rate as f32);
+            assert!(
+                (measured - decay).abs() < decay * 0.1,
+                "expected {} got {}",
+                decay,
+                measured
+            );
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::f32::consts::PI;
+
+/// In place radix 2 fft. The length must be a power of two.
+pub fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    assert!(n.is_power_of_two() && im.len() == n);
+    // bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2. * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                // computing the twiddle directly is slower but avoids accumulating rounding errors
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let a = start + k;
+                let b = a + len / 2;
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Hann window of the given length.
+pub fn hann(len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| 0.5 - 0.5 * (2. * PI * i as f32 / len as f32).cos())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::fft;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn matches_dft() {
+        let n = 64;
+        let input: Vec<f32> = (0..n)
+            .map(|i| (i as f32 * 0.3).sin() + 0.5 * (i as f32 * 1.7).cos() + 0.1)
+            .collect();
+        let mut re = input.clone();
+        let mut im = vec![0.; n];
+        fft(&mut re, &mut im);
+        for k in 0..n {
+            let (mut dft_re, mut dft_im) = (0., 0.);
+            for (i, x) in input.iter().enumerate() {
+                let angle = -2. * PI * (k * i) as f32 / n as f32;
+                dft_re += x * angle.cos();
+                dft_im += x * angle.sin();
+            }
+            assert!((re[k] - dft_re).abs() < 1e-3, "{} {} {}", k, re[k], dft_re);
+            assert!((im[k] - dft_im).abs() < 1e-3, "{} {} {}", k, im[k], dft_im);
+        }
+    }
+}
@@ -1,66 +1,650 @@
-use crossbeam::channel;
-use eframe::egui;
+use crate::monitor::MidiSender;
+use crate::synth::{ActiveNotes, NoteState};
+use eframe::egui::{
+    self, Color32, Event, Key as KeyCode, Pos2, Rect, Sense, Stroke, TouchDeviceId, TouchId,
+    TouchPhase,
+};
 use log::warn;
-use std::{collections::HashSet, convert::TryFrom};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    sync::Arc,
+};
 use wmidi::MidiMessage;
 
+const WHITE_KEY_MIN_WIDTH: f32 = 24.;
+const KEY_HEIGHT: f32 = 80.;
+/// relative to the white keys
+const BLACK_KEY_WIDTH: f32 = 0.6;
+const BLACK_KEY_HEIGHT: f32 = 0.6;
+const MAX_OCTAVE_SHIFT: i8 = 4;
+const MIDDLE_C: u8 = 60;
+const VELOCITY_STEP: u8 = 16;
+/// tracker style computer keyboard layout with semitone offsets,
+/// the bottom row starts at the current octave and the top row an octave up
+const QWERTY_NOTES: [(KeyCode, u8); 29] = [
+    (KeyCode::Z, 0),
+    (KeyCode::S, 1),
+    (KeyCode::X, 2),
+    (KeyCode::D, 3),
+    (KeyCode::C, 4),
+    (KeyCode::V, 5),
+    (KeyCode::G, 6),
+    (KeyCode::B, 7),
+    (KeyCode::H, 8),
+    (KeyCode::N, 9),
+    (KeyCode::J, 10),
+    (KeyCode::M, 11),
+    (KeyCode::Q, 12),
+    (KeyCode::Num2, 13),
+    (KeyCode::W, 14),
+    (KeyCode::Num3, 15),
+    (KeyCode::E, 16),
+    (KeyCode::R, 17),
+    (KeyCode::Num5, 18),
+    (KeyCode::T, 19),
+    (KeyCode::Num6, 20),
+    (KeyCode::Y, 21),
+    (KeyCode::Num7, 22),
+    (KeyCode::U, 23),
+    (KeyCode::I, 24),
+    (KeyCode::Num9, 25),
+    (KeyCode::O, 26),
+    (KeyCode::Num0, 27),
+    (KeyCode::P, 28),
+];
+const PLAYING_COLOR: Color32 = Color32::from_rgb(60, 140, 255);
+const SUSTAINED_COLOR: Color32 = Color32::from_rgb(240, 170, 60);
+/// total height including the controls, for placing the keyboard at the bottom
+pub const HEIGHT: f32 = KEY_HEIGHT + 30.;
+
 fn is_key_black(note: wmidi::Note) -> bool {
     [
         false, true, false, true, false, false, true, false, true, false, true, false,
     ][(u8::from(note) % 12) as usize]
 }
 
+/// What sliding up and down on a held key does.
+#[derive(Clone, Copy, PartialEq)]
+enum SlideMode {
+    Off,
+    PitchBend,
+    Aftertouch,
+}
+
+impl SlideMode {
+    const ALL: [SlideMode; 3] = [SlideMode::Off, SlideMode::PitchBend, SlideMode::Aftertouch];
+
+    fn name(self) -> &'static str {
+        match self {
+            SlideMode::Off => "off",
+            SlideMode::PitchBend => "pitch bend",
+            SlideMode::Aftertouch => "aftertouch",
+        }
+    }
+}
+
+const PITCH_BEND_CENTER: u16 = 0x2000;
+
+fn pitch_bend(value: u16) -> wmidi::U14 {
+    wmidi::U14::try_from(value.min(u16::from(wmidi::U14::MAX))).unwrap_or_default()
+}
+
+type Touch = (TouchDeviceId, TouchId);
+
+/// A key held with the mouse or a finger.
+struct Press {
+    channel: wmidi::Channel,
+    note: wmidi::Note,
+    velocity: wmidi::Velocity,
+    /// where the press started, sliding is measured from here
+    start_y: f32,
+    /// the last pitch bend or aftertouch value sent
+    slide: Option<u16>,
+    /// counts up with each press, the latest touch is the one that bends
+    order: u64,
+}
+
+struct Key {
+    note: wmidi::Note,
+    rect: Rect,
+    black: bool,
+}
+
+/// Piano layout filling `rect`, starting from `first`, which should be a white key.
+fn layout(rect: Rect, first: u8) -> Vec<Key> {
+    let num_white = ((rect.width() / WHITE_KEY_MIN_WIDTH) as usize).max(7);
+    let white_width = rect.width() / num_white as f32;
+    let mut keys = vec![];
+    let mut white_index = 0;
+    for note_num in first.. {
+        if white_index == num_white {
+            break;
+        }
+        let note = match wmidi::Note::try_from(note_num) {
+            Ok(note) => note,
+            Err(_) => break,
+        };
+        if is_key_black(note) {
+            // straddles the edge between the previous white key and the next
+            let center = rect.left() + white_index as f32 * white_width;
+            let width = white_width * BLACK_KEY_WIDTH;
+            keys.push(Key {
+                note,
+                rect: Rect::from_min_max(
+                    Pos2::new(center - width / 2., rect.top()),
+                    Pos2::new(
+                        center + width / 2.,
+                        rect.top() + rect.height() * BLACK_KEY_HEIGHT,
+                    ),
+                ),
+                black: true,
+            });
+        } else {
+            let left = rect.left() + white_index as f32 * white_width;
+            keys.push(Key {
+                note,
+                rect: Rect::from_min_max(
+                    Pos2::new(left, rect.top()),
+                    Pos2::new(left + white_width, rect.bottom()),
+                ),
+                black: false,
+            });
+            white_index += 1;
+        }
+    }
+    keys
+}
+
+/// The key under `pos`, black keys are on top.
+fn key_at(keys: &[Key], pos: Pos2) -> Option<&Key> {
+    keys.iter()
+        .filter(|key| key.black)
+        .chain(keys.iter().filter(|key| !key.black))
+        .find(|key| key.rect.contains(pos))
+}
+
 pub struct OnScreenKeyboard {
-    key_pressed: HashSet<egui::Id>,
-    midi_tx: channel::Sender<MidiMessage<'static>>,
+    octave: i8,
+    channel: wmidi::Channel,
+    /// the note held with the pointer
+    held: Option<Press>,
+    /// each finger on a touch screen plays its own note
+    touches: BTreeMap<Touch, Press>,
+    presses: u64,
+    slide_mode: SlideMode,
+    /// notes held on the computer keyboard, remembered so octave changes don't leave them hanging
+    key_notes: HashMap<KeyCode, (wmidi::Channel, wmidi::Note)>,
+    key_velocity: u8,
+    midi_tx: MidiSender,
+    /// notes sounding in the synth, whatever played them
+    active_notes: Arc<ActiveNotes>,
+}
+
+/// Tints a key towards the highlight color, more for louder notes.
+fn key_fill(base: Color32, state: Option<NoteState>) -> Color32 {
+    let state = match state {
+        Some(state) => state,
+        None => return base,
+    };
+    let color = if state.sustained {
+        SUSTAINED_COLOR
+    } else {
+        PLAYING_COLOR
+    };
+    // quiet notes still need to stand out
+    let amount = 0.3 + 0.7 * state.velocity as f32 / 127.;
+    let mix = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * amount) as u8;
+    Color32::from_rgb(
+        mix(base.r(), color.r()),
+        mix(base.g(), color.g()),
+        mix(base.b(), color.b()),
+    )
 }
 
 impl OnScreenKeyboard {
-    pub fn new(midi_tx: channel::Sender<MidiMessage<'static>>) -> Self {
+    pub fn new(midi_tx: MidiSender, active_notes: Arc<ActiveNotes>) -> Self {
         Self {
-            key_pressed: HashSet::new(),
+            octave: 0,
+            channel: wmidi::Channel::Ch1,
+            held: None,
+            touches: BTreeMap::new(),
+            presses: 0,
+            slide_mode: SlideMode::PitchBend,
+            key_notes: HashMap::new(),
+            key_velocity: 100,
             midi_tx,
+            active_notes,
+        }
+    }
+
+    fn send(&self, message: MidiMessage<'static>) {
+        if let Err(e) = self.midi_tx.try_send(message) {
+            warn!("error sending midi message from keyboard {}", e);
+        }
+    }
+
+    fn press(&mut self, key: &Key, pos: Pos2, force: f32) -> Press {
+        // use the pressure if the screen has it, otherwise further down the key is louder,
+        // like hitting it closer to the front
+        let amount = if force > 0. {
+            force
+        } else {
+            (pos.y - key.rect.top()) / key.rect.height()
+        };
+        let velocity = wmidi::Velocity::from_u8_lossy((1. + amount.clamp(0., 1.) * 126.) as u8);
+        self.send(MidiMessage::NoteOn(self.channel, key.note, velocity));
+        self.presses += 1;
+        Press {
+            channel: self.channel,
+            note: key.note,
+            velocity,
+            start_y: pos.y,
+            slide: None,
+            order: self.presses,
+        }
+    }
+
+    /// Follows a held press to `pos`, gliding onto other keys,
+    /// and sliding vertically unless `vertical` is false.
+    fn slide(&self, press: &mut Press, keys: &[Key], pos: Pos2, vertical: bool) {
+        if let Some(key) = key_at(keys, pos) {
+            if key.note != press.note {
+                self.send(MidiMessage::NoteOff(
+                    press.channel,
+                    press.note,
+                    wmidi::Velocity::from_u8_lossy(0),
+                ));
+                self.send(MidiMessage::NoteOn(press.channel, key.note, press.velocity));
+                press.note = key.note;
+                // the new note hasn't had any aftertouch yet
+                if self.slide_mode == SlideMode::Aftertouch {
+                    press.slide = None;
+                }
+            }
+        }
+        if !vertical {
+            return;
+        }
+        // a key's height up or down is the full range
+        let amount = ((press.start_y - pos.y) / KEY_HEIGHT).clamp(-1., 1.);
+        let (value, resting) = match self.slide_mode {
+            SlideMode::Off => return,
+            SlideMode::PitchBend => (
+                (PITCH_BEND_CENTER as f32 * (1. + amount)) as u16,
+                PITCH_BEND_CENTER,
+            ),
+            SlideMode::Aftertouch => ((amount.max(0.) * 127.) as u16, 0),
+        };
+        // nothing to send until it moves
+        if press.slide.unwrap_or(resting) == value {
+            return;
+        }
+        press.slide = Some(value);
+        self.send(match self.slide_mode {
+            SlideMode::Aftertouch => MidiMessage::PolyphonicKeyPressure(
+                press.channel,
+                press.note,
+                wmidi::U7::from_u8_lossy(value as u8),
+            ),
+            _ => MidiMessage::PitchBendChange(press.channel, pitch_bend(value)),
+        });
+    }
+
+    fn lift(&self, press: Press) {
+        self.send(MidiMessage::NoteOff(
+            press.channel,
+            press.note,
+            wmidi::Velocity::from_u8_lossy(0),
+        ));
+        // leave the pitch where it was found for the next note
+        if self.slide_mode == SlideMode::PitchBend
+            && matches!(press.slide, Some(value) if value != PITCH_BEND_CENTER)
+        {
+            self.send(MidiMessage::PitchBendChange(
+                press.channel,
+                pitch_bend(PITCH_BEND_CENTER),
+            ));
+        }
+    }
+
+    fn release(&mut self) {
+        if let Some(press) = self.held.take() {
+            self.lift(press);
+        }
+    }
+
+    fn release_touches(&mut self) {
+        let touches = std::mem::take(&mut self.touches);
+        for (_, press) in touches {
+            self.lift(press);
+        }
+    }
+
+    /// Plays a note for each finger on the keyboard, returns whether there were any touches.
+    ///
+    /// Touches are also reported as pointer events, which have to be ignored while this handles them.
+    fn handle_touches(&mut self, ui: &egui::Ui, rect: Rect, keys: &[Key]) -> bool {
+        let events = ui.input().events.clone();
+        let mut touched = false;
+        for event in events {
+            if let Event::Touch {
+                device_id,
+                id,
+                phase,
+                pos,
+                force,
+            } = event
+            {
+                touched = true;
+                let touch = (device_id, id);
+                match phase {
+                    TouchPhase::Start if rect.contains(pos) => {
+                        self.touch_start(touch, keys, pos, force)
+                    }
+                    TouchPhase::Start => {}
+                    TouchPhase::Move => self.touch_move(touch, keys, pos),
+                    TouchPhase::End | TouchPhase::Cancel => self.touch_end(touch),
+                }
+            }
+        }
+        touched || !self.touches.is_empty()
+    }
+
+    fn touch_start(&mut self, touch: Touch, keys: &[Key], pos: Pos2, force: f32) {
+        let key = match key_at(keys, pos) {
+            Some(key) => key,
+            None => return,
+        };
+        // pitch bend is for the whole channel, so only the latest finger bends
+        // and the new note starts out in tune
+        if self.slide_mode == SlideMode::PitchBend {
+            let bent: Vec<_> = self
+                .touches
+                .values_mut()
+                .filter_map(|other| match other.slide.take() {
+                    Some(value) if value != PITCH_BEND_CENTER => Some(other.channel),
+                    _ => None,
+                })
+                .collect();
+            for channel in bent {
+                self.send(MidiMessage::PitchBendChange(
+                    channel,
+                    pitch_bend(PITCH_BEND_CENTER),
+                ));
+            }
+        }
+        let press = self.press(key, pos, force);
+        if let Some(old) = self.touches.insert(touch, press) {
+            self.lift(old);
+        }
+    }
+
+    fn touch_move(&mut self, touch: Touch, keys: &[Key], pos: Pos2) {
+        if let Some(mut press) = self.touches.remove(&touch) {
+            let latest = self.touches.values().all(|other| other.order < press.order);
+            let vertical = latest || self.slide_mode != SlideMode::PitchBend;
+            self.slide(&mut press, keys, pos, vertical);
+            self.touches.insert(touch, press);
         }
     }
 
+    fn touch_end(&mut self, touch: Touch) {
+        if let Some(press) = self.touches.remove(&touch) {
+            self.lift(press);
+            // the bend was reset, the finger that takes over needs to send its own again
+            for other in self.touches.values_mut() {
+                other.slide = None;
+            }
+        }
+    }
+
+    fn release_keys(&mut self) {
+        let held: Vec<_> = self.key_notes.drain().map(|(_, held)| held).collect();
+        for (channel, note) in held {
+            self.send(MidiMessage::NoteOff(
+                channel,
+                note,
+                wmidi::Velocity::from_u8_lossy(0),
+            ));
+        }
+    }
+
+    /// Plays notes from the computer keyboard.
+    ///
+    /// The window losing focus isn't reported, so all keys are released when the pointer leaves
+    /// the window instead, as well as when some other widget takes keyboard input.
+    fn handle_keys(&mut self, ui: &egui::Ui) {
+        if ui.ctx().wants_keyboard_input() {
+            self.release_keys();
+            return;
+        }
+        let events = ui.input().events.clone();
+        for event in events {
+            match event {
+                Event::Key {
+                    key,
+                    pressed,
+                    modifiers,
+                } => {
+                    // leave shortcuts alone
+                    if modifiers.ctrl || modifiers.alt || modifiers.command {
+                        continue;
+                    }
+                    match key {
+                        KeyCode::ArrowLeft if pressed => {
+                            self.octave = (self.octave - 1).max(-MAX_OCTAVE_SHIFT)
+                        }
+                        KeyCode::ArrowRight if pressed => {
+                            self.octave = (self.octave + 1).min(MAX_OCTAVE_SHIFT)
+                        }
+                        KeyCode::ArrowDown if pressed => {
+                            self.key_velocity =
+                                self.key_velocity.saturating_sub(VELOCITY_STEP).max(1)
+                        }
+                        KeyCode::ArrowUp if pressed => {
+                            self.key_velocity = (self.key_velocity + VELOCITY_STEP).min(127)
+                        }
+                        // panic button for anything stuck
+                        KeyCode::Escape if pressed => self.release_all(),
+                        _ => {
+                            let offset = match QWERTY_NOTES.iter().find(|(k, _)| *k == key) {
+                                Some((_, offset)) => *offset,
+                                None => continue,
+                            };
+                            if pressed {
+                                // held keys repeat, only the first press counts
+                                if self.key_notes.contains_key(&key) {
+                                    continue;
+                                }
+                                let note_num =
+                                    MIDDLE_C as i16 + self.octave as i16 * 12 + offset as i16;
+                                if let Ok(note) = wmidi::Note::try_from(note_num as u8) {
+                                    self.key_notes.insert(key, (self.channel, note));
+                                    self.send(MidiMessage::NoteOn(
+                                        self.channel,
+                                        note,
+                                        wmidi::Velocity::from_u8_lossy(self.key_velocity),
+                                    ));
+                                }
+                            } else if let Some((channel, note)) = self.key_notes.remove(&key) {
+                                self.send(MidiMessage::NoteOff(
+                                    channel,
+                                    note,
+                                    wmidi::Velocity::from_u8_lossy(0),
+                                ));
+                            }
+                        }
+                    }
+                }
+                Event::PointerGone => self.release_keys(),
+                _ => {}
+            }
+        }
+    }
+
+    /// Stops everything played from the ui.
+    pub fn release_all(&mut self) {
+        self.release_keys();
+        self.release();
+        self.release_touches();
+    }
+
     pub fn show(&mut self, ui: &mut egui::Ui) {
+        self.handle_keys(ui);
         ui.horizontal(|ui| {
-            // start from middle c
-            for note_num in 60.. {
-                if ui.available_width() <= 0f32 {
-                    break;
-                }
-                let note = match wmidi::Note::try_from(note_num) {
-                    Ok(note) => note,
-                    Err(_) => break,
-                };
-                let b = egui::Button::new(" ").fill(if is_key_black(note) {
-                    egui::Color32::BLACK
-                } else {
-                    egui::Color32::WHITE
+            ui.label("octave:");
+            if ui.small_button("-").clicked() {
+                self.octave = (self.octave - 1).max(-MAX_OCTAVE_SHIFT);
+            }
+            ui.label(format!("C{}", 4 + self.octave));
+            if ui.small_button("+").clicked() {
+                self.octave = (self.octave + 1).min(MAX_OCTAVE_SHIFT);
+            }
+            ui.label("channel:");
+            let mut channel_index = self.channel.index() as usize;
+            egui::ComboBox::from_id_source("keyboard channel combo box").show_index(
+                ui,
+                &mut channel_index,
+                16,
+                |i| (i + 1).to_string(),
+            );
+            if let Ok(channel) = wmidi::Channel::from_index(channel_index as u8) {
+                self.channel = channel;
+            }
+            ui.label(format!("key velocity: {}", self.key_velocity));
+            ui.label("slide:");
+            egui::ComboBox::from_id_source("keyboard slide combo box")
+                .selected_text(self.slide_mode.name())
+                .show_ui(ui, |ui| {
+                    for mode in SlideMode::ALL.iter() {
+                        ui.selectable_value(&mut self.slide_mode, *mode, mode.name());
+                    }
                 });
-                let r = ui.add(b);
-                // egui doesn't seem to have any convenient "pressed" or "released" event
-                if r.is_pointer_button_down_on() {
-                    if self.key_pressed.insert(r.id) {
-                        if let Err(e) = self.midi_tx.try_send(MidiMessage::NoteOn(
-                            wmidi::Channel::Ch1,
-                            note,
-                            wmidi::Velocity::from_u8_lossy(127),
-                        )) {
-                            warn!("error sending note on midi message {}", e);
-                        }
+        });
+
+        let (rect, response) = ui.allocate_exact_size(
+            egui::vec2(ui.available_width(), KEY_HEIGHT),
+            Sense::click_and_drag(),
+        );
+        let first = (MIDDLE_C as i16 + self.octave as i16 * 12) as u8;
+        let keys = layout(rect, first);
+
+        // egui doesn't seem to have any convenient "pressed" or "released" event
+        if self.handle_touches(ui, rect, &keys) {
+            self.release();
+        } else if response.is_pointer_button_down_on() {
+            if let Some(pos) = response.interact_pointer_pos() {
+                match self.held.take() {
+                    Some(mut press) => {
+                        self.slide(&mut press, &keys, pos, true);
+                        self.held = Some(press);
                     }
-                } else if self.key_pressed.remove(&r.id) {
-                    if let Err(e) = self.midi_tx.try_send(MidiMessage::NoteOff(
-                        wmidi::Channel::Ch1,
-                        note,
-                        wmidi::Velocity::from_u8_lossy(0),
-                    )) {
-                        warn!("error sending midi note off message {}", e);
+                    None => {
+                        if let Some(key) = key_at(&keys, pos) {
+                            self.held = Some(self.press(key, pos, 0.));
+                        }
                     }
                 }
             }
-        });
+        } else {
+            self.release();
+        }
+
+        let painter = ui.painter();
+        let outline = Stroke::new(1., Color32::from_gray(80));
+        let state = |note| {
+            // notes held here show straight away, before the synth has seen them
+            let pressed = self
+                .held
+                .iter()
+                .chain(self.touches.values())
+                .find(|press| press.note == note)
+                .map(|press| u8::from(press.velocity));
+            let keyed = if self.key_notes.values().any(|(_, n)| *n == note) {
+                Some(self.key_velocity)
+            } else {
+                None
+            };
+            let local = pressed.or(keyed).map(|velocity| NoteState {
+                velocity,
+                sustained: false,
+            });
+            self.active_notes.get(note).or(local)
+        };
+        for key in keys.iter().filter(|key| !key.black) {
+            painter.rect(
+                key.rect,
+                0.,
+                key_fill(Color32::WHITE, state(key.note)),
+                outline,
+            );
+        }
+        for key in keys.iter().filter(|key| key.black) {
+            painter.rect(
+                key.rect,
+                0.,
+                key_fill(Color32::BLACK, state(key.note)),
+                outline,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{layout, OnScreenKeyboard, SlideMode, KEY_HEIGHT, WHITE_KEY_MIN_WIDTH};
+    use crate::monitor::MidiMonitor;
+    use crate::synth::Synth;
+    use crossbeam::channel;
+    use eframe::egui::{Pos2, Rect, TouchDeviceId, TouchId};
+    use wmidi::MidiMessage;
+
+    #[test]
+    fn latest_touch_bends() {
+        let (tx, rx) = channel::unbounded();
+        let (_synth_tx, synth_rx) = channel::bounded(1);
+        let monitor = MidiMonitor::new();
+        let mut keyboard = OnScreenKeyboard::new(
+            monitor.sender(tx, "test"),
+            Synth::new(synth_rx).get_active_notes(),
+        );
+        let rect = Rect::from_min_size(Pos2::ZERO, (WHITE_KEY_MIN_WIDTH * 7., KEY_HEIGHT).into());
+        let keys = layout(rect, 60);
+        let bends = |rx: &channel::Receiver<MidiMessage<'static>>| -> Vec<u16> {
+            rx.try_iter()
+                .filter_map(|message| match message {
+                    MidiMessage::PitchBendChange(_, bend) => Some(bend.into()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let first = (TouchDeviceId(0), TouchId(0));
+        let second = (TouchDeviceId(0), TouchId(1));
+        // the lower half of the white keys, clear of the black ones
+        let low = KEY_HEIGHT * 0.9;
+
+        keyboard.touch_start(first, &keys, Pos2::new(10., low), 0.);
+        keyboard.touch_move(first, &keys, Pos2::new(10., low - KEY_HEIGHT / 2.));
+        assert_eq!(vec![0x3000], bends(&rx));
+
+        // a new finger plays in tune and takes over the bend from the first
+        keyboard.touch_start(second, &keys, Pos2::new(60., low), 0.);
+        assert_eq!(vec![0x2000], bends(&rx));
+        keyboard.touch_move(first, &keys, Pos2::new(10., low - KEY_HEIGHT));
+        assert_eq!(Vec::<u16>::new(), bends(&rx));
+        keyboard.touch_move(second, &keys, Pos2::new(60., low + KEY_HEIGHT / 4.));
+        assert_eq!(vec![0x1800], bends(&rx));
+        keyboard.touch_end(second);
+        assert_eq!(vec![0x2000], bends(&rx));
+
+        // aftertouch is per note, so any finger can send it
+        keyboard.slide_mode = SlideMode::Aftertouch;
+        keyboard.touch_start(second, &keys, Pos2::new(60., low), 0.);
+        keyboard.touch_move(first, &keys, Pos2::new(10., low - KEY_HEIGHT / 2.));
+        assert!(rx.try_iter().any(|message| matches!(
+            message,
+            MidiMessage::PolyphonicKeyPressure(_, wmidi::Note::C4, pressure) if u8::from(pressure) == 63
+        )));
     }
 }
@@ -7,12 +7,31 @@ if #[cfg(target_arch = "wasm32")] {
 use log::{warn, Level, Metadata, Record};
 use web_sys::console;
 
+mod arpeggiator;
 mod audio;
+mod effects;
+mod fft;
 mod keyboard;
+mod meter;
+mod metronome;
 mod midi;
+mod midi_map;
+mod mixer;
+mod monitor;
+mod param;
+mod patch;
 mod synth;
 mod periodic_updater;
+mod presets;
+mod program_loader;
+mod rack;
+mod scope;
+mod sequencer;
+mod spectrogram;
+mod spectrum;
     mod timer;
+mod transport;
+mod zones;
 
 mod app;
 pub use app::Wayfarer;
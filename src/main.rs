@@ -1,11 +1,30 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
+mod arpeggiator;
 mod audio;
+mod effects;
+mod fft;
 mod keyboard;
+mod meter;
+mod metronome;
 mod midi;
+mod midi_map;
+mod mixer;
+mod monitor;
+mod param;
+mod patch;
 mod periodic_updater;
+mod presets;
+mod program_loader;
+mod rack;
+mod scope;
+mod sequencer;
+mod spectrogram;
+mod spectrum;
 mod synth;
 mod timer;
+mod transport;
+mod zones;
 
 mod app;
 use app::Wayfarer;
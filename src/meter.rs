@@ -0,0 +1,309 @@
+use crossbeam::atomic::AtomicCell;
+use std::{f64::consts::PI, sync::Arc};
+
+const PEAK_FALL_DB_PER_SECOND: f32 = 20.;
+const RMS_MS: f32 = 300.;
+/// loudness is measured in 100ms steps, blocks are made from several of these
+const SUB_BLOCK_MS: u32 = 100;
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+const ABSOLUTE_GATE_LUFS: f64 = -70.;
+const RELATIVE_GATE_LU: f64 = -10.;
+/// integrated loudness keeps a histogram of block loudness instead of every block,
+/// so it can run forever without allocating
+const HISTOGRAM_MAX_LUFS: f64 = 10.;
+const HISTOGRAM_STEP_LU: f64 = 0.1;
+const HISTOGRAM_BINS: usize =
+    ((HISTOGRAM_MAX_LUFS - ABSOLUTE_GATE_LUFS) / HISTOGRAM_STEP_LU) as usize;
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10. * energy.max(1e-20).log10()
+}
+
+/// Readings shared between the audio thread and the ui.
+pub struct Meters {
+    /// linear, falling slowly after each peak
+    pub peak: [AtomicCell<f32>; 2],
+    /// linear, over the last 300ms or so
+    pub rms: [AtomicCell<f32>; 2],
+    /// latched until reset
+    pub clip: [AtomicCell<bool>; 2],
+    /// LUFS over the last 3s
+    pub short_term: AtomicCell<f32>,
+    /// gated LUFS since the last reset
+    pub integrated: AtomicCell<f32>,
+    reset_requested: AtomicCell<bool>,
+}
+
+impl Meters {
+    pub fn new() -> Self {
+        Self {
+            peak: [AtomicCell::new(0.), AtomicCell::new(0.)],
+            rms: [AtomicCell::new(0.), AtomicCell::new(0.)],
+            clip: [AtomicCell::new(false), AtomicCell::new(false)],
+            short_term: AtomicCell::new(f32::NEG_INFINITY),
+            integrated: AtomicCell::new(f32::NEG_INFINITY),
+            reset_requested: AtomicCell::new(false),
+        }
+    }
+
+    /// Clears the clip indicators and starts a new integrated measurement.
+    pub fn reset(&self) {
+        for clip in self.clip.iter() {
+            clip.store(false);
+        }
+        self.reset_requested.store(true);
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The two stage k-weighting filter from ITU-R BS.1770, for any sample rate.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    // high shelf modelling the head
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain_db / 20.);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1. + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2. * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        ..Biquad::default()
+    };
+    // revised low frequency b-curve, a highpass
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1. + k / q + k * k;
+    let highpass = Biquad {
+        b: [1., -2., 1.],
+        a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        ..Biquad::default()
+    };
+    [shelf, highpass]
+}
+
+/// Measures the output in the audio callback and publishes the results to [`Meters`].
+pub struct LevelMeter {
+    meters: Arc<Meters>,
+    sample_rate: u32,
+    peak: [f32; 2],
+    mean_square: [f32; 2],
+    filters: [[Biquad; 2]; 2],
+    sub_block_energy: f64,
+    sub_block_frames: u32,
+    /// energy of the latest sub blocks
+    sub_blocks: [f64; SHORT_TERM_SUB_BLOCKS],
+    sub_block_pos: usize,
+    num_sub_blocks: usize,
+    histogram_counts: Vec<u32>,
+    histogram_energy: Vec<f64>,
+}
+
+impl LevelMeter {
+    /// Creates a meter publishing to existing readings,
+    /// for when the audio stream is recreated but the ui should keep its handle.
+    pub fn with_meters(meters: Arc<Meters>) -> Self {
+        Self {
+            meters,
+            sample_rate: 0,
+            peak: [0.; 2],
+            mean_square: [0.; 2],
+            filters: [[Biquad::default(); 2]; 2],
+            sub_block_energy: 0.,
+            sub_block_frames: 0,
+            sub_blocks: [0.; SHORT_TERM_SUB_BLOCKS],
+            sub_block_pos: 0,
+            num_sub_blocks: 0,
+            histogram_counts: vec![0; HISTOGRAM_BINS],
+            histogram_energy: vec![0.; HISTOGRAM_BINS],
+        }
+    }
+
+    fn reset(&mut self) {
+        let filters = k_weighting(self.sample_rate as f64);
+        self.filters = [filters, filters];
+        self.peak = [0.; 2];
+        self.mean_square = [0.; 2];
+        self.sub_block_energy = 0.;
+        self.sub_block_frames = 0;
+        self.num_sub_blocks = 0;
+        self.histogram_counts.fill(0);
+        self.histogram_energy.fill(0.);
+        self.meters.short_term.store(f32::NEG_INFINITY);
+        self.meters.integrated.store(f32::NEG_INFINITY);
+    }
+
+    /// Mean energy of the latest `count` sub blocks.
+    fn recent_energy(&self, count: usize) -> f64 {
+        let len = SHORT_TERM_SUB_BLOCKS;
+        (0..count)
+            .map(|i| self.sub_blocks[(self.sub_block_pos + len - 1 - i) % len])
+            .sum::<f64>()
+            / count as f64
+    }
+
+    fn finish_sub_block(&mut self) {
+        self.sub_blocks[self.sub_block_pos] = self.sub_block_energy / self.sub_block_frames as f64;
+        self.sub_block_pos = (self.sub_block_pos + 1) % SHORT_TERM_SUB_BLOCKS;
+        self.num_sub_blocks += 1;
+        self.sub_block_energy = 0.;
+        self.sub_block_frames = 0;
+
+        if self.num_sub_blocks >= SHORT_TERM_SUB_BLOCKS {
+            let energy = self.recent_energy(SHORT_TERM_SUB_BLOCKS);
+            self.meters.short_term.store(energy_to_lufs(energy) as f32);
+        }
+        if self.num_sub_blocks >= MOMENTARY_SUB_BLOCKS {
+            // 400ms gating blocks overlapping by 75%
+            let energy = self.recent_energy(MOMENTARY_SUB_BLOCKS);
+            let lufs = energy_to_lufs(energy);
+            if lufs > ABSOLUTE_GATE_LUFS {
+                let bin = (((lufs - ABSOLUTE_GATE_LUFS) / HISTOGRAM_STEP_LU) as usize)
+                    .min(HISTOGRAM_BINS - 1);
+                self.histogram_counts[bin] += 1;
+                self.histogram_energy[bin] += energy;
+            }
+            self.meters.integrated.store(self.integrated() as f32);
+        }
+    }
+
+    fn integrated(&self) -> f64 {
+        let gated_mean = |first_bin: usize| {
+            let count: u32 = self.histogram_counts[first_bin..].iter().sum();
+            let energy: f64 = self.histogram_energy[first_bin..].iter().sum();
+            if count == 0 {
+                None
+            } else {
+                Some(energy / count as f64)
+            }
+        };
+        let ungated = match gated_mean(0) {
+            Some(energy) => energy,
+            None => return f64::NEG_INFINITY,
+        };
+        let threshold = energy_to_lufs(ungated) + RELATIVE_GATE_LU;
+        let first_bin = ((threshold - ABSOLUTE_GATE_LUFS) / HISTOGRAM_STEP_LU)
+            .ceil()
+            .max(0.) as usize;
+        gated_mean(first_bin.min(HISTOGRAM_BINS - 1)).map_or(f64::NEG_INFINITY, energy_to_lufs)
+    }
+
+    /// Measures interleaved stereo frames.
+    pub fn process(&mut self, sample_rate: u32, data: &[f32]) {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.reset();
+        }
+        if self.meters.reset_requested.swap(false) {
+            self.reset();
+        }
+        let peak_fall = 10f32.powf(-PEAK_FALL_DB_PER_SECOND / 20. / sample_rate as f32);
+        let rms_coefficient = 1. - (-1000. / (RMS_MS * sample_rate as f32)).exp();
+        let sub_block_len = sample_rate * SUB_BLOCK_MS / 1000;
+        for frame in data.chunks_exact(2) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let level = sample.abs();
+                self.peak[channel] = level.max(self.peak[channel] * peak_fall);
+                if level >= 1. {
+                    self.meters.clip[channel].store(true);
+                }
+                self.mean_square[channel] +=
+                    (sample * sample - self.mean_square[channel]) * rms_coefficient;
+                let [shelf, highpass] = &mut self.filters[channel];
+                let weighted = highpass.process(shelf.process(sample as f64));
+                self.sub_block_energy += weighted * weighted;
+            }
+            self.sub_block_frames += 1;
+            if self.sub_block_frames >= sub_block_len {
+                self.finish_sub_block();
+            }
+        }
+        for channel in 0..2 {
+            self.meters.peak[channel].store(self.peak[channel]);
+            self.meters.rms[channel].store(self.mean_square[channel].sqrt());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LevelMeter, Meters};
+    use crate::effects::gain_to_db;
+    use std::{f32::consts::PI, sync::Arc};
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn measure(amplitude: [f32; 2], seconds: f32) -> Arc<Meters> {
+        let meters = Arc::new(Meters::new());
+        let mut meter = LevelMeter::with_meters(meters.clone());
+        let data: Vec<f32> = (0..(seconds * SAMPLE_RATE as f32) as usize)
+            .flat_map(|i| {
+                let value = (2. * PI * 1000. * i as f32 / SAMPLE_RATE as f32).sin();
+                vec![value * amplitude[0], value * amplitude[1]]
+            })
+            .collect();
+        for block in data.chunks(512) {
+            meter.process(SAMPLE_RATE, block);
+        }
+        meters
+    }
+
+    #[test]
+    fn peak_and_rms() {
+        let meters = measure([0.5, 0.1], 3.);
+        assert!((gain_to_db(meters.peak[0].load()) - -6.02).abs() < 0.05);
+        assert!((gain_to_db(meters.rms[0].load()) - -9.03).abs() < 0.1);
+        assert!((gain_to_db(meters.peak[1].load()) - -20.).abs() < 0.05);
+        assert!((gain_to_db(meters.rms[1].load()) - -23.01).abs() < 0.1);
+        assert!(!meters.clip[0].load());
+        assert!(measure([1., 0.], 0.1).clip[0].load());
+    }
+
+    #[test]
+    fn loudness() {
+        // a 1kHz sine in both channels reads the same in LUFS as its peak in dBFS
+        let meters = measure([0.1, 0.1], 5.);
+        assert!(
+            (meters.short_term.load() - -20.).abs() < 0.1,
+            "{}",
+            meters.short_term.load()
+        );
+        assert!(
+            (meters.integrated.load() - -20.).abs() < 0.1,
+            "{}",
+            meters.integrated.load()
+        );
+        // and 3dB less in one channel
+        let meters = measure([1., 0.], 5.);
+        assert!(
+            (meters.integrated.load() - -3.01).abs() < 0.1,
+            "{}",
+            meters.integrated.load()
+        );
+    }
+}
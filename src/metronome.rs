@@ -0,0 +1,206 @@
+use crate::effects::db_to_gain;
+use crate::param::{Param, ParamSet};
+use crate::synth::SynthPlayer;
+use crate::transport::TransportState;
+use std::{f32::consts::PI, sync::Arc};
+
+const CLICK_SECONDS: f32 = 0.03;
+/// time constant of the click's decay
+const DECAY_SECONDS: f32 = 0.006;
+const CLICK_HZ: f32 = 1000.;
+const ACCENT_HZ: f32 = 1500.;
+/// how loud the other beats are next to the downbeat
+const BEAT_GAIN: f32 = 0.5;
+
+pub struct MetronomeParams {
+    pub enabled: Param,
+    /// click through a count in, even when the metronome is off
+    pub count_in: Param,
+    pub volume: Param,
+}
+
+impl MetronomeParams {
+    fn new() -> Self {
+        Self {
+            enabled: Param::toggle("metronome", false),
+            count_in: Param::toggle("count in click", true),
+            volume: Param::new("volume", "dB", -12., -40f32..=0f32),
+        }
+    }
+}
+
+impl ParamSet for MetronomeParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![&self.enabled, &self.count_in, &self.volume]
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Click {
+    /// in samples
+    age: usize,
+    hz: f32,
+    gain: f32,
+}
+
+/// Mixes a click on each beat into the output of a `SynthPlayer`, higher and louder on the downbeats.
+///
+/// Can click through a count in even when turned off, so there is something to count along to.
+#[derive(Clone)]
+pub struct Metronome<T> {
+    source: T,
+    params: Arc<MetronomeParams>,
+    click: Option<Click>,
+}
+
+impl<T> Metronome<T> {
+    pub fn new(source: T) -> Self {
+        Self {
+            source,
+            params: Arc::new(MetronomeParams::new()),
+            click: None,
+        }
+    }
+
+    pub fn get_params(&self) -> Arc<MetronomeParams> {
+        self.params.clone()
+    }
+}
+
+impl<T> SynthPlayer for Metronome<T>
+where
+    T: SynthPlayer,
+{
+    fn play(&mut self, sample_rate: u32, channels: usize, output: &mut [f32]) {
+        self.source.play(sample_rate, channels, output);
+        let transport = self.source.transport();
+        let frames = output.len() / channels;
+        let audible = (transport.count_in && self.params.count_in.is_on())
+            || (transport.playing && self.params.enabled.is_on());
+        let beat = transport.beat_length();
+        let beats_per_bar = transport.steps_per_bar(1. / beat) as i64;
+        let mut next_beat = (transport.position / beat).ceil();
+        // can be 0 while waiting for the midi clock, in which case no beat comes up
+        let per_frame = (transport.end - transport.position) / frames as f64;
+        let beat_offset = |index: f64| {
+            let position = index * beat;
+            if audible && position < transport.end {
+                ((position - transport.position) / per_frame) as usize
+            } else {
+                usize::MAX
+            }
+        };
+        let mut next_offset = beat_offset(next_beat);
+
+        let sample_rate = sample_rate as f32;
+        let length = (CLICK_SECONDS * sample_rate) as usize;
+        let volume = db_to_gain(self.params.volume.get());
+        for (offset, frame) in output.chunks_exact_mut(channels).enumerate() {
+            if offset == next_offset {
+                // the count in can start before the song, where beats are negative
+                let beat_in_bar = (next_beat as i64).rem_euclid(beats_per_bar);
+                self.click = Some(if beat_in_bar == 0 {
+                    Click {
+                        age: 0,
+                        hz: ACCENT_HZ,
+                        gain: volume,
+                    }
+                } else {
+                    Click {
+                        age: 0,
+                        hz: CLICK_HZ,
+                        gain: volume * BEAT_GAIN,
+                    }
+                });
+                next_beat += 1.;
+                next_offset = beat_offset(next_beat);
+            }
+            if let Some(click) = &mut self.click {
+                let t = click.age as f32 / sample_rate;
+                let value =
+                    click.gain * (2. * PI * click.hz * t).sin() * (-t / DECAY_SECONDS).exp();
+                for sample in frame.iter_mut() {
+                    *sample += value;
+                }
+                click.age += 1;
+                if click.age >= length {
+                    self.click = None;
+                }
+            }
+        }
+    }
+
+    fn transport(&self) -> TransportState {
+        self.source.transport()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Metronome;
+    use crate::synth::SynthPlayer;
+    use crate::transport::TransportState;
+
+    #[derive(Clone)]
+    struct Silence(TransportState);
+
+    impl SynthPlayer for Silence {
+        fn play(&mut self, _sample_rate: u32, _channels: usize, output: &mut [f32]) {
+            output.fill(0.);
+        }
+
+        fn transport(&self) -> TransportState {
+            self.0
+        }
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples
+            .iter()
+            .fold(0f32, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn accented_downbeats() {
+        // the last beat of a bar of 3/4 and the next downbeat, at 120 bpm
+        let transport = TransportState {
+            playing: true,
+            position: 2.,
+            end: 4.,
+            time_signature: (3, 4),
+            ..TransportState::default()
+        };
+        let mut metronome = Metronome::new(Silence(transport));
+        let mut output = vec![0f32; 48000];
+        metronome.play(48000, 1, &mut output);
+        assert_eq!(0., peak(&output));
+
+        metronome.get_params().enabled.set(1.);
+        metronome.play(48000, 1, &mut output);
+        let beat = peak(&output[..2000]);
+        let downbeat = peak(&output[24000..26000]);
+        assert!(beat > 0.05);
+        assert!(downbeat > 1.5 * beat);
+        assert_eq!(0., peak(&output[2000..24000]));
+        assert_eq!(0., peak(&output[26000..]));
+    }
+
+    #[test]
+    fn count_in_click() {
+        let transport = TransportState {
+            playing: true,
+            position: -1.,
+            end: 0.,
+            count_in: true,
+            ..TransportState::default()
+        };
+        let mut metronome = Metronome::new(Silence(transport));
+        let mut output = vec![0f32; 24000];
+        metronome.play(48000, 1, &mut output);
+        assert!(peak(&output) > 0.05);
+
+        metronome.get_params().count_in.set(0.);
+        metronome.play(48000, 1, &mut output);
+        assert_eq!(0., peak(&output));
+    }
+}
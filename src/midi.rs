@@ -1,74 +1,263 @@
-use crate::timer::Timer;
-use anyhow::{anyhow, bail, Result};
+use crate::monitor::MidiSender;
+use crate::timer::{Interval, Stopwatch, Timer};
+use anyhow::{anyhow, Result};
 use chrono::Duration;
-use crossbeam::channel;
+use crossbeam::queue::ArrayQueue;
 use log::{error, warn};
-use midir::{MidiInput, MidiInputConnection};
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
 use std::{convert::TryFrom, sync::{Arc, Mutex}};
-use wmidi::MidiMessage;
 
-type MidiSender = channel::Sender<MidiMessage<'static>>;
+/// messages from the audio thread waiting to go out, a few blocks' worth of clock
+const MAX_QUEUED: usize = 256;
+/// how often queued messages are looked at, short next to a clock tick at the fastest tempo
+const SEND_MILLISECONDS: i64 = 1;
+/// how far the audio may be rendered ahead of the wall clock before the writer stops waiting
+/// for it, more than the longest block
+const MAX_LEAD_SECONDS: f64 = 0.5;
+/// messages later than this push back the ones after them, rather than going out bunched up
+const MAX_LATE_SECONDS: f64 = 0.005;
 
-pub struct MidiReader {
-    midi_events: MidiSender,
+type Connect<T> = Box<dyn Fn() -> Result<(T, String)> + Send + Sync>;
+
+/// A connection to a midi port, opened by `connect` which is retried every second until it works.
+struct Port<T> {
+    /// for the warnings
+    kind: &'static str,
+    connect: Connect<T>,
     timer: Timer,
-    port: Mutex<Option<(MidiInputConnection<()>, String)>>,
+    connection: Mutex<Option<(T, String)>>,
 }
 
-impl MidiReader {
-    pub fn new(midi_events: MidiSender) -> Arc<Self> {
-        let aself = Arc::new(Self {
+impl<T: Send + 'static> Port<T> {
+    fn new(
+        kind: &'static str,
+        connect: impl Fn() -> Result<(T, String)> + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        let port = Arc::new(Self {
+            kind,
+            connect: Box::new(connect),
             timer: Timer::new(),
-            port: Mutex::new(None),
-            midi_events,
+            connection: Mutex::new(None),
         });
-        aself.init();
-        aself
+        port.init();
+        port
     }
 
     fn init(self: &Arc<Self>) {
-        debug_assert!(self.port.lock().unwrap().is_none());
-        let r = (|| -> Result<()> {
+        debug_assert!(self.connection.lock().unwrap().is_none());
+        match (self.connect)() {
+            Ok(connection) => *self.connection.lock().unwrap() = Some(connection),
+            Err(e) => {
+                warn!("error setting up {}: {}. retrying", self.kind, e);
+                let weak_self = Arc::downgrade(self);
+                self.timer
+                    .schedule_with_delay(&Duration::seconds(1), move || {
+                        if let Some(s) = weak_self.upgrade() {
+                            s.init();
+                        }
+                    });
+            }
+        }
+    }
+
+    fn get_name(&self) -> String {
+        self.connection.lock().unwrap().as_ref().map(|(_, name)| name.clone()).unwrap_or("-".to_string())
+    }
+}
+
+pub struct MidiReader {
+    port: Arc<Port<MidiInputConnection<()>>>,
+}
+
+impl MidiReader {
+    pub fn new(midi_events: MidiSender) -> Arc<Self> {
+        let port = Port::new("midi", move || {
             let midi = MidiInput::new("wayfarer")?;
             let ports = midi.ports();
-            Ok(if let Some(port) = ports.first() {
-                let name = midi.port_name(port)?;
-                let midi_events = self.midi_events.clone();
-                let connection = midi
-                    .connect(
-                        port,
-                        &name,
-                        move |_time_ms, message, _| match wmidi::MidiMessage::try_from(message) {
-                            Ok(message) => {
-                                if let Err(e) = midi_events.try_send(message.to_owned()) {
-                                    error!("error sending midi event {}", e);
-                                }
-                            }
-                            Err(e) => {
-                                error!("error parsing midi event {}", e);
+            let port = ports.first().ok_or_else(|| anyhow!("no midi so far"))?;
+            let name = midi.port_name(port)?;
+            let midi_events = midi_events.with_source(&name);
+            let connection = midi
+                .connect(
+                    port,
+                    &name,
+                    move |_time_ms, message, _| match wmidi::MidiMessage::try_from(message) {
+                        Ok(message) => {
+                            if let Err(e) = midi_events.try_send(message.to_owned()) {
+                                error!("error sending midi event {}", e);
                             }
-                        },
-                        (),
-                    )
-                    .map_err(|e| anyhow!("{}", e))?;
-                *self.port.lock().unwrap() = Some((connection, name));
-            } else {
-                bail!("no midi so far");
-            })
-        })();
-        if let Err(e) = r {
-            warn!("error setting up midi: {}. retrying", e);
-            let weak_self = Arc::downgrade(&self);
-            self.timer
-                .schedule_with_delay(&Duration::seconds(1), move || {
-                    if let Some(s) = weak_self.upgrade() {
-                        s.init();
-                    }
-                });
+                        }
+                        Err(e) => {
+                            error!("error parsing midi event {}", e);
+                        }
+                    },
+                    (),
+                )
+                .map_err(|e| anyhow!("{}", e))?;
+            Ok((connection, name))
+        });
+        Arc::new(Self { port })
+    }
+
+    pub fn get_name(&self) -> String {
+        self.port.get_name()
+    }
+}
+
+/// A message for a `MidiWriter` and when to send it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scheduled {
+    bytes: [u8; 3],
+    len: usize,
+    /// in seconds of audio rendered, which the writer lines up with the wall clock
+    pub time: f64,
+}
+
+impl Scheduled {
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Messages the audio thread has for a `MidiWriter`.
+pub struct MidiQueue {
+    queue: ArrayQueue<Scheduled>,
+}
+
+impl MidiQueue {
+    pub fn new() -> Self {
+        Self {
+            queue: ArrayQueue::new(MAX_QUEUED),
+        }
+    }
+
+    /// Called from the audio thread, so it only queues the message to go out at `time`, in
+    /// seconds of audio rendered. Messages are dropped when the queue is full.
+    pub fn push(&self, message: &wmidi::MidiMessage<'_>, time: f64) {
+        let mut bytes = [0u8; 3];
+        if let Ok(len) = message.copy_to_slice(&mut bytes) {
+            let _ignore = self.queue.push(Scheduled { bytes, len, time });
+        }
+    }
+
+    pub fn pop(&self) -> Option<Scheduled> {
+        self.queue.pop()
+    }
+}
+
+/// Lines the times of queued messages up with the wall clock.
+///
+/// The first message goes out right away and the ones after it keep their spacing from it, so
+/// the messages of a block are as far apart as they are in the audio. When a block comes in late
+/// the ones after it are pushed back by as much.
+struct Schedule {
+    /// popped but not due yet
+    pending: Option<Scheduled>,
+    /// wall clock minus audio time, in seconds
+    offset: Option<f64>,
+}
+
+impl Schedule {
+    fn new() -> Self {
+        Self {
+            pending: None,
+            offset: None,
         }
     }
 
+    /// The next message due at `now`, in seconds of the wall clock.
+    fn next(&mut self, queue: &MidiQueue, now: f64) -> Option<Scheduled> {
+        let message = self.pending.take().or_else(|| queue.pop())?;
+        let due = self.offset.map_or(f64::NEG_INFINITY, |offset| message.time + offset);
+        if due < now - MAX_LATE_SECONDS || due > now + MAX_LEAD_SECONDS {
+            // the first message, a new stream or the clocks drifted apart
+            self.offset = Some(now - message.time);
+        } else if due > now {
+            self.pending = Some(message);
+            return None;
+        }
+        Some(message)
+    }
+}
+
+/// The first midi output port, for sending clock.
+///
+/// The audio thread only queues messages, they are sent from a thread of their own since that
+/// goes through the os.
+pub struct MidiWriter {
+    port: Arc<Port<MidiOutputConnection>>,
+    queue: Arc<MidiQueue>,
+    _sender: Interval,
+}
+
+impl MidiWriter {
+    pub fn new() -> Arc<Self> {
+        let port = Port::new("midi output", || {
+            let midi = MidiOutput::new("wayfarer")?;
+            let ports = midi.ports();
+            let port = ports
+                .first()
+                .ok_or_else(|| anyhow!("no midi output so far"))?;
+            let name = midi.port_name(port)?;
+            let connection = midi.connect(port, &name).map_err(|e| anyhow!("{}", e))?;
+            Ok((connection, name))
+        });
+        let queue = Arc::new(MidiQueue::new());
+        let sender = {
+            let port = port.clone();
+            let queue = queue.clone();
+            let schedule = Mutex::new(Schedule::new());
+            let stopwatch = Stopwatch::new();
+            Interval::new(&Duration::milliseconds(SEND_MILLISECONDS), move || {
+                let mut schedule = schedule.lock().unwrap();
+                while let Some(message) = schedule.next(&queue, stopwatch.seconds()) {
+                    // dropped while the port is being set up
+                    if let Some((connection, _)) = port.connection.lock().unwrap().as_mut() {
+                        // a lost clock tick isn't worth a warning each time
+                        let _ignore = connection.send(message.bytes());
+                    }
+                }
+            })
+        };
+        Arc::new(Self {
+            port,
+            queue,
+            _sender: sender,
+        })
+    }
+
+    /// Where the audio thread puts what to send.
+    pub fn get_queue(&self) -> Arc<MidiQueue> {
+        self.queue.clone()
+    }
+
     pub fn get_name(&self) -> String {
-        self.port.lock().unwrap().as_ref().map(|(_, name)| name.clone()).unwrap_or("-".to_string())
+        self.port.get_name()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MidiQueue, Schedule};
+    use wmidi::MidiMessage;
+
+    #[test]
+    fn keeps_the_spacing_of_the_audio() {
+        let queue = MidiQueue::new();
+        let mut schedule = Schedule::new();
+        let mut next = |now| schedule.next(&queue, now).map(|message| message.time);
+        queue.push(&MidiMessage::TimingClock, 1.);
+        queue.push(&MidiMessage::TimingClock, 1.02);
+        assert_eq!(Some(1.), next(10.));
+        assert_eq!(None, next(10.01));
+        assert_eq!(Some(1.02), next(10.021));
+        assert_eq!(None, next(10.03));
+
+        // a block rendered late goes out right away and pushes back the ones after it
+        queue.push(&MidiMessage::TimingClock, 1.04);
+        queue.push(&MidiMessage::TimingClock, 1.06);
+        assert_eq!(Some(1.04), next(10.1));
+        assert_eq!(None, next(10.11));
+        assert_eq!(Some(1.06), next(10.121));
     }
 }
@@ -0,0 +1,392 @@
+use crate::param::Param;
+use anyhow::{anyhow, bail, Result};
+use std::{fmt, str::FromStr};
+
+/// enough that learning never allocates on the audio thread
+const MAX_BINDINGS: usize = 128;
+/// 14 bit controllers send the low bits on this much higher cc number
+const LSB_OFFSET: u8 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CcMode {
+    Absolute,
+    /// endless encoders sending 1 to 63 for steps up and 127 down to 65 for steps down
+    Relative,
+    /// msb on the learned cc and lsb on the cc 32 above it
+    Fine,
+}
+
+impl CcMode {
+    pub const ALL: [CcMode; 3] = [CcMode::Absolute, CcMode::Relative, CcMode::Fine];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CcMode::Absolute => "absolute",
+            CcMode::Relative => "relative",
+            CcMode::Fine => "14 bit",
+        }
+    }
+}
+
+/// A controller driving a param of one of the synth's parts.
+#[derive(Clone, Debug)]
+pub struct Binding {
+    /// index of the part, whatever channel the controller is on
+    pub part: usize,
+    pub param: &'static str,
+    pub channel: wmidi::Channel,
+    pub cc: u8,
+    pub mode: CcMode,
+    /// part of the param's range the controller covers, from 0 to 1
+    pub min: f32,
+    pub max: f32,
+    pub invert: bool,
+    msb: u8,
+}
+
+impl Binding {
+    fn new(part: usize, param: &'static str, channel: wmidi::Channel, cc: u8) -> Self {
+        Self {
+            part,
+            param,
+            channel,
+            cc,
+            mode: CcMode::Absolute,
+            min: 0.,
+            max: 1.,
+            invert: false,
+            msb: 0,
+        }
+    }
+
+    /// The new position for `param` from 0 to 1, if this message concerns it.
+    fn position(&mut self, param: &Param, cc: u8, value: u8) -> Option<f32> {
+        let mut t = match self.mode {
+            CcMode::Absolute if cc == self.cc => value as f32 / 127.,
+            CcMode::Fine if cc == self.cc => {
+                // the lsb usually follows, but the msb alone should still do something
+                self.msb = value;
+                (value as u16 * 128) as f32 / 16383.
+            }
+            CcMode::Fine if cc == self.cc + LSB_OFFSET => {
+                ((self.msb as u16 * 128 + value as u16) as f32) / 16383.
+            }
+            CcMode::Relative if cc == self.cc => {
+                let steps = if value < 64 {
+                    value as f32
+                } else {
+                    value as f32 - 128.
+                };
+                let span = self.max - self.min;
+                if span.abs() < f32::EPSILON {
+                    return Some(self.min);
+                }
+                // steps move the current value, so inverting flips their direction instead
+                let steps = if self.invert { -steps } else { steps };
+                let current = (normalize(param) - self.min) / span;
+                let t = (current + steps / 127.).clamp(0., 1.);
+                return Some(self.min + span * t);
+            }
+            _ => return None,
+        };
+        if self.invert {
+            t = 1. - t;
+        }
+        Some(self.min + (self.max - self.min) * t)
+    }
+}
+
+/// Position of the param's value within its range, from 0 to 1.
+fn normalize(param: &Param) -> f32 {
+    let (start, end) = (*param.range.start(), *param.range.end());
+    if param.logarithmic && start > 0. {
+        (param.get() / start).ln() / (end / start).ln()
+    } else {
+        (param.get() - start) / (end - start)
+    }
+}
+
+fn denormalize(param: &Param, t: f32) -> f32 {
+    let (start, end) = (*param.range.start(), *param.range.end());
+    let value = if param.logarithmic && start > 0. {
+        start * (end / start).powf(t)
+    } else {
+        start + (end - start) * t
+    };
+    if param.choices.is_empty() {
+        value
+    } else {
+        value.round()
+    }
+}
+
+/// Controller assignments, shared between the ui and the synth's midi handling.
+///
+/// ```text
+/// gain = part 1 channel 1 cc 7 mode absolute min 0 max 1 invert off
+/// ```
+#[derive(Clone, Debug)]
+pub struct MidiMap {
+    bindings: Vec<Binding>,
+    /// the part and param the next controller moved gets bound to
+    learning: Option<(usize, &'static str)>,
+}
+
+impl Default for MidiMap {
+    fn default() -> Self {
+        Self {
+            bindings: Vec::with_capacity(MAX_BINDINGS),
+            learning: None,
+        }
+    }
+}
+
+impl MidiMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn learn(&mut self, part: usize, param: &'static str) {
+        self.learning = Some((part, param));
+    }
+
+    pub fn cancel_learn(&mut self) {
+        self.learning = None;
+    }
+
+    pub fn learning(&self) -> Option<(usize, &'static str)> {
+        self.learning
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    pub fn bindings_mut(&mut self) -> &mut Vec<Binding> {
+        &mut self.bindings
+    }
+
+    pub fn binding(&self, part: usize, param: &str) -> Option<&Binding> {
+        self.bindings
+            .iter()
+            .find(|binding| binding.part == part && binding.param == param)
+    }
+
+    /// Applies a control change to the bound params, or binds it when learning.
+    /// `params` gives the params of a part by index.
+    /// Called from the audio thread, so doesn't allocate.
+    pub fn control_change<'a, F, P>(
+        &mut self,
+        params: F,
+        channel: wmidi::Channel,
+        cc: u8,
+        value: u8,
+    ) where
+        F: Fn(usize) -> P,
+        P: IntoIterator<Item = &'a Param>,
+    {
+        if let Some((part, param)) = self.learning {
+            // the low half of a 14 bit pair shouldn't steal a fine binding
+            let is_lsb = self.bindings.iter().any(|binding| {
+                binding.mode == CcMode::Fine
+                    && binding.channel == channel
+                    && binding.cc + LSB_OFFSET == cc
+            });
+            if !is_lsb
+                && (self.bindings.len() < MAX_BINDINGS || self.binding(part, param).is_some())
+            {
+                self.learning = None;
+                self.bindings
+                    .retain(|binding| binding.part != part || binding.param != param);
+                self.bindings.push(Binding::new(part, param, channel, cc));
+            }
+        }
+        for binding in self
+            .bindings
+            .iter_mut()
+            .filter(|binding| binding.channel == channel)
+        {
+            let param = params(binding.part)
+                .into_iter()
+                .find(|param| param.name == binding.param);
+            if let Some(param) = param {
+                if let Some(t) = binding.position(param, cc, value) {
+                    param.set(denormalize(param, t));
+                }
+            }
+        }
+    }
+
+    /// Resolves the param names in a saved map to the params of each part,
+    /// dropping any that don't exist anymore.
+    pub fn load(text: &str, parts: &[Vec<&Param>]) -> Result<Self> {
+        let mut map = Self::new();
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, settings) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("unable to parse line {}: {}", line_num + 1, line))?;
+            let mut binding: Binding = settings.parse()?;
+            let param = parts
+                .get(binding.part)
+                .and_then(|params| params.iter().find(|param| param.name == name.trim()));
+            binding.param = match param {
+                Some(param) => param.name,
+                None => continue,
+            };
+            if map.bindings.len() < MAX_BINDINGS {
+                map.bindings.push(binding);
+            }
+        }
+        Ok(map)
+    }
+}
+
+impl fmt::Display for MidiMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for binding in &self.bindings {
+            writeln!(
+                f,
+                "{} = part {} channel {} cc {} mode {} min {} max {} invert {}",
+                binding.param,
+                binding.part + 1,
+                binding.channel.number(),
+                binding.cc,
+                match binding.mode {
+                    CcMode::Absolute => "absolute",
+                    CcMode::Relative => "relative",
+                    CcMode::Fine => "fine",
+                },
+                binding.min,
+                binding.max,
+                if binding.invert { "on" } else { "off" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the settings after the `=`, leaving the param empty.
+impl FromStr for Binding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut binding = Binding::new(0, "", wmidi::Channel::Ch1, 0);
+        let mut part = None;
+        let words: Vec<&str> = s.split_whitespace().collect();
+        for pair in words.chunks(2) {
+            let (key, value) = match pair {
+                [key, value] => (*key, *value),
+                _ => bail!("missing value in midi mapping: {}", s),
+            };
+            let bad_value = || anyhow!("bad value for {}: {}", key, value);
+            match key {
+                "part" => {
+                    let number: usize = value.parse().map_err(|_| bad_value())?;
+                    part = Some(number.checked_sub(1).ok_or_else(bad_value)?);
+                }
+                "channel" => {
+                    let number: u8 = value.parse().map_err(|_| bad_value())?;
+                    binding.channel = wmidi::Channel::from_index(number.wrapping_sub(1))
+                        .map_err(|_| bad_value())?;
+                }
+                "cc" => {
+                    binding.cc = value
+                        .parse()
+                        .ok()
+                        .filter(|cc| *cc < 128)
+                        .ok_or_else(bad_value)?
+                }
+                "mode" => {
+                    binding.mode = match value {
+                        "absolute" => CcMode::Absolute,
+                        "relative" => CcMode::Relative,
+                        "fine" => CcMode::Fine,
+                        _ => return Err(bad_value()),
+                    }
+                }
+                "min" => binding.min = value.parse::<f32>().map_err(|_| bad_value())?.clamp(0., 1.),
+                "max" => binding.max = value.parse::<f32>().map_err(|_| bad_value())?.clamp(0., 1.),
+                "invert" => {
+                    binding.invert = match value {
+                        "on" => true,
+                        "off" => false,
+                        _ => return Err(bad_value()),
+                    }
+                }
+                // newer versions may know more
+                _ => {}
+            }
+        }
+        // maps from before parts drove whichever part was on the controller's channel
+        binding.part = part.unwrap_or(binding.channel.index() as usize);
+        Ok(binding)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CcMode, MidiMap};
+    use crate::param::Param;
+
+    #[test]
+    fn learn_and_map() {
+        let gain = Param::new("gain", "", 1., 0f32..=1f32);
+        let freq = Param::new("freq", "Hz", 100., 10f32..=1000f32).logarithmic();
+        let other_gain = Param::new("gain", "", 1., 0f32..=1f32);
+        let parts = [vec![&other_gain], vec![&gain, &freq]];
+        let params = |part: usize| parts[part].clone();
+        let channel = wmidi::Channel::Ch2;
+        let mut map = MidiMap::new();
+        // nothing bound yet
+        map.control_change(params, channel, 7, 0);
+        assert_eq!(1., gain.get());
+
+        map.learn(1, "gain");
+        map.control_change(params, channel, 7, 127);
+        assert_eq!(None, map.learning());
+        map.control_change(params, channel, 7, 0);
+        assert_eq!(0., gain.get());
+        // other channels are left alone
+        map.control_change(params, wmidi::Channel::Ch1, 7, 127);
+        assert_eq!(0., gain.get());
+        // and the same param on other parts
+        assert_eq!(1., other_gain.get());
+
+        let binding = &mut map.bindings_mut()[0];
+        binding.invert = true;
+        binding.min = 0.5;
+        map.control_change(params, channel, 7, 0);
+        assert_eq!(1., gain.get());
+        map.control_change(params, channel, 7, 127);
+        assert_eq!(0.5, gain.get());
+
+        map.learn(1, "freq");
+        map.control_change(params, channel, 1, 0);
+        map.bindings_mut()[1].mode = CcMode::Fine;
+        // halfway in 14 bits is the geometric middle of a log range
+        map.control_change(params, channel, 1, 64);
+        map.control_change(params, channel, 33, 0);
+        assert!((freq.get() - 100.).abs() < 0.1, "{}", freq.get());
+
+        map.bindings_mut()[1].mode = CcMode::Relative;
+        map.control_change(params, channel, 1, 127);
+        assert!(freq.get() < 100.);
+        map.control_change(params, channel, 1, 2);
+        assert!(freq.get() > 100.);
+
+        let loaded = MidiMap::load(&map.to_string(), &parts).unwrap();
+        assert_eq!(map.to_string(), loaded.to_string());
+        assert_eq!(2, loaded.bindings.len());
+        // the part is remembered, older maps go by the channel
+        assert!(MidiMap::load(&map.to_string(), &parts[..1])
+            .unwrap()
+            .bindings
+            .is_empty());
+        let old = MidiMap::load("gain = channel 2 cc 7", &parts).unwrap();
+        assert_eq!(1, old.bindings[0].part);
+    }
+}
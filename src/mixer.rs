@@ -0,0 +1,64 @@
+use crate::param::Param;
+use crate::synth::MixerParams;
+use eframe::egui;
+use std::sync::Arc;
+
+/// Ui for the parts' mix settings, and for picking which part the synth controls edit.
+pub struct Mixer {
+    parts: Vec<Arc<MixerParams>>,
+    selected: usize,
+}
+
+fn toggle_button(ui: &mut egui::Ui, param: &Param, text: &str) {
+    if ui
+        .selectable_label(param.is_on(), text)
+        .on_hover_text(param.name)
+        .clicked()
+    {
+        param.set(if param.is_on() { 0. } else { 1. });
+    }
+}
+
+impl Mixer {
+    pub fn new(parts: Vec<Arc<MixerParams>>) -> Self {
+        Self { parts, selected: 0 }
+    }
+
+    /// Index of the part being edited, which is also its midi channel index.
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("mixer grid").striped(true).show(ui, |ui| {
+            ui.label("channel");
+            ui.label("volume");
+            ui.label("pan");
+            ui.label("");
+            ui.label("transpose");
+            ui.end_row();
+            for (index, part) in self.parts.iter().enumerate() {
+                ui.selectable_value(&mut self.selected, index, format!("{}", index + 1))
+                    .on_hover_text("edit this part");
+                let mut volume = part.volume.get();
+                ui.add(egui::Slider::new(&mut volume, part.volume.range.clone()).suffix(" dB"));
+                part.volume.set(volume);
+                let mut pan = part.pan.get();
+                ui.add(egui::Slider::new(&mut pan, part.pan.range.clone()));
+                part.pan.set(pan);
+                ui.horizontal(|ui| {
+                    toggle_button(ui, &part.mute, "M");
+                    toggle_button(ui, &part.solo, "S");
+                });
+                let mut transpose = part.transpose.get().round() as i32;
+                ui.add(
+                    egui::DragValue::new(&mut transpose)
+                        .clamp_range(part.transpose.range.clone())
+                        .suffix(" st"),
+                );
+                part.transpose.set(transpose as f32);
+                ui.end_row();
+            }
+        });
+    }
+}
@@ -0,0 +1,358 @@
+use chrono::{DateTime, Local};
+use crossbeam::channel;
+use eframe::egui::{self, TextStyle};
+use std::{collections::VecDeque, sync::Arc};
+use wmidi::MidiMessage;
+
+const MAX_EVENTS: usize = 1000;
+const LOG_CHANNEL_SIZE: usize = 1024;
+const ROWS_SHOWN: f32 = 12.;
+
+pub struct MidiEvent {
+    time: DateTime<Local>,
+    source: Arc<str>,
+    message: MidiMessage<'static>,
+}
+
+/// Sends midi to the synth, passing a copy of each message on to the monitor.
+#[derive(Clone)]
+pub struct MidiSender {
+    synth: channel::Sender<MidiMessage<'static>>,
+    log: channel::Sender<MidiEvent>,
+    source: Arc<str>,
+}
+
+impl MidiSender {
+    /// The same destinations, tagged with another source.
+    pub fn with_source(&self, source: &str) -> Self {
+        Self {
+            source: source.into(),
+            ..self.clone()
+        }
+    }
+
+    pub fn try_send(
+        &self,
+        message: MidiMessage<'static>,
+    ) -> Result<(), channel::TrySendError<MidiMessage<'static>>> {
+        self.synth.try_send(message.clone())?;
+        // the log only fills up if the ui stalls, no need to complain about it
+        let _ = self.log.try_send(MidiEvent {
+            time: Local::now(),
+            source: self.source.clone(),
+            message,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Note,
+    Pressure,
+    Control,
+    Program,
+    PitchBend,
+    /// timing clock and active sensing, which arrive many times a second
+    Clock,
+    System,
+}
+
+impl Kind {
+    const ALL: [Kind; 7] = [
+        Kind::Note,
+        Kind::Pressure,
+        Kind::Control,
+        Kind::Program,
+        Kind::PitchBend,
+        Kind::Clock,
+        Kind::System,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Note => "notes",
+            Kind::Pressure => "pressure",
+            Kind::Control => "cc",
+            Kind::Program => "program",
+            Kind::PitchBend => "pitch bend",
+            Kind::Clock => "clock",
+            Kind::System => "system",
+        }
+    }
+}
+
+/// Kind, name and decoded values of a message.
+fn describe(message: &MidiMessage<'_>) -> (Kind, &'static str, String) {
+    let note = |note: wmidi::Note| format!("{} ({})", note.to_str(), u8::from(note));
+    match message {
+        MidiMessage::NoteOff(_, n, velocity) => (
+            Kind::Note,
+            "note off",
+            format!("{} velocity {}", note(*n), u8::from(*velocity)),
+        ),
+        MidiMessage::NoteOn(_, n, velocity) => (
+            Kind::Note,
+            "note on",
+            format!("{} velocity {}", note(*n), u8::from(*velocity)),
+        ),
+        MidiMessage::PolyphonicKeyPressure(_, n, pressure) => (
+            Kind::Pressure,
+            "key pressure",
+            format!("{} pressure {}", note(*n), u8::from(*pressure)),
+        ),
+        MidiMessage::ControlChange(_, function, value) => (
+            Kind::Control,
+            "control change",
+            format!("cc {} value {}", u8::from(*function), u8::from(*value)),
+        ),
+        MidiMessage::ProgramChange(_, program) => (
+            Kind::Program,
+            "program change",
+            format!("program {}", u8::from(*program)),
+        ),
+        MidiMessage::ChannelPressure(_, pressure) => (
+            Kind::Pressure,
+            "channel pressure",
+            format!("pressure {}", u8::from(*pressure)),
+        ),
+        MidiMessage::PitchBendChange(_, bend) => {
+            let value = u16::from(*bend);
+            (
+                Kind::PitchBend,
+                "pitch bend",
+                format!("{} ({:+})", value, value as i32 - 0x2000),
+            )
+        }
+        MidiMessage::SysEx(data) => (Kind::System, "sysex", format!("{} bytes", data.len())),
+        MidiMessage::OwnedSysEx(data) => (Kind::System, "sysex", format!("{} bytes", data.len())),
+        MidiMessage::MidiTimeCode(value) => {
+            (Kind::System, "time code", format!("{}", u8::from(*value)))
+        }
+        MidiMessage::SongPositionPointer(position) => (
+            Kind::System,
+            "song position",
+            format!("{} beats", u16::from(*position)),
+        ),
+        MidiMessage::SongSelect(song) => {
+            (Kind::System, "song select", format!("{}", u8::from(*song)))
+        }
+        MidiMessage::Reserved(status) => (Kind::System, "reserved", format!("{:#04x}", status)),
+        MidiMessage::TuneRequest => (Kind::System, "tune request", String::new()),
+        MidiMessage::TimingClock => (Kind::Clock, "clock", String::new()),
+        MidiMessage::Start => (Kind::System, "start", String::new()),
+        MidiMessage::Continue => (Kind::System, "continue", String::new()),
+        MidiMessage::Stop => (Kind::System, "stop", String::new()),
+        MidiMessage::ActiveSensing => (Kind::Clock, "active sensing", String::new()),
+        MidiMessage::Reset => (Kind::System, "reset", String::new()),
+    }
+}
+
+/// An event with its description, formatted once when it's logged rather than on every frame.
+struct LoggedEvent {
+    event: MidiEvent,
+    kind: Kind,
+    name: &'static str,
+    values: String,
+    row: String,
+}
+
+impl LoggedEvent {
+    fn new(event: MidiEvent) -> Self {
+        let (kind, name, values) = describe(&event.message);
+        let channel = event
+            .message
+            .channel()
+            .map_or("-".to_string(), |channel| channel.number().to_string());
+        let row = format!(
+            "{} {:<16} {:>2} {:<16} {}",
+            event.time.format("%H:%M:%S%.3f"),
+            event.source,
+            channel,
+            name,
+            values
+        );
+        Self {
+            event,
+            kind,
+            name,
+            values,
+            row,
+        }
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Scrolling log of the midi coming into the synth, newest first.
+///
+/// Only shows input, the notes the arpeggiator and sequencer play are made up inside the synth.
+pub struct MidiMonitor {
+    log_tx: channel::Sender<MidiEvent>,
+    log_rx: channel::Receiver<MidiEvent>,
+    events: VecDeque<LoggedEvent>,
+    paused: bool,
+    kinds: Vec<bool>,
+    channel: Option<wmidi::Channel>,
+    source: Option<Arc<str>>,
+    sources: Vec<Arc<str>>,
+    export_path: String,
+    export_status: String,
+}
+
+impl MidiMonitor {
+    pub fn new() -> Self {
+        let (log_tx, log_rx) = channel::bounded(LOG_CHANNEL_SIZE);
+        Self {
+            log_tx,
+            log_rx,
+            events: VecDeque::with_capacity(MAX_EVENTS),
+            paused: false,
+            // the clock would drown out everything else
+            kinds: Kind::ALL.iter().map(|kind| *kind != Kind::Clock).collect(),
+            channel: None,
+            source: None,
+            sources: vec![],
+            export_path: "midi_log.csv".to_string(),
+            export_status: String::new(),
+        }
+    }
+
+    /// A sender to `synth` whose messages show up here.
+    pub fn sender(&self, synth: channel::Sender<MidiMessage<'static>>, source: &str) -> MidiSender {
+        MidiSender {
+            synth,
+            log: self.log_tx.clone(),
+            source: source.into(),
+        }
+    }
+
+    /// Takes in new events, should be called every frame so the log doesn't fill up.
+    pub fn update(&mut self) {
+        for event in self.log_rx.try_iter() {
+            if self.paused {
+                continue;
+            }
+            if !self.sources.contains(&event.source) {
+                self.sources.push(event.source.clone());
+            }
+            if self.events.len() == MAX_EVENTS {
+                self.events.pop_front();
+            }
+            self.events.push_back(LoggedEvent::new(event));
+        }
+    }
+
+    fn shown(&self, logged: &LoggedEvent) -> bool {
+        let event = &logged.event;
+        let kind_index = Kind::ALL
+            .iter()
+            .position(|k| *k == logged.kind)
+            .unwrap_or(0);
+        self.kinds[kind_index]
+            && (self.channel.is_none() || event.message.channel() == self.channel)
+            && (self.source.is_none() || self.source.as_ref() == Some(&event.source))
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = "time,source,channel,type,values\n".to_string();
+        for logged in self.events.iter().filter(|event| self.shown(event)) {
+            let event = &logged.event;
+            let channel = event
+                .message
+                .channel()
+                .map_or(String::new(), |channel| channel.number().to_string());
+            csv += &format!(
+                "{},{},{},{},{}\n",
+                event.time.format("%Y-%m-%d %H:%M:%S%.3f"),
+                csv_field(&event.source),
+                channel,
+                logged.name,
+                csv_field(&logged.values)
+            );
+        }
+        csv
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        ui.label("input only, notes from the arpeggiator and sequencer aren't shown");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.paused, "pause");
+            if ui.small_button("clear").clicked() {
+                self.events.clear();
+            }
+            ui.label("channel:");
+            let mut channel_index = self.channel.map_or(0, |channel| channel.number() as usize);
+            egui::ComboBox::from_id_source("monitor channel combo box").show_index(
+                ui,
+                &mut channel_index,
+                17,
+                |i| {
+                    if i == 0 {
+                        "all".to_string()
+                    } else {
+                        i.to_string()
+                    }
+                },
+            );
+            self.channel = if channel_index == 0 {
+                None
+            } else {
+                wmidi::Channel::from_index(channel_index as u8 - 1).ok()
+            };
+            ui.label("source:");
+            let sources = &self.sources;
+            let source = &mut self.source;
+            egui::ComboBox::from_id_source("monitor source combo box")
+                .selected_text(source.as_deref().unwrap_or("all"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(source, None, "all");
+                    for s in sources.iter() {
+                        ui.selectable_value(source, Some(s.clone()), &**s);
+                    }
+                });
+        });
+        ui.horizontal_wrapped(|ui| {
+            for (kind, shown) in Kind::ALL.iter().zip(self.kinds.iter_mut()) {
+                ui.checkbox(shown, kind.name());
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("copy csv").clicked() {
+                ui.output().copied_text = self.to_csv();
+            }
+            // there's no file system to save to on the web
+            if cfg!(not(target_arch = "wasm32")) {
+                if ui.button("save csv").clicked() {
+                    self.export_status = match std::fs::write(&self.export_path, self.to_csv()) {
+                        Ok(()) => format!("saved {}", self.export_path),
+                        Err(e) => format!("error saving {}: {}", self.export_path, e),
+                    };
+                }
+                ui.text_edit_singleline(&mut self.export_path);
+            }
+            ui.label(&self.export_status);
+        });
+
+        let shown: Vec<&LoggedEvent> = self
+            .events
+            .iter()
+            .rev()
+            .filter(|event| self.shown(event))
+            .collect();
+        let row_height = ui.fonts().row_height(TextStyle::Monospace);
+        egui::ScrollArea::from_max_height(row_height * ROWS_SHOWN)
+            .id_source("midi monitor")
+            .show_rows(ui, row_height, shown.len(), |ui, rows| {
+                for logged in &shown[rows] {
+                    ui.monospace(&logged.row);
+                }
+            });
+    }
+}
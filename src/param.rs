@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Result};
+use crossbeam::atomic::AtomicCell;
+use std::ops::RangeInclusive;
+
+/// A single value shared between the ui and the audio thread.
+pub struct Param {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub range: RangeInclusive<f32>,
+    /// names of the options if this is a discrete choice, in which case the value is the index
+    pub choices: &'static [&'static str],
+    pub logarithmic: bool,
+    value: AtomicCell<f32>,
+}
+
+impl Param {
+    pub fn new(
+        name: &'static str,
+        unit: &'static str,
+        default: f32,
+        range: RangeInclusive<f32>,
+    ) -> Self {
+        Self {
+            name,
+            unit,
+            range,
+            choices: &[],
+            logarithmic: false,
+            value: default.into(),
+        }
+    }
+
+    pub fn choice(name: &'static str, default: usize, choices: &'static [&'static str]) -> Self {
+        debug_assert!(!choices.is_empty());
+        Self {
+            name,
+            unit: "",
+            range: 0f32..=(choices.len() - 1) as f32,
+            choices,
+            logarithmic: false,
+            value: (default as f32).into(),
+        }
+    }
+
+    pub fn toggle(name: &'static str, default: bool) -> Self {
+        Self::choice(name, default as usize, &["off", "on"])
+    }
+
+    pub fn logarithmic(mut self) -> Self {
+        self.logarithmic = true;
+        self
+    }
+
+    pub fn get(&self) -> f32 {
+        self.value.load()
+    }
+
+    pub fn set(&self, value: f32) {
+        self.value
+            .store(value.clamp(*self.range.start(), *self.range.end()));
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.get().round() as usize
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.get_index() != 0
+    }
+
+    /// The value as written in patches. Choices are written by name.
+    pub fn to_text(&self) -> String {
+        if self.choices.is_empty() {
+            self.get().to_string()
+        } else {
+            self.choices[self.get_index()].to_string()
+        }
+    }
+
+    /// Reads a value as written in patches, without setting it.
+    pub fn parse_text(&self, text: &str) -> Result<f32> {
+        match self.choices.iter().position(|choice| *choice == text) {
+            Some(index) => Ok(index as f32),
+            None => text
+                .parse()
+                .map_err(|_| anyhow!("bad value for {}: {}", self.name, text)),
+        }
+    }
+
+    pub fn set_text(&self, text: &str) -> Result<()> {
+        self.set(self.parse_text(text)?);
+        Ok(())
+    }
+}
+
+/// A group of params that can be listed without knowing the concrete type, for building ui and such.
+pub trait ParamSet: Send + Sync {
+    fn params(&self) -> Vec<&Param>;
+}
@@ -0,0 +1,243 @@
+use crate::effects::{EffectKind, Slot, SlotState};
+use crate::param::ParamSet;
+use anyhow::{anyhow, bail, Result};
+use log::warn;
+use std::{fmt, str::FromStr, sync::Arc};
+
+const SYNTH_SECTION: &str = "synth";
+const SEQUENCER_SECTION: &str = "sequencer";
+const SLOT_BYPASS: &str = "slot bypass";
+const SLOT_MIX: &str = "slot mix";
+
+type Values = Vec<(String, String)>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EffectPatch {
+    pub kind: EffectKind,
+    pub values: Values,
+}
+
+/// Plain text snapshot of the synth and effect settings, and optionally the sequencer patterns.
+///
+/// ```text
+/// [synth]
+/// gain = 0.8
+///
+/// [reverb]
+/// slot bypass = off
+/// slot mix = 1
+/// size = 0.5
+/// ```
+///
+/// Effects are listed in chain order. Unknown keys are ignored so older versions can read newer patches.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Patch {
+    pub synth: Values,
+    pub effects: Vec<EffectPatch>,
+    /// as written by `Patterns::to_values`, left out when empty
+    pub sequencer: Values,
+}
+
+fn capture_params(params: &dyn ParamSet) -> Values {
+    params
+        .params()
+        .iter()
+        .map(|param| (param.name.to_string(), param.to_text()))
+        .collect()
+}
+
+fn apply_params(params: &dyn ParamSet, values: &[(String, String)]) -> Result<()> {
+    let params = params.params();
+    for (key, value) in values {
+        match params.iter().find(|param| param.name == key) {
+            Some(param) => param.set_text(value)?,
+            None => warn!("ignoring unknown patch parameter {}", key),
+        }
+    }
+    Ok(())
+}
+
+impl Patch {
+    pub fn capture(synth: &dyn ParamSet, slots: &[Arc<SlotState>]) -> Self {
+        Self {
+            synth: capture_params(synth),
+            effects: slots
+                .iter()
+                .map(|slot| {
+                    let mut values = vec![
+                        (
+                            SLOT_BYPASS.to_string(),
+                            if slot.bypass.load() { "on" } else { "off" }.to_string(),
+                        ),
+                        (SLOT_MIX.to_string(), slot.mix.load().to_string()),
+                    ];
+                    values.extend(capture_params(slot.params.as_ref()));
+                    EffectPatch {
+                        kind: slot.kind,
+                        values,
+                    }
+                })
+                .collect(),
+            sequencer: vec![],
+        }
+    }
+
+    /// The patch's value for each of the synth params in order, without setting them.
+    /// Params the patch doesn't mention are left as `None`.
+    pub fn synth_values(&self, synth: &dyn ParamSet) -> Result<Vec<Option<f32>>> {
+        let params = synth.params();
+        let mut values = vec![None; params.len()];
+        for (key, value) in &self.synth {
+            match params.iter().position(|param| param.name == key) {
+                Some(index) => values[index] = Some(params[index].parse_text(value)?),
+                None => warn!("ignoring unknown patch parameter {}", key),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Creates new effects with the settings from the patch.
+    /// This allocates, so don't call it from the audio thread.
+    pub fn create_slots(&self) -> Result<Vec<Slot>> {
+        self.effects
+            .iter()
+            .map(|effect| {
+                let slot = Slot::new(effect.kind);
+                let state = slot.state();
+                let mut params = vec![];
+                for (key, value) in &effect.values {
+                    match key.as_str() {
+                        SLOT_BYPASS => state.bypass.store(match value.as_str() {
+                            "on" => true,
+                            "off" => false,
+                            _ => bail!("bad value for {}: {}", key, value),
+                        }),
+                        SLOT_MIX => state.mix.store(
+                            value
+                                .parse::<f32>()
+                                .map_err(|_| anyhow!("bad value for {}: {}", key, value))?
+                                .clamp(0., 1.),
+                        ),
+                        _ => params.push((key.clone(), value.clone())),
+                    }
+                }
+                apply_params(state.params.as_ref(), &params)?;
+                Ok(slot)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[{}]", SYNTH_SECTION)?;
+        for (key, value) in &self.synth {
+            writeln!(f, "{} = {}", key, value)?;
+        }
+        if !self.sequencer.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "[{}]", SEQUENCER_SECTION)?;
+            for (key, value) in &self.sequencer {
+                writeln!(f, "{} = {}", key, value)?;
+            }
+        }
+        for effect in &self.effects {
+            writeln!(f)?;
+            writeln!(f, "[{}]", effect.kind.name())?;
+            for (key, value) in &effect.values {
+                writeln!(f, "{} = {}", key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Patch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut patch = Patch::default();
+        let mut section: Option<&mut Values> = None;
+        for (line_num, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                if name == SYNTH_SECTION {
+                    section = Some(&mut patch.synth);
+                } else if name == SEQUENCER_SECTION {
+                    section = Some(&mut patch.sequencer);
+                } else {
+                    let kind = EffectKind::from_name(name).ok_or_else(|| {
+                        anyhow!("unknown effect {} on line {}", name, line_num + 1)
+                    })?;
+                    patch.effects.push(EffectPatch {
+                        kind,
+                        values: vec![],
+                    });
+                    section = patch.effects.last_mut().map(|effect| &mut effect.values);
+                }
+            } else if let Some((key, value)) = line.split_once('=') {
+                section
+                    .as_mut()
+                    .ok_or_else(|| anyhow!("value outside of section on line {}", line_num + 1))?
+                    .push((key.trim().to_string(), value.trim().to_string()));
+            } else {
+                bail!("unable to parse line {}: {}", line_num + 1, line);
+            }
+        }
+        Ok(patch)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Patch;
+    use crate::effects::{EffectKind, Slot};
+    use crate::param::{Param, ParamSet};
+
+    struct Synth {
+        gain: Param,
+    }
+
+    impl ParamSet for Synth {
+        fn params(&self) -> Vec<&Param> {
+            vec![&self.gain]
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let synth = Synth {
+            gain: Param::new("gain", "", 0.25, 0f32..=1f32),
+        };
+        let reverb = Slot::new(EffectKind::Reverb);
+        reverb.state().mix.store(0.5);
+        reverb.state().params.params()[1].set(3.5);
+        let delay = Slot::new(EffectKind::Delay);
+        delay.state().bypass.store(true);
+        delay.state().params.params()[1].set_text("1/8.").unwrap();
+        let text =
+            Patch::capture(&synth, &[reverb.state().clone(), delay.state().clone()]).to_string();
+
+        let patch: Patch = text.parse().unwrap();
+        assert_eq!(vec![Some(0.25)], patch.synth_values(&synth).unwrap());
+        let slots = patch.create_slots().unwrap();
+        assert_eq!(2, slots.len());
+        assert_eq!(EffectKind::Reverb, slots[0].state().kind);
+        assert_eq!(0.5, slots[0].state().mix.load());
+        assert_eq!(3.5, slots[0].state().params.params()[1].get());
+        assert!(slots[1].state().bypass.load());
+        assert_eq!("1/8.", slots[1].state().params.params()[1].to_text());
+        assert_eq!(
+            text,
+            Patch::capture(
+                &synth,
+                &[slots[0].state().clone(), slots[1].state().clone()]
+            )
+            .to_string()
+        );
+    }
+}
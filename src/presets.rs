@@ -0,0 +1,210 @@
+use crate::patch::Patch;
+use anyhow::{anyhow, bail, Result};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+const BANK_SELECT_KEY: &str = "bank select";
+const PROGRAM_PREFIX: &str = "program ";
+
+/// Which bank select controllers pick the bank.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BankSelect {
+    /// everything is in bank 0
+    Ignore,
+    /// cc 0
+    Msb,
+    /// cc 32
+    Lsb,
+    /// cc 0 and 32 together, for up to 16384 banks
+    MsbLsb,
+}
+
+impl BankSelect {
+    pub const ALL: [BankSelect; 4] = [
+        BankSelect::Ignore,
+        BankSelect::Msb,
+        BankSelect::Lsb,
+        BankSelect::MsbLsb,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BankSelect::Ignore => "ignore",
+            BankSelect::Msb => "msb",
+            BankSelect::Lsb => "lsb",
+            BankSelect::MsbLsb => "msb lsb",
+        }
+    }
+
+    fn bank(self, msb: u8, lsb: u8) -> u16 {
+        match self {
+            BankSelect::Ignore => 0,
+            BankSelect::Msb => msb as u16,
+            BankSelect::Lsb => lsb as u16,
+            BankSelect::MsbLsb => msb as u16 * 128 + lsb as u16,
+        }
+    }
+}
+
+/// A program change together with the bank select that came before it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProgramChange {
+    pub bank_msb: u8,
+    pub bank_lsb: u8,
+    pub program: u8,
+}
+
+/// Patches stored by bank and program number, for switching with program changes.
+///
+/// ```text
+/// bank select = msb
+/// program 0 5
+/// [synth]
+/// gain = 0.8
+/// ```
+///
+/// Each `program <bank> <program>` line is followed by the patch, as written by [`Patch`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Presets {
+    pub bank_select: BankSelect,
+    patches: BTreeMap<(u16, u8), Patch>,
+}
+
+impl Default for Presets {
+    fn default() -> Self {
+        Self {
+            bank_select: BankSelect::MsbLsb,
+            patches: BTreeMap::new(),
+        }
+    }
+}
+
+impl Presets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn store(&mut self, bank: u16, program: u8, patch: Patch) {
+        self.patches.insert((bank, program & 0x7f), patch);
+    }
+
+    pub fn remove(&mut self, bank: u16, program: u8) {
+        self.patches.remove(&(bank, program));
+    }
+
+    /// Bank, program and patch for each preset in order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u8, &Patch)> {
+        self.patches
+            .iter()
+            .map(|((bank, program), patch)| (*bank, *program, patch))
+    }
+
+    pub fn bank(&self, change: ProgramChange) -> u16 {
+        self.bank_select.bank(change.bank_msb, change.bank_lsb)
+    }
+
+    pub fn find(&self, change: ProgramChange) -> Option<&Patch> {
+        self.patches.get(&(self.bank(change), change.program))
+    }
+}
+
+impl fmt::Display for Presets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} = {}", BANK_SELECT_KEY, self.bank_select.name())?;
+        for ((bank, program), patch) in &self.patches {
+            writeln!(f, "{}{} {}", PROGRAM_PREFIX, bank, program)?;
+            write!(f, "{}", patch)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Presets {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut presets = Presets::new();
+        // collect the text of each patch, then parse them one by one
+        let mut current: Option<((u16, u8), String)> = None;
+        let mut finish = |current: Option<((u16, u8), String)>| -> Result<()> {
+            if let Some((key, text)) = current {
+                presets.patches.insert(
+                    key,
+                    text.parse()
+                        .map_err(|e| anyhow!("bad preset {} {}: {}", key.0, key.1, e))?,
+                );
+            }
+            Ok(())
+        };
+        let mut bank_select = None;
+        for line in s.lines() {
+            if let Some(numbers) = line.trim().strip_prefix(PROGRAM_PREFIX) {
+                let key = match numbers
+                    .split_whitespace()
+                    .map(str::parse::<u32>)
+                    .collect::<Vec<_>>()
+                    .as_slice()
+                {
+                    [Ok(bank), Ok(program)] if *bank < 0x4000 && *program < 0x80 => {
+                        (*bank as u16, *program as u8)
+                    }
+                    _ => bail!("bad program line: {}", line),
+                };
+                finish(current.take())?;
+                current = Some((key, String::new()));
+            } else if let Some((_, text)) = current.as_mut() {
+                text.push_str(line);
+                text.push('\n');
+            } else if let Some((key, value)) = line.split_once('=') {
+                if key.trim() == BANK_SELECT_KEY {
+                    bank_select = Some(
+                        *BankSelect::ALL
+                            .iter()
+                            .find(|mode| mode.name() == value.trim())
+                            .ok_or_else(|| anyhow!("bad bank select: {}", value))?,
+                    );
+                }
+            } else if !line.trim().is_empty() {
+                bail!("unable to parse line: {}", line);
+            }
+        }
+        finish(current)?;
+        if let Some(bank_select) = bank_select {
+            presets.bank_select = bank_select;
+        }
+        Ok(presets)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BankSelect, Presets, ProgramChange};
+    use crate::patch::Patch;
+
+    #[test]
+    fn bank_layout() {
+        let patch = |gain: &str| Patch {
+            synth: vec![("gain".to_string(), gain.to_string())],
+            effects: vec![],
+            sequencer: vec![],
+        };
+        let mut presets = Presets::new();
+        presets.store(0, 3, patch("0.1"));
+        presets.store(2, 3, patch("0.2"));
+        presets.store(2 * 128 + 1, 3, patch("0.3"));
+        let change = ProgramChange {
+            bank_msb: 2,
+            bank_lsb: 1,
+            program: 3,
+        };
+        assert_eq!(Some(&patch("0.3")), presets.find(change));
+        presets.bank_select = BankSelect::Msb;
+        assert_eq!(Some(&patch("0.2")), presets.find(change));
+        presets.bank_select = BankSelect::Lsb;
+        assert_eq!(None, presets.find(change));
+        presets.bank_select = BankSelect::Ignore;
+        assert_eq!(Some(&patch("0.1")), presets.find(change));
+
+        let loaded: Presets = presets.to_string().parse().unwrap();
+        assert_eq!(presets, loaded);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::patch::Patch;
+use crate::presets::Presets;
+use crate::sequencer::PatternsEditor;
+use crate::synth::{Params, PreparedProgram, Programs};
+use crate::timer::Interval;
+use anyhow::{anyhow, bail, Result};
+use chrono::Duration;
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// how often to look for program changes
+const POLL_MILLISECONDS: i64 = 10;
+
+struct Loader {
+    programs: Arc<Programs>,
+    /// one per part
+    params: Vec<Arc<Params>>,
+    patterns: Arc<Mutex<PatternsEditor>>,
+    presets: Arc<Mutex<Presets>>,
+    status_text: Arc<Mutex<String>>,
+}
+
+impl Loader {
+    fn prepare(&self, part: usize, patch: &Patch, shared: bool) -> Result<PreparedProgram> {
+        let params = self
+            .params
+            .get(part)
+            .ok_or_else(|| anyhow!("no part {}", part + 1))?;
+        let mut patterns = if shared {
+            Some(self.patterns.lock())
+        } else {
+            None
+        };
+        PreparedProgram::new(part, params, patch, patterns.as_deref_mut())
+    }
+
+    /// Hands a program to the synth, unless too many are waiting already.
+    fn push(&self, program: PreparedProgram) -> bool {
+        // the synth moves programs from one queue to the other, so together they never have more than this
+        let programs = &self.programs;
+        programs.prepared.len() + programs.retired.len() < programs.prepared.capacity()
+            && programs.prepared.push(program).is_ok()
+    }
+
+    fn load(&self, part: usize, patch: &Patch, shared: bool) -> Result<()> {
+        let program = self.prepare(part, patch, shared)?;
+        if !self.push(program) {
+            bail!("too many programs waiting to load");
+        }
+        Ok(())
+    }
+
+    /// Drops what the synth switched away from and prepares the presets for new program changes.
+    fn poll(&self) {
+        while let Some(retired) = self.programs.retired.pop() {
+            drop(retired);
+        }
+        for (index, cell) in self.programs.changes.iter().enumerate() {
+            let change = match cell.take() {
+                Some(change) => change,
+                None => continue,
+            };
+            let presets = self.presets.lock();
+            let r = match presets.find(change) {
+                // the effects and patterns are shared by all parts, so only the first channel changes them
+                Some(patch) => self.prepare(index, patch, index == 0),
+                None => Err(anyhow!(
+                    "no preset in bank {} program {}",
+                    presets.bank(change),
+                    change.program
+                )),
+            };
+            drop(presets);
+            match r {
+                Ok(program) => {
+                    if !self.push(program) {
+                        // try again next time, unless a newer one came in meanwhile
+                        let _ignore = cell.compare_exchange(None, Some(change));
+                    }
+                }
+                Err(e) => {
+                    *self.status_text.lock() =
+                        format!("error changing program on channel {}: {}", index + 1, e);
+                }
+            }
+        }
+    }
+}
+
+/// Prepares patches for the synth to switch to between blocks, both the presets picked by
+/// program changes and patches loaded from the ui.
+///
+/// Program changes are looked for on a thread of its own so they still load while the ui isn't
+/// drawing, e.g. when the window is minimized.
+pub struct ProgramLoader {
+    loader: Arc<Loader>,
+    _poller: Interval,
+}
+
+impl ProgramLoader {
+    pub fn new(
+        programs: Arc<Programs>,
+        params: Vec<Arc<Params>>,
+        patterns: Arc<Mutex<PatternsEditor>>,
+        presets: Arc<Mutex<Presets>>,
+        status_text: Arc<Mutex<String>>,
+    ) -> Self {
+        let loader = Arc::new(Loader {
+            programs,
+            params,
+            patterns,
+            presets,
+            status_text,
+        });
+        let poller = {
+            let loader = loader.clone();
+            Interval::new(&Duration::milliseconds(POLL_MILLISECONDS), move || {
+                loader.poll()
+            })
+        };
+        Self {
+            loader,
+            _poller: poller,
+        }
+    }
+
+    /// Switches `part` to `patch`, along with the effects and patterns when `shared`.
+    pub fn load(&self, part: usize, patch: &Patch, shared: bool) -> Result<()> {
+        self.loader.load(part, patch, shared)
+    }
+}
@@ -0,0 +1,148 @@
+use crate::effects::{
+    EffectChain, EffectKind, Slot, SlotState, MAX_METERED_REDUCTION_DB, MAX_SLOTS,
+};
+use crate::param::Param;
+use eframe::egui;
+use std::{hash::Hash, sync::Arc};
+
+pub fn gain_reduction_meter(ui: &mut egui::Ui, reduction_db: f32) {
+    ui.add(
+        egui::ProgressBar::new(reduction_db / MAX_METERED_REDUCTION_DB)
+            .text(format!("{:.1} dB", reduction_db)),
+    );
+}
+
+/// Slider or combo box for `param`, returns the response of the control itself.
+pub fn param_widget(ui: &mut egui::Ui, id_source: impl Hash, param: &Param) -> egui::Response {
+    ui.horizontal(|ui| {
+        ui.label(format!("{}:", param.name));
+        if param.choices.is_empty() {
+            let mut value = param.get();
+            let mut slider =
+                egui::Slider::new(&mut value, param.range.clone()).logarithmic(param.logarithmic);
+            if !param.unit.is_empty() {
+                slider = slider.suffix(format!(" {}", param.unit));
+            }
+            let response = ui.add(slider);
+            param.set(value);
+            response
+        } else {
+            let mut index = param.get_index();
+            let response = egui::ComboBox::from_id_source((id_source, param.name)).show_index(
+                ui,
+                &mut index,
+                param.choices.len(),
+                |i| param.choices[i].to_string(),
+            );
+            param.set(index as f32);
+            response
+        }
+    })
+    .inner
+}
+
+enum Edit {
+    Add(EffectKind),
+    Remove(usize),
+    Move(usize, usize),
+}
+
+/// Ui for the master effect chain.
+pub struct EffectRack {
+    chain: EffectChain,
+    // mirror of the slots in the chain so we don't need to lock it to draw
+    slots: Vec<Arc<SlotState>>,
+}
+
+impl EffectRack {
+    pub fn new(chain: EffectChain) -> Self {
+        Self {
+            chain,
+            slots: vec![],
+        }
+    }
+
+    pub fn slots(&self) -> &[Arc<SlotState>] {
+        &self.slots
+    }
+
+    /// Mirrors the chain again after the audio thread swapped in a patch's effects.
+    pub fn reload(&mut self) {
+        self.slots = self.chain.states();
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        let mut edit = None;
+        let num_slots = self.slots.len();
+        for (index, slot) in self.slots.iter().enumerate() {
+            ui.group(|ui| {
+                ui.horizontal(|ui| {
+                    ui.label(slot.kind.name());
+                    let mut bypass = slot.bypass.load();
+                    ui.checkbox(&mut bypass, "bypass");
+                    slot.bypass.store(bypass);
+                    if index > 0 && ui.small_button("⬆").clicked() {
+                        edit = Some(Edit::Move(index, index - 1));
+                    }
+                    if index + 1 < num_slots && ui.small_button("⬇").clicked() {
+                        edit = Some(Edit::Move(index, index + 1));
+                    }
+                    if ui.small_button("🗑").clicked() {
+                        edit = Some(Edit::Remove(index));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("mix:");
+                    let mut mix = slot.mix.load();
+                    ui.add(egui::Slider::new(&mut mix, 0f32..=1f32));
+                    slot.mix.store(mix);
+                });
+                if let Some(reduction) = &slot.gain_reduction {
+                    ui.horizontal(|ui| {
+                        ui.label("reduction:");
+                        gain_reduction_meter(ui, reduction.load());
+                    });
+                }
+                for param in slot.params.params() {
+                    param_widget(ui, Arc::as_ptr(slot), param);
+                }
+            });
+        }
+        if self.slots.len() < MAX_SLOTS {
+            egui::ComboBox::from_id_source("add effect combo box")
+                .selected_text("add effect")
+                .show_ui(ui, |ui| {
+                    for kind in EffectKind::ALL {
+                        if ui.selectable_label(false, kind.name()).clicked() {
+                            edit = Some(Edit::Add(*kind));
+                        }
+                    }
+                });
+        }
+        let latency: u32 = self
+            .slots
+            .iter()
+            .filter(|slot| !slot.bypass.load())
+            .map(|slot| slot.latency.load())
+            .sum();
+        ui.label(format!("latency: {} frames", latency));
+
+        match edit {
+            Some(Edit::Add(kind)) => {
+                let slot = Slot::new(kind);
+                self.slots.push(slot.state().clone());
+                self.chain.insert(self.slots.len() - 1, slot);
+            }
+            Some(Edit::Remove(index)) => {
+                self.chain.remove(index);
+                self.slots.remove(index);
+            }
+            Some(Edit::Move(from, to)) => {
+                self.chain.move_slot(from, to);
+                let slot = self.slots.remove(from);
+                self.slots.insert(to, slot);
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use eframe::egui::{
+    self,
+    plot::{Line, Plot, Value, Values},
+    Color32,
+};
+use std::collections::VecDeque;
+
+const MIN_TIMEBASE_MS: f32 = 1.;
+const MAX_TIMEBASE_MS: f32 = 100.;
+const LEFT_COLOR: Color32 = Color32::from_rgb(100, 180, 255);
+const RIGHT_COLOR: Color32 = Color32::from_rgb(255, 140, 80);
+
+#[derive(Clone, Copy, PartialEq)]
+enum TriggerMode {
+    Rising,
+    Falling,
+    FreeRun,
+    /// waits for a rising edge and then freezes until armed again
+    Single,
+}
+
+impl TriggerMode {
+    const ALL: [TriggerMode; 4] = [
+        TriggerMode::Rising,
+        TriggerMode::Falling,
+        TriggerMode::FreeRun,
+        TriggerMode::Single,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            TriggerMode::Rising => "rising edge",
+            TriggerMode::Falling => "falling edge",
+            TriggerMode::FreeRun => "free run",
+            TriggerMode::Single => "single shot",
+        }
+    }
+}
+
+/// Index of the latest frame in `start..end` where `channel` crosses `level`.
+fn find_trigger(
+    history: &VecDeque<[f32; 2]>,
+    channel: usize,
+    level: f32,
+    rising: bool,
+    start: usize,
+    end: usize,
+) -> Option<usize> {
+    (start.max(1)..end).rev().find(|&i| {
+        let prev = history[i - 1][channel];
+        let value = history[i][channel];
+        if rising {
+            prev < level && value >= level
+        } else {
+            prev >= level && value < level
+        }
+    })
+}
+
+/// Stereo oscilloscope with a few trigger modes and an xy view.
+pub struct Oscilloscope {
+    mode: TriggerMode,
+    channel: usize,
+    level: f32,
+    timebase_ms: f32,
+    xy: bool,
+    capture: Vec<[f32; 2]>,
+    armed: bool,
+    /// frames received since arming, the single shot shouldn't trigger on older audio
+    fresh: usize,
+}
+
+impl Oscilloscope {
+    pub fn new() -> Self {
+        Self {
+            mode: TriggerMode::Rising,
+            channel: 0,
+            level: 0.,
+            timebase_ms: 10.,
+            xy: false,
+            capture: vec![],
+            armed: true,
+            fresh: 0,
+        }
+    }
+
+    /// Looks for a new trigger among the latest frames in `history`, `new_frames` of which arrived since the last call.
+    pub fn update(&mut self, history: &VecDeque<[f32; 2]>, new_frames: usize, sample_rate: u32) {
+        // leave room to search for a trigger before the newest window
+        let window =
+            ((self.timebase_ms / 1000. * sample_rate as f32) as usize).min(history.len() / 2);
+        if window < 2 {
+            return;
+        }
+        let latest_start = history.len() - window;
+        let start = match self.mode {
+            TriggerMode::FreeRun => Some(latest_start),
+            TriggerMode::Rising | TriggerMode::Falling => find_trigger(
+                history,
+                self.channel,
+                self.level,
+                self.mode == TriggerMode::Rising,
+                history.len() - window * 2,
+                latest_start,
+            ),
+            TriggerMode::Single => {
+                if !self.armed {
+                    return;
+                }
+                self.fresh += new_frames;
+                let start = find_trigger(
+                    history,
+                    self.channel,
+                    self.level,
+                    true,
+                    history.len().saturating_sub(self.fresh),
+                    latest_start,
+                );
+                if start.is_some() {
+                    self.armed = false;
+                }
+                start
+            }
+        };
+        // without a trigger, keep showing the last capture so the view doesn't jump around
+        if let Some(start) = start {
+            self.capture.clear();
+            self.capture
+                .extend(history.range(start..start + window).copied());
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("trigger:");
+            let mut mode = self.mode;
+            egui::ComboBox::from_id_source("trigger mode combo box")
+                .selected_text(mode.name())
+                .show_ui(ui, |ui| {
+                    for m in TriggerMode::ALL.iter() {
+                        ui.selectable_value(&mut mode, *m, m.name());
+                    }
+                });
+            if mode != self.mode {
+                self.mode = mode;
+                self.arm();
+            }
+            if self.mode != TriggerMode::FreeRun {
+                egui::ComboBox::from_id_source("trigger channel combo box").show_index(
+                    ui,
+                    &mut self.channel,
+                    2,
+                    |i| ["left", "right"][i].to_string(),
+                );
+                ui.label("level:");
+                ui.add(egui::Slider::new(&mut self.level, -1f32..=1f32));
+            }
+            if self.mode == TriggerMode::Single {
+                let text = if self.armed { "waiting" } else { "arm" };
+                if ui
+                    .add(egui::Button::new(text).enabled(!self.armed))
+                    .clicked()
+                {
+                    self.arm();
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("timebase:");
+            ui.add(
+                egui::Slider::new(&mut self.timebase_ms, MIN_TIMEBASE_MS..=MAX_TIMEBASE_MS)
+                    .logarithmic(true)
+                    .suffix(" ms"),
+            );
+            ui.checkbox(&mut self.xy, "xy");
+        });
+
+        let width = ui.available_width().min(300.);
+        if self.xy {
+            ui.add(
+                Plot::new("xy scope")
+                    .include_x(-1.)
+                    .include_x(1.)
+                    .include_y(-1.)
+                    .include_y(1.)
+                    .line(
+                        Line::new(Values::from_values_iter(self.capture.iter().map(
+                            |[left, right]| Value {
+                                x: *left as f64,
+                                y: *right as f64,
+                            },
+                        )))
+                        .color(LEFT_COLOR),
+                    )
+                    .width(width)
+                    .data_aspect(1.)
+                    .view_aspect(1.),
+            );
+        } else {
+            let channel_line = |channel: usize, color| {
+                Line::new(Values::from_values_iter(
+                    self.capture.iter().enumerate().map(|(x, frame)| Value {
+                        x: x as f64,
+                        y: frame[channel] as f64,
+                    }),
+                ))
+                .color(color)
+            };
+            ui.add(
+                Plot::new("waveform")
+                    .include_y(-1.)
+                    .include_y(1.)
+                    .include_x(0.)
+                    .include_x(self.capture.len().max(1) as f32)
+                    .line(channel_line(0, LEFT_COLOR))
+                    .line(channel_line(1, RIGHT_COLOR))
+                    .width(width)
+                    .view_aspect(2.0),
+            );
+        }
+    }
+
+    fn arm(&mut self) {
+        self.armed = true;
+        self.fresh = 0;
+    }
+}
@@ -0,0 +1,697 @@
+use crate::param::{Param, ParamSet};
+use crate::transport::TransportState;
+use anyhow::{anyhow, bail, Result};
+use crossbeam::{atomic::AtomicCell, queue::ArrayQueue};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use wmidi::MidiMessage;
+
+pub const NUM_PATTERNS: usize = 8;
+pub const STEP_COUNTS: [usize; 2] = [16, 32];
+const STEPS_PER_QUARTER: f64 = 4.;
+/// per step when polyphonic, so the audio thread never has more to track
+pub const MAX_STEP_NOTES: usize = 8;
+const PATTERN_NAMES: &[&str] = &["1", "2", "3", "4", "5", "6", "7", "8"];
+const PATTERN_PREFIX: &str = "pattern ";
+/// notes recorded faster than the ui picks them up are dropped after this many
+const MAX_RECORDED: usize = 64;
+/// copies of the patterns on their way to the sequencer and back, the ui only sends one at a time
+const MAX_PATTERN_UPDATES: usize = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub notes: Vec<wmidi::Note>,
+    pub velocity: u8,
+    /// fraction of the step the notes are held for
+    pub gate: f32,
+    /// keeps the previous step's notes going instead of playing this one's
+    pub tie: bool,
+    /// chance of the step playing, from 0 to 1
+    pub probability: f32,
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self {
+            notes: vec![],
+            velocity: 100,
+            gate: 0.5,
+            tie: false,
+            probability: 1.,
+        }
+    }
+}
+
+/// A note played while recording, for the ui to put in its step since that allocates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecordedNote {
+    pub pattern: usize,
+    pub step: usize,
+    pub note: wmidi::Note,
+    pub velocity: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    pub steps: Vec<Step>,
+    pub channel: wmidi::Channel,
+    /// otherwise only the first note of each step plays
+    pub polyphonic: bool,
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Self {
+            steps: vec![Step::default(); STEP_COUNTS[0]],
+            channel: wmidi::Channel::Ch1,
+            polyphonic: false,
+        }
+    }
+}
+
+/// The sequencer's patterns.
+///
+/// Saved as patch values, leaving out empty steps:
+///
+/// ```text
+/// pattern 1 = steps 16 channel 1 poly off
+/// pattern 1 step 5 = notes 60 64 velocity 100 gate 0.5 tie off probability 1
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Patterns {
+    patterns: Vec<Pattern>,
+}
+
+impl Default for Patterns {
+    fn default() -> Self {
+        Self {
+            patterns: vec![Pattern::default(); NUM_PATTERNS],
+        }
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+/// From 0 to 1, by xorshift.
+fn next_random(random: &mut u32) -> f32 {
+    *random ^= *random << 13;
+    *random ^= *random >> 17;
+    *random ^= *random << 5;
+    *random as f32 / u32::MAX as f32
+}
+
+/// Splits `key value key value ...`, calling `set` for each pair.
+fn parse_pairs(text: &str, mut set: impl FnMut(&str, &str) -> Result<()>) -> Result<()> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut index = 0;
+    while index < words.len() {
+        let key = words[index];
+        // notes take every number up to the next key
+        let count = if key == "notes" {
+            words[index + 1..]
+                .iter()
+                .take_while(|word| word.parse::<u8>().is_ok())
+                .count()
+        } else {
+            1
+        };
+        if index + count >= words.len() && count > 0 {
+            bail!("missing value in sequencer: {}", text);
+        }
+        set(key, &words[index + 1..=index + count].join(" "))?;
+        index += count + 1;
+    }
+    Ok(())
+}
+
+impl Patterns {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, index: usize) -> &Pattern {
+        &self.patterns[index]
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> &mut Pattern {
+        &mut self.patterns[index]
+    }
+
+    /// Adds a note played while recording to its step, on top of what's there.
+    pub fn record(&mut self, recorded: &RecordedNote) {
+        let step = match self
+            .patterns
+            .get_mut(recorded.pattern)
+            .and_then(|pattern| pattern.steps.get_mut(recorded.step))
+        {
+            Some(step) => step,
+            None => return,
+        };
+        step.tie = false;
+        step.velocity = recorded.velocity;
+        if !step.notes.contains(&recorded.note) && step.notes.len() < MAX_STEP_NOTES {
+            step.notes.push(recorded.note);
+        }
+    }
+
+    pub fn to_values(&self) -> Vec<(String, String)> {
+        let mut values = vec![];
+        for (index, pattern) in self.patterns.iter().enumerate() {
+            let name = format!("{}{}", PATTERN_PREFIX, index + 1);
+            values.push((
+                name.clone(),
+                format!(
+                    "steps {} channel {} poly {}",
+                    pattern.steps.len(),
+                    pattern.channel.number(),
+                    on_off(pattern.polyphonic)
+                ),
+            ));
+            for (step_index, step) in pattern.steps.iter().enumerate() {
+                if *step == Step::default() {
+                    continue;
+                }
+                let notes: Vec<String> = step
+                    .notes
+                    .iter()
+                    .map(|note| u8::from(*note).to_string())
+                    .collect();
+                values.push((
+                    format!("{} step {}", name, step_index + 1),
+                    format!(
+                        "notes {} velocity {} gate {} tie {} probability {}",
+                        notes.join(" "),
+                        step.velocity,
+                        step.gate,
+                        on_off(step.tie),
+                        step.probability
+                    ),
+                ));
+            }
+        }
+        values
+    }
+
+    pub fn from_values(values: &[(String, String)]) -> Result<Self> {
+        let mut patterns = Self::new();
+        for (key, value) in values {
+            let bad_key = || anyhow!("bad sequencer key: {}", key);
+            let numbers: Vec<usize> = key
+                .strip_prefix(PATTERN_PREFIX)
+                .ok_or_else(bad_key)?
+                .split(" step ")
+                .map(|number| number.trim().parse::<usize>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| bad_key())?;
+            let pattern = match numbers.first() {
+                Some(index) if (1..=NUM_PATTERNS).contains(index) => {
+                    &mut patterns.patterns[index - 1]
+                }
+                _ => return Err(bad_key()),
+            };
+            let bad_value = |name: &str, text: &str| anyhow!("bad value for {}: {}", name, text);
+            let on_off = |name: &str, text: &str| match text {
+                "on" => Ok(true),
+                "off" => Ok(false),
+                _ => Err(bad_value(name, text)),
+            };
+            match numbers[1..] {
+                [] => parse_pairs(value, |name, text| {
+                    match name {
+                        "steps" => {
+                            let count = text
+                                .parse()
+                                .ok()
+                                .filter(|count| STEP_COUNTS.contains(count))
+                                .ok_or_else(|| bad_value(name, text))?;
+                            pattern.steps.resize(count, Step::default());
+                        }
+                        "channel" => {
+                            let number: u8 = text.parse().map_err(|_| bad_value(name, text))?;
+                            pattern.channel = wmidi::Channel::from_index(number.wrapping_sub(1))
+                                .map_err(|_| bad_value(name, text))?;
+                        }
+                        "poly" => pattern.polyphonic = on_off(name, text)?,
+                        // newer versions may know more
+                        _ => {}
+                    }
+                    Ok(())
+                })?,
+                [step] if (1..=pattern.steps.len()).contains(&step) => {
+                    let step = &mut pattern.steps[step - 1];
+                    parse_pairs(value, |name, text| {
+                        let number = || text.parse::<f32>().map_err(|_| bad_value(name, text));
+                        match name {
+                            "notes" => {
+                                step.notes = text
+                                    .split_whitespace()
+                                    .filter_map(|note| note.parse::<u8>().ok())
+                                    .map(wmidi::Note::from_u8_lossy)
+                                    .take(MAX_STEP_NOTES)
+                                    .collect()
+                            }
+                            "velocity" => step.velocity = number()?.clamp(1., 127.) as u8,
+                            "gate" => step.gate = number()?.clamp(0.05, 1.),
+                            "tie" => step.tie = on_off(name, text)?,
+                            "probability" => step.probability = number()?.clamp(0., 1.),
+                            _ => {}
+                        }
+                        Ok(())
+                    })?
+                }
+                _ => return Err(bad_key()),
+            }
+        }
+        Ok(patterns)
+    }
+}
+
+/// A copy of the patterns for the sequencer to play, numbered so an older one never replaces a newer one.
+pub struct PatternsUpdate {
+    generation: u64,
+    patterns: Patterns,
+}
+
+/// The ui's copy of the patterns, which the sequencer is sent a copy of after each change.
+pub struct PatternsEditor {
+    params: Arc<SequencerParams>,
+    patterns: Patterns,
+    generation: u64,
+}
+
+impl PatternsEditor {
+    fn new(params: Arc<SequencerParams>) -> Self {
+        Self {
+            params,
+            patterns: Patterns::new(),
+            generation: 0,
+        }
+    }
+
+    pub fn get(&self) -> &Patterns {
+        &self.patterns
+    }
+
+    /// For changing the patterns, which `publish` sends on.
+    pub fn get_mut(&mut self) -> &mut Patterns {
+        self.generation += 1;
+        &mut self.patterns
+    }
+
+    /// Replaces the patterns, returning a copy for the sequencer to switch to along with the rest of a patch.
+    pub fn load(&mut self, patterns: Patterns) -> PatternsUpdate {
+        self.generation += 1;
+        self.patterns = patterns;
+        PatternsUpdate {
+            generation: self.generation,
+            patterns: self.patterns.clone(),
+        }
+    }
+
+    /// Sends the sequencer a copy when it's behind, and drops the ones it's done with.
+    /// Allocates, so not for the audio thread.
+    pub fn publish(&mut self) {
+        let params = &self.params;
+        while let Some(retired) = params.retired.pop() {
+            drop(retired);
+        }
+        // comparing with what's playing rather than what was sent also catches up a sequencer
+        // that started over, e.g. when the audio stream is rebuilt
+        if params.generation.load() >= self.generation || !params.updates.is_empty() {
+            return;
+        }
+        let _ignore = params.updates.push(PatternsUpdate {
+            generation: self.generation,
+            patterns: self.patterns.clone(),
+        });
+    }
+}
+
+pub struct SequencerParams {
+    /// plays along while the transport is running
+    pub playing: Param,
+    /// how far every second step is pushed back, as a fraction of a step
+    pub swing: Param,
+    /// switched to at the next bar
+    pub pattern: Param,
+    /// pattern and step being played, for the ui
+    pub playhead: AtomicCell<Option<(usize, usize)>>,
+    pub recorded: ArrayQueue<RecordedNote>,
+    /// sent by the ui, swapped in at the start of a block
+    updates: ArrayQueue<PatternsUpdate>,
+    /// swapped out, for the ui to drop
+    retired: ArrayQueue<PatternsUpdate>,
+    /// of the patterns playing
+    generation: AtomicCell<u64>,
+}
+
+impl SequencerParams {
+    fn new() -> Self {
+        Self {
+            playing: Param::toggle("sequencer", false),
+            swing: Param::new("swing", "", 0., 0f32..=0.5f32),
+            pattern: Param::choice("pattern", 0, PATTERN_NAMES),
+            playhead: AtomicCell::new(None),
+            recorded: ArrayQueue::new(MAX_RECORDED),
+            updates: ArrayQueue::new(MAX_PATTERN_UPDATES),
+            retired: ArrayQueue::new(MAX_PATTERN_UPDATES),
+            generation: AtomicCell::new(0),
+        }
+    }
+}
+
+impl ParamSet for SequencerParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![&self.playing, &self.swing, &self.pattern]
+    }
+}
+
+/// Plays patterns of 16th notes, on the audio thread next to the arpeggiator.
+///
+/// Plays its own copy of the patterns so it never waits for the ui editing them.
+pub struct Sequencer {
+    params: Arc<SequencerParams>,
+    patterns: Patterns,
+    generation: u64,
+    editor: Arc<Mutex<PatternsEditor>>,
+    playing: bool,
+    current: usize,
+    /// in steps since the start of the song
+    position: f64,
+    next_step: u64,
+    /// index of the next step within the pattern
+    step_index: usize,
+    /// channel, note and the position it ends at
+    sounding: Vec<(wmidi::Channel, wmidi::Note, f64)>,
+    random: u32,
+}
+
+// derived clone would lose the room reserved for the notes, and the synth is cloned for each stream
+impl Clone for Sequencer {
+    fn clone(&self) -> Self {
+        let mut sounding = Vec::with_capacity(MAX_STEP_NOTES);
+        sounding.extend_from_slice(&self.sounding);
+        Self {
+            params: self.params.clone(),
+            patterns: self.patterns.clone(),
+            generation: self.generation,
+            editor: self.editor.clone(),
+            playing: self.playing,
+            current: self.current,
+            position: self.position,
+            next_step: self.next_step,
+            step_index: self.step_index,
+            sounding,
+            random: self.random,
+        }
+    }
+}
+
+impl Sequencer {
+    pub fn new() -> Self {
+        let params = Arc::new(SequencerParams::new());
+        Self {
+            editor: Arc::new(Mutex::new(PatternsEditor::new(params.clone()))),
+            params,
+            patterns: Patterns::new(),
+            generation: 0,
+            playing: false,
+            current: 0,
+            position: 0.,
+            next_step: 0,
+            step_index: 0,
+            sounding: Vec::with_capacity(MAX_STEP_NOTES),
+            random: 0x8765_4321,
+        }
+    }
+
+    pub fn get_params(&self) -> Arc<SequencerParams> {
+        self.params.clone()
+    }
+
+    pub fn get_patterns(&self) -> Arc<Mutex<PatternsEditor>> {
+        self.editor.clone()
+    }
+
+    /// Switches to the patterns in `update` unless newer ones are playing already,
+    /// leaving whichever aren't used in it to be dropped off the audio thread.
+    pub fn swap_patterns(&mut self, update: &mut PatternsUpdate) {
+        if update.generation > self.generation {
+            std::mem::swap(&mut self.patterns, &mut update.patterns);
+            std::mem::swap(&mut self.generation, &mut update.generation);
+        }
+    }
+
+    fn step_position(&self, step: u64) -> f64 {
+        step as f64 + (step % 2) as f64 * self.params.swing.get() as f64
+    }
+
+    /// Notes played while recording go to the nearest step of the pattern playing.
+    /// Called from the audio thread, so the ui adds them to the pattern.
+    pub fn record(&self, transport: &TransportState, message: &MidiMessage<'_>) {
+        let (note, velocity) = match message {
+            MidiMessage::NoteOn(_, note, velocity) if u8::from(*velocity) > 0 => (*note, *velocity),
+            _ => return,
+        };
+        if !transport.recording {
+            return;
+        }
+        let nearest = (transport.end * STEPS_PER_QUARTER).round() as i64;
+        let patterns = &self.patterns;
+        let (pattern, step) = if self.playing {
+            // counted from the step coming up, since switching patterns starts them over
+            let length = patterns.get(self.current).steps.len() as i64;
+            let step = self.step_index as i64 + nearest - self.next_step as i64;
+            (self.current, step.rem_euclid(length) as usize)
+        } else {
+            let pattern = self.params.pattern.get_index();
+            let length = patterns.get(pattern).steps.len() as i64;
+            (pattern, nearest.rem_euclid(length) as usize)
+        };
+        let _ignore = self.params.recorded.push(RecordedNote {
+            pattern,
+            step,
+            note,
+            velocity: u8::from(velocity),
+        });
+    }
+
+    /// Plays the next `frames`, calling `emit` with the frame offset of each message.
+    /// Called from the audio thread, so doesn't allocate.
+    pub fn run(
+        &mut self,
+        transport: &TransportState,
+        frames: usize,
+        mut emit: impl FnMut(usize, MidiMessage<'static>),
+    ) {
+        while let Some(mut update) = self.params.updates.pop() {
+            self.swap_patterns(&mut update);
+            // the ui empties this every frame, if it's full anyway the copy is dropped here
+            let _ignore = self.params.retired.push(update);
+        }
+        // every block, since a copy of the sequencer on a new audio stream starts from its own patterns
+        self.params.generation.store(self.generation);
+        if !self.params.playing.is_on() || !transport.playing {
+            if self.playing {
+                for (channel, note, _) in self.sounding.drain(..) {
+                    emit(0, MidiMessage::NoteOff(channel, note, wmidi::U7::MIN));
+                }
+                self.params.playhead.store(None);
+                self.playing = false;
+            }
+            return;
+        }
+        let start = transport.position * STEPS_PER_QUARTER;
+        let end = transport.end * STEPS_PER_QUARTER;
+        // patterns switch at the start of a bar, whatever the time signature
+        let steps_per_bar = transport.steps_per_bar(STEPS_PER_QUARTER);
+        if !self.playing || start != self.position {
+            // started, or the song position jumped. count steps from the start of the song
+            // so the bars line up with everything else following the transport
+            self.playing = true;
+            self.next_step = start.ceil() as u64;
+            self.current = self.params.pattern.get_index();
+            self.step_index = self.next_step as usize % self.patterns.get(self.current).steps.len();
+        }
+        // can be 0 while waiting for the midi clock, in which case nothing plays
+        let steps_per_frame = (end - start) / frames as f64;
+        let offset = |position: f64| {
+            (((position - start) / steps_per_frame).round().max(0.) as usize)
+                .min(frames.saturating_sub(1))
+        };
+        loop {
+            let step_position = self.step_position(self.next_step);
+            self.sounding.retain(|&(channel, note, off)| {
+                if off <= step_position && off < end {
+                    emit(
+                        offset(off),
+                        MidiMessage::NoteOff(channel, note, wmidi::U7::MIN),
+                    );
+                    false
+                } else {
+                    true
+                }
+            });
+            if step_position >= end {
+                break;
+            }
+            let step_in_bar = self.next_step % steps_per_bar;
+            let queued = self.params.pattern.get_index();
+            if step_in_bar == 0 && queued != self.current {
+                self.current = queued;
+                self.step_index = 0;
+            }
+            let pattern = self.patterns.get(self.current);
+            self.step_index %= pattern.steps.len();
+            let step = &pattern.steps[self.step_index];
+            let next = self.step_position(self.next_step + 1);
+            // a tie after this step holds the notes until it decides how long they go on
+            let off = if pattern.steps[(self.step_index + 1) % pattern.steps.len()].tie {
+                f64::INFINITY
+            } else {
+                step_position + step.gate as f64 * (next - step_position)
+            };
+            if step.tie {
+                for sounding in self.sounding.iter_mut() {
+                    sounding.2 = off;
+                }
+            } else {
+                for (channel, note, _) in self.sounding.drain(..) {
+                    emit(
+                        offset(step_position),
+                        MidiMessage::NoteOff(channel, note, wmidi::U7::MIN),
+                    );
+                }
+                if !step.notes.is_empty() && next_random(&mut self.random) < step.probability {
+                    let count = if pattern.polyphonic {
+                        MAX_STEP_NOTES
+                    } else {
+                        1
+                    };
+                    let velocity = wmidi::U7::from_u8_lossy(step.velocity);
+                    for note in step.notes.iter().take(count) {
+                        emit(
+                            offset(step_position),
+                            MidiMessage::NoteOn(pattern.channel, *note, velocity),
+                        );
+                        self.sounding.push((pattern.channel, *note, off));
+                    }
+                }
+            }
+            self.params
+                .playhead
+                .store(Some((self.current, self.step_index)));
+            self.step_index += 1;
+            self.next_step += 1;
+        }
+        self.position = end;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Patterns, Sequencer};
+    use crate::transport::TransportState;
+    use wmidi::{Channel, MidiMessage, Note, U7};
+
+    #[test]
+    fn steps_and_pattern_switch() {
+        let mut sequencer = Sequencer::new();
+        let params = sequencer.get_params();
+        {
+            let editor = sequencer.get_patterns();
+            let mut editor = editor.lock();
+            let patterns = editor.get_mut();
+            let pattern = patterns.get_mut(0);
+            pattern.steps[0].notes = vec![Note::C4];
+            // tied over the next step
+            pattern.steps[1].tie = true;
+            pattern.steps[1].gate = 1.;
+            pattern.steps[2].notes = vec![Note::D4];
+            pattern.steps[2].probability = 0.;
+            patterns.get_mut(1).steps[0].notes = vec![Note::E4];
+            editor.publish();
+        }
+        params.playing.set(1.);
+        params.pattern.set(1.);
+        let mut transport = TransportState {
+            playing: true,
+            ..TransportState::default()
+        };
+        let mut run = |steps: usize| {
+            let mut events = vec![];
+            // 16th steps at 120 bpm are 6000 samples
+            transport.end = transport.position + steps as f64 / 4.;
+            sequencer.run(&transport, 6000 * steps, |offset, message| {
+                events.push((offset, message))
+            });
+            transport.position = transport.end;
+            events
+        };
+        let velocity = U7::from_u8_lossy(100);
+        assert_eq!(
+            vec![
+                (0, MidiMessage::NoteOn(Channel::Ch1, Note::E4, velocity)),
+                (3000, MidiMessage::NoteOff(Channel::Ch1, Note::E4, U7::MIN)),
+            ],
+            run(8)
+        );
+        // switching waits for the next bar
+        params.pattern.set(0.);
+        assert!(run(8).is_empty());
+        assert_eq!(
+            vec![
+                (0, MidiMessage::NoteOn(Channel::Ch1, Note::C4, velocity)),
+                (12000, MidiMessage::NoteOff(Channel::Ch1, Note::C4, U7::MIN)),
+            ],
+            run(16)
+        );
+
+        let patterns = sequencer.patterns.clone();
+        assert_eq!(
+            patterns,
+            Patterns::from_values(&patterns.to_values()).unwrap()
+        );
+    }
+
+    #[test]
+    fn switches_on_bars_of_the_time_signature() {
+        let mut sequencer = Sequencer::new();
+        let params = sequencer.get_params();
+        {
+            let editor = sequencer.get_patterns();
+            let mut editor = editor.lock();
+            editor.get_mut().get_mut(1).steps[0].notes = vec![Note::E4];
+            editor.publish();
+        }
+        params.playing.set(1.);
+        // a bar of 3/4 is 12 steps
+        let mut transport = TransportState {
+            playing: true,
+            time_signature: (3, 4),
+            end: 2.,
+            ..TransportState::default()
+        };
+        sequencer.run(&transport, 48000, |_, _| {});
+        params.pattern.set(1.);
+        let mut events = vec![];
+        transport.position = transport.end;
+        transport.end = 4.;
+        sequencer.run(&transport, 48000, |offset, message| {
+            events.push((offset, message))
+        });
+        assert_eq!(
+            Some(&(
+                24000,
+                MidiMessage::NoteOn(Channel::Ch1, Note::E4, U7::from_u8_lossy(100))
+            )),
+            events.first()
+        );
+    }
+}
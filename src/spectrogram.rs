@@ -0,0 +1,173 @@
+use crate::fft::{fft, hann};
+use eframe::{
+    egui::{self, Color32, TextureId},
+    epi::TextureAllocator,
+};
+use std::collections::VecDeque;
+
+const FFT_SIZE: usize = 2048;
+/// texture size, each column is one fft frame
+const WIDTH: usize = 256;
+const HEIGHT: usize = 128;
+const MIN_HZ: f32 = 20.;
+const MIN_DB: f32 = -96.;
+
+/// Black through purple, red and yellow to white.
+fn heat(t: f32) -> Color32 {
+    const STOPS: [[f32; 3]; 5] = [
+        [0., 0., 0.],
+        [80., 20., 140.],
+        [220., 40., 40.],
+        [250., 200., 40.],
+        [255., 255., 255.],
+    ];
+    let position = t.clamp(0., 1.) * (STOPS.len() - 1) as f32;
+    let index = (position as usize).min(STOPS.len() - 2);
+    let fract = position - index as f32;
+    let channel =
+        |c: usize| (STOPS[index][c] + (STOPS[index + 1][c] - STOPS[index][c]) * fract) as u8;
+    Color32::from_rgb(channel(0), channel(1), channel(2))
+}
+
+/// Scrolling image of the spectrum over the last few seconds.
+///
+/// Like the spectrum analyzer, the ffts are computed on the ui thread from the visualization buffer.
+pub struct Spectrogram {
+    seconds: f32,
+    window: Vec<f32>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    /// first and last fft bin for each row, from the bottom
+    row_bins: Vec<(usize, usize)>,
+    sample_rate: u32,
+    /// levels in dB, one column after the other
+    levels: Vec<f32>,
+    next_column: usize,
+    /// frames received since the last column
+    pending: usize,
+    texture: Option<TextureId>,
+    dirty: bool,
+}
+
+impl Spectrogram {
+    pub fn new() -> Self {
+        Self {
+            seconds: 10.,
+            window: hann(FFT_SIZE),
+            re: vec![0.; FFT_SIZE],
+            im: vec![0.; FFT_SIZE],
+            row_bins: vec![],
+            sample_rate: 0,
+            levels: vec![MIN_DB; WIDTH * HEIGHT],
+            next_column: 0,
+            pending: 0,
+            texture: None,
+            dirty: true,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.levels.fill(MIN_DB);
+        self.pending = 0;
+        self.dirty = true;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        let nyquist = sample_rate as f32 / 2.;
+        let bin_hz = sample_rate as f32 / FFT_SIZE as f32;
+        let hz_for_row = |row: usize| MIN_HZ * (nyquist / MIN_HZ).powf(row as f32 / HEIGHT as f32);
+        self.row_bins = (0..HEIGHT)
+            .map(|row| {
+                let first = (hz_for_row(row) / bin_hz) as usize;
+                let last = ((hz_for_row(row + 1) / bin_hz) as usize).clamp(first, FFT_SIZE / 2 - 1);
+                (first, last)
+            })
+            .collect();
+        self.clear();
+    }
+
+    /// Adds a column for the frames in `history` ending at `end`.
+    fn add_column(&mut self, history: &VecDeque<[f32; 2]>, end: Option<usize>) {
+        let range = self.next_column * HEIGHT..(self.next_column + 1) * HEIGHT;
+        self.next_column = (self.next_column + 1) % WIDTH;
+        let end = match end {
+            Some(end) if end >= FFT_SIZE => end,
+            // not enough history, leave a gap
+            _ => {
+                self.levels[range].fill(MIN_DB);
+                return;
+            }
+        };
+        for ((re, im), (frame, window)) in self
+            .re
+            .iter_mut()
+            .zip(self.im.iter_mut())
+            .zip(history.range(end - FFT_SIZE..end).zip(self.window.iter()))
+        {
+            *re = (frame[0] + frame[1]) * 0.5 * window;
+            *im = 0.;
+        }
+        fft(&mut self.re, &mut self.im);
+        let scale = 2. / self.window.iter().sum::<f32>();
+        let (re, im) = (&self.re, &self.im);
+        for (level, &(first, last)) in self.levels[range].iter_mut().zip(self.row_bins.iter()) {
+            // the loudest bin wins where several share a row
+            let magnitude = (first..=last)
+                .map(|k| (re[k] * re[k] + im[k] * im[k]).sqrt())
+                .fold(0f32, f32::max)
+                * scale;
+            *level = (20. * magnitude.max(1e-9).log10()).max(MIN_DB);
+        }
+    }
+
+    /// Analyzes the frames that arrived since the last call, `new_frames` of which are at the end of `history`.
+    pub fn update(&mut self, history: &VecDeque<[f32; 2]>, new_frames: usize, sample_rate: u32) {
+        if sample_rate != self.sample_rate {
+            self.set_sample_rate(sample_rate);
+        }
+        let hop = ((self.seconds * sample_rate as f32) as usize / WIDTH).max(1);
+        self.pending += new_frames;
+        let columns = self.pending / hop;
+        self.pending %= hop;
+        // if the ui stalled for a long time only the latest columns matter
+        for column in (0..columns.min(WIDTH)).rev() {
+            let end = history.len().checked_sub(self.pending + column * hop);
+            self.add_column(history, end);
+            self.dirty = true;
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, tex_allocator: &mut dyn TextureAllocator) {
+        ui.horizontal(|ui| {
+            ui.label("length:");
+            if ui
+                .add(egui::Slider::new(&mut self.seconds, 2f32..=30f32).suffix(" s"))
+                .changed()
+            {
+                self.clear();
+            }
+        });
+        if self.dirty || self.texture.is_none() {
+            let mut pixels = Vec::with_capacity(WIDTH * HEIGHT);
+            // highest frequency at the top, oldest column to the left
+            for row in (0..HEIGHT).rev() {
+                for x in 0..WIDTH {
+                    let column = (self.next_column + x) % WIDTH;
+                    let level = self.levels[column * HEIGHT + row];
+                    pixels.push(heat(1. - level / MIN_DB));
+                }
+            }
+            // textures can't be changed, only replaced
+            if let Some(texture) = self.texture.take() {
+                tex_allocator.free(texture);
+            }
+            self.texture = Some(tex_allocator.alloc_srgba_premultiplied((WIDTH, HEIGHT), &pixels));
+            self.dirty = false;
+        }
+        if let Some(texture) = self.texture {
+            let width = ui.available_width().min(600.);
+            ui.add(egui::Image::new(texture, egui::vec2(width, width / 3.)));
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use crate::fft::{fft, hann};
+use eframe::egui::{self, Align2, Color32, Pos2, Sense, Shape, Stroke, TextStyle};
+use std::collections::VecDeque;
+
+const FFT_SIZES: [usize; 4] = [1024, 2048, 4096, 8192];
+const MIN_HZ: f32 = 20.;
+const MIN_DB: f32 = -96.;
+const GRID_HZ: [f32; 3] = [100., 1000., 10000.];
+const GRID_DB_STEP: f32 = 24.;
+
+/// Windowed fft of the latest output, drawn with a log frequency axis.
+///
+/// Runs on the ui thread from the visualization buffer, so it doesn't cost the audio thread anything.
+pub struct SpectrumAnalyzer {
+    size_index: usize,
+    smoothing: f32,
+    peak_hold: bool,
+    window: Vec<f32>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    /// smoothed level per bin in dB
+    levels: Vec<f32>,
+    peaks: Vec<f32>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new() -> Self {
+        let mut s = Self {
+            size_index: 2,
+            smoothing: 0.5,
+            peak_hold: false,
+            window: vec![],
+            re: vec![],
+            im: vec![],
+            levels: vec![],
+            peaks: vec![],
+        };
+        s.resize();
+        s
+    }
+
+    fn size(&self) -> usize {
+        FFT_SIZES[self.size_index]
+    }
+
+    fn resize(&mut self) {
+        let size = self.size();
+        self.window = hann(size);
+        self.re = vec![0.; size];
+        self.im = vec![0.; size];
+        self.levels = vec![MIN_DB; size / 2];
+        self.reset_peaks();
+    }
+
+    fn reset_peaks(&mut self) {
+        self.peaks = vec![MIN_DB; self.size() / 2];
+    }
+
+    /// Analyzes the mid channel of the newest frames in `history`.
+    pub fn update(&mut self, history: &VecDeque<[f32; 2]>) {
+        let size = self.size();
+        if history.len() < size {
+            return;
+        }
+        for ((re, im), (frame, window)) in self.re.iter_mut().zip(self.im.iter_mut()).zip(
+            history
+                .iter()
+                .skip(history.len() - size)
+                .zip(self.window.iter()),
+        ) {
+            *re = (frame[0] + frame[1]) * 0.5 * window;
+            *im = 0.;
+        }
+        fft(&mut self.re, &mut self.im);
+        // scaled so a full scale sine reads 0dB
+        let scale = 2. / self.window.iter().sum::<f32>();
+        for (k, level) in self.levels.iter_mut().enumerate() {
+            let magnitude = (self.re[k] * self.re[k] + self.im[k] * self.im[k]).sqrt() * scale;
+            let db = (20. * magnitude.max(1e-9).log10()).max(MIN_DB);
+            *level = *level * self.smoothing + db * (1. - self.smoothing);
+        }
+        if self.peak_hold {
+            for (peak, level) in self.peaks.iter_mut().zip(self.levels.iter()) {
+                *peak = peak.max(*level);
+            }
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, sample_rate: Option<u32>) {
+        ui.horizontal(|ui| {
+            ui.label("size:");
+            let mut size_index = self.size_index;
+            egui::ComboBox::from_id_source("spectrum size combo box").show_index(
+                ui,
+                &mut size_index,
+                FFT_SIZES.len(),
+                |i| FFT_SIZES[i].to_string(),
+            );
+            if size_index != self.size_index {
+                self.size_index = size_index;
+                self.resize();
+            }
+            ui.label("smoothing:");
+            ui.add(egui::Slider::new(&mut self.smoothing, 0f32..=0.99f32));
+            if ui.checkbox(&mut self.peak_hold, "peak hold").changed() {
+                self.reset_peaks();
+            }
+            if ui.small_button("reset").clicked() {
+                self.reset_peaks();
+            }
+        });
+
+        let width = ui.available_width().min(600.);
+        let (response, painter) =
+            ui.allocate_painter(egui::vec2(width, width / 3.), Sense::hover());
+        let rect = response.rect;
+        painter.rect_filled(rect, 0., ui.visuals().extreme_bg_color);
+        let sample_rate = match sample_rate {
+            Some(sample_rate) => sample_rate as f32,
+            None => return,
+        };
+        let nyquist = sample_rate / 2.;
+        let x_for_hz =
+            |hz: f32| rect.left() + rect.width() * (hz / MIN_HZ).ln() / (nyquist / MIN_HZ).ln();
+        let y_for_db = |db: f32| rect.top() + rect.height() * (db / MIN_DB).clamp(0., 1.);
+
+        let grid_color = ui.visuals().weak_text_color();
+        let grid_stroke = Stroke::new(0.5, grid_color);
+        for &hz in GRID_HZ.iter().filter(|&&hz| hz < nyquist) {
+            let x = x_for_hz(hz);
+            painter.line_segment(
+                [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+                grid_stroke,
+            );
+            let label = if hz >= 1000. {
+                format!("{}k", hz / 1000.)
+            } else {
+                format!("{}", hz)
+            };
+            painter.text(
+                Pos2::new(x + 2., rect.bottom()),
+                Align2::LEFT_BOTTOM,
+                label,
+                TextStyle::Small,
+                grid_color,
+            );
+        }
+        let mut db = 0.;
+        while db > MIN_DB {
+            let y = y_for_db(db);
+            painter.line_segment(
+                [Pos2::new(rect.left(), y), Pos2::new(rect.right(), y)],
+                grid_stroke,
+            );
+            painter.text(
+                Pos2::new(rect.left() + 2., y),
+                Align2::LEFT_TOP,
+                format!("{} dB", db),
+                TextStyle::Small,
+                grid_color,
+            );
+            db -= GRID_DB_STEP;
+        }
+
+        let bin_hz = sample_rate / self.size() as f32;
+        let curve = |levels: &[f32]| {
+            // many bins end up on the same pixel at the top end, only keep the loudest
+            let mut points: Vec<Pos2> = vec![];
+            for (k, level) in levels.iter().enumerate().skip(1) {
+                let hz = k as f32 * bin_hz;
+                if hz < MIN_HZ {
+                    continue;
+                }
+                let point = Pos2::new(x_for_hz(hz).round(), y_for_db(*level));
+                match points.last_mut() {
+                    Some(last) if last.x == point.x => last.y = last.y.min(point.y),
+                    _ => points.push(point),
+                }
+            }
+            points
+        };
+        if self.peak_hold {
+            painter.add(Shape::line(
+                curve(&self.peaks),
+                Stroke::new(1., Color32::from_rgb(200, 120, 40)),
+            ));
+        }
+        painter.add(Shape::line(
+            curve(&self.levels),
+            Stroke::new(1.5, ui.visuals().text_color()),
+        ));
+    }
+}
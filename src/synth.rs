@@ -1,33 +1,768 @@
 use std::{f32::consts::PI, sync::Arc};
 
-use crossbeam::{atomic::AtomicCell, channel};
+use crate::arpeggiator::{ArpParams, Arpeggiator};
+use crate::effects::{db_to_gain, EffectChain, Shape, Shaper, ShaperParams, Slot};
+use crate::midi::MidiQueue;
+use crate::midi_map::MidiMap;
+use crate::param::{Param, ParamSet};
+use crate::patch::Patch;
+use crate::presets::ProgramChange;
+use crate::sequencer::{Patterns, PatternsEditor, PatternsUpdate, Sequencer, SequencerParams};
+use crate::transport::{Transport, TransportParams, TransportState};
+use crate::zones::Zones;
+use anyhow::Result;
+use crossbeam::{atomic::AtomicCell, channel, queue::ArrayQueue};
+use parking_lot::Mutex;
 use wmidi::MidiMessage;
 
 // super simple synth
 // TODO make interesting
 
+const MAX_VOICES: usize = 16;
+/// one per midi channel
+pub const NUM_PARTS: usize = 16;
+const PITCH_BEND_SEMITONES: f32 = 2.;
+/// how much louder full aftertouch makes a note
+const AFTERTOUCH_DB: f32 = 6.;
+/// time constant for following aftertouch, which only arrives every so often
+const AFTERTOUCH_SMOOTHING_SECONDS: f32 = 0.005;
+/// parts render into this before being mixed, bigger blocks are done in pieces
+const PART_BUFFER_FRAMES: usize = 4096;
+/// arpeggiator and sequencer notes per block, more than they could sensibly play
+const MAX_EVENTS: usize = 256;
+/// keys held in mono mode, one of each
+const MAX_HELD: usize = 128;
+/// waiting to be switched to, a program change on every channel at once
+const MAX_PREPARED_PROGRAMS: usize = NUM_PARTS;
+const NUM_PARAMS: usize = 13;
+
+const MODE_NAMES: &[&str] = &["poly", "mono", "legato"];
+const PRIORITY_NAMES: &[&str] = &["last", "low", "high"];
+/// glide the same time whatever the interval, or at the same rate, taking the time per octave
+const GLIDE_MODE_NAMES: &[&str] = &["time", "rate"];
+const MAX_UNISON: usize = 16;
+const UNISON_NAMES: &[&str] = &[
+    "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14", "15", "16",
+];
+
 type MidiChannel = channel::Receiver<MidiMessage<'static>>;
 
 #[derive(Clone)]
 struct NoteEvent {
+    /// the key that was played, before transposing
+    key: wmidi::Note,
     note: wmidi::Note,
     velocity: wmidi::U7,
+    /// from the zone's fade range
+    level: f32,
     pressed: u64,
     released: Option<u64>,
+    /// let go of but held by the sustain pedal
+    sustained: bool,
+}
+
+/// A key held down in mono mode, to go back to when the one playing is let go.
+#[derive(Clone, Copy)]
+struct HeldNote {
+    key: wmidi::Note,
+    note: wmidi::Note,
+    velocity: wmidi::U7,
+    level: f32,
+}
+
+#[derive(Clone)]
+struct Voice {
+    event: Option<NoteEvent>,
+    /// per voice and channel so the distortion doesn't intermodulate between notes
+    shapers: [Shaper; 2],
+    /// of each unison copy in cycles, accumulated so the pitch can bend smoothly
+    phases: [f32; MAX_UNISON],
+    /// in semitones, gliding towards the note
+    pitch: f32,
+    /// in semitones per second
+    glide: f32,
+    /// from polyphonic aftertouch, 0 to 1
+    pressure: f32,
+    /// gain following the pressure smoothly
+    pressure_gain: f32,
+}
+
+fn pitch_to_freq(pitch: f32) -> f32 {
+    440. * 2f32.powf((pitch - 69.) / 12.)
+}
+
+/// Tuning and stereo gains of one of the copies a unison voice plays.
+#[derive(Clone, Copy, Default)]
+struct UnisonCopy {
+    ratio: f32,
+    left: f32,
+    right: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoteState {
+    pub velocity: u8,
+    pub sustained: bool,
+}
+
+/// Which notes are sounding, from any source. Written by the audio thread for the ui to show.
+pub struct ActiveNotes {
+    /// velocity with the top bit set if sustained, 0 if not playing
+    notes: Vec<AtomicCell<u8>>,
+}
+
+const SUSTAINED_BIT: u8 = 0x80;
+
+impl ActiveNotes {
+    fn new() -> Self {
+        Self {
+            notes: (0..128).map(|_| AtomicCell::new(0)).collect(),
+        }
+    }
+
+    pub fn get(&self, note: wmidi::Note) -> Option<NoteState> {
+        match self.notes[u8::from(note) as usize].load() {
+            0 => None,
+            value => Some(NoteState {
+                velocity: value & !SUSTAINED_BIT,
+                sustained: value & SUSTAINED_BIT != 0,
+            }),
+        }
+    }
 }
 
 // TODO handle params using messages instead?
 pub struct Params {
-    pub gain: AtomicCell<f32>,
+    pub gain: Param,
+    /// per voice distortion, before velocity and gain
+    pub shaper: ShaperParams,
+    /// poly, or a single voice that retriggers on each note or only when none are held
+    pub mode: Param,
+    /// which of the held keys a single voice plays
+    pub priority: Param,
+    pub glide: Param,
+    pub glide_mode: Param,
+    /// copies of the oscillator each note plays
+    pub unison: Param,
+    /// of the outermost copies
+    pub detune: Param,
+    /// how the copies are spread between those, 1 is even, higher bunches them in the middle
+    pub detune_curve: Param,
+    /// level of the detuned copies next to the middle ones
+    pub blend: Param,
+    /// stereo width of the copies
+    pub spread: Param,
 }
 
-#[derive(Clone)]
+impl Params {
+    fn new() -> Self {
+        Self {
+            gain: Param::new("gain", "", 1., 0f32..=1f32),
+            shaper: ShaperParams::new(Shape::Off, 12.),
+            mode: Param::choice("mode", 0, MODE_NAMES),
+            priority: Param::choice("priority", 0, PRIORITY_NAMES),
+            glide: Param::new("glide", "ms", 0., 0f32..=2000f32),
+            glide_mode: Param::choice("glide mode", 0, GLIDE_MODE_NAMES),
+            unison: Param::choice("unison", 0, UNISON_NAMES),
+            detune: Param::new("detune", "cents", 20., 0f32..=100f32),
+            detune_curve: Param::new("detune curve", "", 1., 0.25f32..=4f32).logarithmic(),
+            blend: Param::new("blend", "", 1., 0f32..=1f32),
+            spread: Param::new("spread", "", 1., 0f32..=1f32),
+        }
+    }
+
+    /// Same as `params` but without allocating, for the audio thread.
+    fn all(&self) -> [&Param; NUM_PARAMS] {
+        [
+            &self.gain,
+            &self.shaper.shape,
+            &self.shaper.drive,
+            &self.shaper.oversampling,
+            &self.mode,
+            &self.priority,
+            &self.glide,
+            &self.glide_mode,
+            &self.unison,
+            &self.detune,
+            &self.detune_curve,
+            &self.blend,
+            &self.spread,
+        ]
+    }
+
+    /// Fills in the copies for the unison count, which it returns.
+    /// Their levels add up to about the same loudness whatever the count.
+    fn unison_copies(&self, copies: &mut [UnisonCopy; MAX_UNISON]) -> usize {
+        let count = self.unison.get_index() + 1;
+        let detune = self.detune.get();
+        let curve = self.detune_curve.get();
+        let blend = self.blend.get();
+        let spread = self.spread.get();
+        // the middle one, or two when there's an even number
+        let middle = if count % 2 == 1 {
+            0.
+        } else {
+            1. / (count - 1) as f32
+        };
+        let mut power = 0.;
+        for (i, copy) in copies.iter_mut().take(count).enumerate() {
+            // from -1 to 1 across the copies
+            let position = if count > 1 {
+                2. * i as f32 / (count - 1) as f32 - 1.
+            } else {
+                0.
+            };
+            let cents = detune * position.abs().powf(curve).copysign(position);
+            let level = if (position.abs() - middle).abs() < 1e-6 {
+                1.
+            } else {
+                blend
+            };
+            let pan = position * spread;
+            copy.ratio = 2f32.powf(cents / 1200.);
+            copy.left = level * (1. - pan).min(1.);
+            copy.right = level * (1. + pan).min(1.);
+            power += level * level;
+        }
+        // the copies aren't in phase, so they add up by power
+        let scale = 1. / power.sqrt();
+        for copy in copies.iter_mut().take(count) {
+            copy.left *= scale;
+            copy.right *= scale;
+        }
+        count
+    }
+
+    /// Semitones per second to get from one pitch to another, infinite when not gliding.
+    fn glide_speed(&self, from: f32, to: f32) -> f32 {
+        let seconds = self.glide.get() / 1000.;
+        if seconds <= 0. {
+            f32::INFINITY
+        } else if GLIDE_MODE_NAMES[self.glide_mode.get_index()] == "time" {
+            (to - from).abs() / seconds
+        } else {
+            12. / seconds
+        }
+    }
+}
+
+impl ParamSet for Params {
+    fn params(&self) -> Vec<&Param> {
+        self.all().to_vec()
+    }
+}
+
+/// Where a part sits in the mix. Kept out of `Params` so patches don't change it.
+pub struct MixerParams {
+    pub volume: Param,
+    pub pan: Param,
+    pub mute: Param,
+    pub solo: Param,
+    pub transpose: Param,
+}
+
+impl MixerParams {
+    fn new() -> Self {
+        Self {
+            volume: Param::new("volume", "dB", 0., -60f32..=6f32),
+            pan: Param::new("pan", "", 0., -1f32..=1f32),
+            mute: Param::toggle("mute", false),
+            solo: Param::toggle("solo", false),
+            transpose: Param::new("transpose", "st", 0., -24f32..=24f32),
+        }
+    }
+
+    /// Left and right gains, balance style so a centered part is at full volume.
+    fn gains(&self) -> (f32, f32) {
+        let volume = db_to_gain(self.volume.get());
+        let pan = self.pan.get();
+        (volume * (1. - pan).min(1.), volume * (1. + pan).min(1.))
+    }
+}
+
+impl ParamSet for MixerParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![
+            &self.volume,
+            &self.pan,
+            &self.mute,
+            &self.solo,
+            &self.transpose,
+        ]
+    }
+}
+
+/// A patch worked out ahead of time, for the audio thread to switch a part to between blocks.
+pub struct PreparedProgram {
+    part: usize,
+    /// for each of the part's params in order, left alone where the patch doesn't say
+    values: [Option<f32>; NUM_PARAMS],
+    slots: Option<Vec<Slot>>,
+    patterns: Option<PatternsUpdate>,
+}
+
+impl PreparedProgram {
+    /// Reads everything `patch` changes on `part`. Given the ui's patterns, also the effects and
+    /// patterns, which all parts share. Allocates, so not for the audio thread.
+    pub fn new(
+        part: usize,
+        params: &Params,
+        patch: &Patch,
+        patterns: Option<&mut PatternsEditor>,
+    ) -> Result<Self> {
+        let mut values = [None; NUM_PARAMS];
+        for (value, loaded) in values.iter_mut().zip(patch.synth_values(params)?) {
+            *value = loaded;
+        }
+        let (slots, patterns) = match patterns {
+            Some(editor) => {
+                // patches from before the sequencer leave the patterns alone
+                let loaded = if patch.sequencer.is_empty() {
+                    None
+                } else {
+                    Some(Patterns::from_values(&patch.sequencer)?)
+                };
+                let slots = EffectChain::with_room(patch.create_slots()?);
+                (Some(slots), loaded.map(|loaded| editor.load(loaded)))
+            }
+            None => (None, None),
+        };
+        Ok(Self {
+            part,
+            values,
+            slots,
+            patterns,
+        })
+    }
+}
+
+/// Program changes on their way from midi to the loader, and back to the audio thread as
+/// prepared programs.
+pub struct Programs {
+    /// the latest program change on each channel, for the loader to look up the preset
+    pub changes: Vec<AtomicCell<Option<ProgramChange>>>,
+    /// switched to at the start of the next block
+    pub prepared: ArrayQueue<PreparedProgram>,
+    /// switched to, now holding the effects and patterns they replaced to be dropped off the audio thread.
+    /// twice the size of `prepared` so it has room for everything in flight
+    pub retired: ArrayQueue<PreparedProgram>,
+    /// how many programs have been switched to, for the ui to notice the effects changed
+    pub switched: AtomicCell<u64>,
+}
+
+impl Programs {
+    fn new() -> Self {
+        Self {
+            changes: (0..NUM_PARTS).map(|_| AtomicCell::new(None)).collect(),
+            prepared: ArrayQueue::new(MAX_PREPARED_PROGRAMS),
+            retired: ArrayQueue::new(MAX_PREPARED_PROGRAMS * 2),
+            switched: AtomicCell::new(0),
+        }
+    }
+}
+
+/// The voices and controller state for one midi channel.
+struct Part {
+    voices: Vec<Voice>,
+    sustain: bool,
+    /// frequency ratio from the pitch wheel
+    bend: f32,
+    params: Arc<Params>,
+    mixer: Arc<MixerParams>,
+    bank_msb: u8,
+    bank_lsb: u8,
+    /// keys held in mono mode, in the order they were pressed
+    held: Vec<HeldNote>,
+    /// of the latest note, where the next one glides from
+    last_pitch: Option<f32>,
+    random: u32,
+}
+
+// derived clone would lose the room reserved for held keys, and the synth is cloned for each stream
+impl Clone for Part {
+    fn clone(&self) -> Self {
+        let mut held = Vec::with_capacity(MAX_HELD);
+        held.extend_from_slice(&self.held);
+        Self {
+            voices: self.voices.clone(),
+            sustain: self.sustain,
+            bend: self.bend,
+            params: self.params.clone(),
+            mixer: self.mixer.clone(),
+            bank_msb: self.bank_msb,
+            bank_lsb: self.bank_lsb,
+            held,
+            last_pitch: self.last_pitch,
+            random: self.random,
+        }
+    }
+}
+
+impl Part {
+    fn new() -> Self {
+        Self {
+            voices: vec![
+                Voice {
+                    event: None,
+                    shapers: [Shaper::new(), Shaper::new()],
+                    phases: [0.; MAX_UNISON],
+                    pitch: 0.,
+                    glide: f32::INFINITY,
+                    pressure: 0.,
+                    pressure_gain: 1.,
+                };
+                MAX_VOICES
+            ],
+            sustain: false,
+            bend: 1.,
+            params: Arc::new(Params::new()),
+            mixer: Arc::new(MixerParams::new()),
+            bank_msb: 0,
+            bank_lsb: 0,
+            held: Vec::with_capacity(MAX_HELD),
+            last_pitch: None,
+            random: 0x1234_5678,
+        }
+    }
+
+    fn next_random(&mut self) -> u32 {
+        // xorshift
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+        self.random
+    }
+
+    /// Where a new note's oscillators start. Random with unison so the copies don't start out in phase
+    /// and swell the attack, but the same every time for a single one.
+    fn start_phases(&mut self) -> [f32; MAX_UNISON] {
+        let mut phases = [0.; MAX_UNISON];
+        if self.params.unison.get_index() > 0 {
+            for phase in phases.iter_mut() {
+                *phase = self.next_random() as f32 / u32::MAX as f32;
+            }
+        }
+        phases
+    }
+
+    fn mode(&self) -> &'static str {
+        MODE_NAMES[self.params.mode.get_index()]
+    }
+
+    /// The held key a single voice should play.
+    fn top_note(&self) -> Option<HeldNote> {
+        let notes = self.held.iter().copied();
+        match PRIORITY_NAMES[self.params.priority.get_index()] {
+            "low" => notes.min_by_key(|held| held.note),
+            "high" => notes.max_by_key(|held| held.note),
+            _ => notes.last(),
+        }
+    }
+
+    fn note_on(
+        &mut self,
+        clock: u64,
+        key: wmidi::Note,
+        velocity: wmidi::U7,
+        transpose: i8,
+        level: f32,
+    ) {
+        let transpose = transpose.saturating_add(self.mixer.transpose.get().round() as i8);
+        let note = match key.step(transpose) {
+            Ok(note) => note,
+            // transposed off the end of the keyboard
+            Err(_) => return,
+        };
+        let held = HeldNote {
+            key,
+            note,
+            velocity,
+            level,
+        };
+        if self.mode() == "poly" {
+            self.held.clear();
+            self.poly_note_on(clock, held);
+        } else {
+            self.held.retain(|held| held.key != key);
+            if self.held.len() < MAX_HELD {
+                self.held.push(held);
+            }
+            if let Some(top) = self.top_note() {
+                self.mono_note(clock, top);
+            }
+        }
+    }
+
+    fn poly_note_on(&mut self, clock: u64, held: HeldNote) {
+        let HeldNote {
+            key,
+            note,
+            velocity,
+            level,
+        } = held;
+        let event = NoteEvent {
+            key,
+            note,
+            velocity,
+            level,
+            pressed: clock,
+            released: None,
+            sustained: false,
+        };
+        // retrigger the same note, otherwise take a free voice or steal the oldest one
+        let index = self
+            .voices
+            .iter()
+            .position(|voice| {
+                matches!(voice.event, Some(NoteEvent { key: k, note: n, released: None, .. }) if k == key && n == note)
+            })
+            .or_else(|| self.voices.iter().position(|voice| voice.event.is_none()))
+            .or_else(|| {
+                (0..self.voices.len()).min_by_key(|&i| {
+                    self.voices[i].event.as_ref().map_or(0, |event| event.pressed)
+                })
+            });
+        if let Some(index) = index {
+            let pitch = u8::from(note) as f32;
+            // each new voice glides from wherever the last note was
+            let from = self.last_pitch.unwrap_or(pitch);
+            let phases = self.start_phases();
+            let voice = &mut self.voices[index];
+            voice.event = Some(event);
+            voice.phases = phases;
+            voice.pressure = 0.;
+            voice.pressure_gain = 1.;
+            voice.pitch = from;
+            voice.glide = self.params.glide_speed(from, pitch);
+            self.last_pitch = Some(pitch);
+        }
+    }
+
+    /// Plays a note on the single voice, retriggering it unless legato and it's still playing.
+    fn mono_note(&mut self, clock: u64, held: HeldNote) {
+        let pitch = u8::from(held.note) as f32;
+        let legato = self.mode() == "legato";
+        let voice = &mut self.voices[0];
+        let from = match voice.event {
+            Some(_) => voice.pitch,
+            None => self.last_pitch.unwrap_or(pitch),
+        };
+        match voice.event {
+            // a key that doesn't change the note, like a higher one with low note priority
+            Some(ref event)
+                if event.key == held.key
+                    && event.note == held.note
+                    && event.released.is_none()
+                    && !event.sustained =>
+            {
+                return
+            }
+            Some(ref mut event) if legato && event.released.is_none() => {
+                event.key = held.key;
+                event.note = held.note;
+                event.level = held.level;
+                event.sustained = false;
+            }
+            _ => {
+                voice.event = Some(NoteEvent {
+                    key: held.key,
+                    note: held.note,
+                    velocity: held.velocity,
+                    level: held.level,
+                    pressed: clock,
+                    released: None,
+                    sustained: false,
+                });
+            }
+        }
+        voice.pitch = from;
+        voice.glide = self.params.glide_speed(from, pitch);
+        // the new key hasn't been pressed into yet
+        voice.pressure = 0.;
+        self.last_pitch = Some(pitch);
+    }
+
+    /// Polyphonic aftertouch, for the voices playing `key`.
+    fn aftertouch(&mut self, key: wmidi::Note, pressure: wmidi::U7) {
+        let pressure = u8::from(pressure) as f32 / 127.;
+        for voice in self.voices.iter_mut() {
+            if matches!(voice.event, Some(NoteEvent { key: k, released: None, .. }) if k == key) {
+                voice.pressure = pressure;
+            }
+        }
+    }
+
+    /// Matches on the key rather than the note, so changing the transpose doesn't leave notes hanging.
+    fn note_off(&mut self, clock: u64, key: wmidi::Note) {
+        if self.mode() != "poly" {
+            self.held.retain(|held| held.key != key);
+            let playing = matches!(self.voices[0].event, Some(NoteEvent { key: k, released: None, .. }) if k == key);
+            // go back to a key that is still held
+            if playing {
+                if let Some(top) = self.top_note() {
+                    self.mono_note(clock, top);
+                    return;
+                }
+            }
+        }
+        for voice in self.voices.iter_mut() {
+            if let Some(NoteEvent {
+                key: held_key,
+                ref mut released,
+                ref mut sustained,
+                ..
+            }) = voice.event
+            {
+                if key == held_key && released.is_none() {
+                    if self.sustain {
+                        *sustained = true;
+                    } else {
+                        *released = Some(clock);
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_sustain(&mut self, clock: u64, sustain: bool) {
+        self.sustain = sustain;
+        if !sustain {
+            for event in self
+                .voices
+                .iter_mut()
+                .filter_map(|voice| voice.event.as_mut())
+            {
+                if event.sustained && event.released.is_none() {
+                    event.released = Some(clock);
+                }
+            }
+        }
+    }
+
+    /// Renders the voices in interleaved stereo, replacing what's in `output`.
+    fn play(&mut self, clock: u64, sample_rate: u32, output: &mut [f32]) {
+        output.fill(0f32);
+        let frames = (output.len() / 2) as u64;
+        let gain = self.params.gain.get();
+        let shaper = self.params.shaper.settings();
+        let mut copies = [UnisonCopy::default(); MAX_UNISON];
+        let count = self.params.unison_copies(&mut copies);
+        let copies = &copies[..count];
+        let pressure_smoothing =
+            1. - (-1. / (AFTERTOUCH_SMOOTHING_SECONDS * sample_rate as f32)).exp();
+        for voice in self.voices.iter_mut() {
+            let NoteEvent {
+                note,
+                velocity,
+                level,
+                pressed,
+                released,
+                ..
+            } = match voice.event {
+                Some(ref event) => event.clone(),
+                None => continue,
+            };
+            let norm_vel = (u8::from(velocity) - u8::from(wmidi::U7::MIN)) as f32
+                / (u8::from(wmidi::U7::MAX) - u8::from(wmidi::U7::MIN)) as f32;
+            let target = u8::from(note) as f32;
+            let glide_step = voice.glide / sample_rate as f32;
+            let mut phase_step = pitch_to_freq(voice.pitch) * self.bend / sample_rate as f32;
+            let pressure_gain = db_to_gain(voice.pressure * AFTERTOUCH_DB);
+            for (i, frame) in output.chunks_exact_mut(2).enumerate() {
+                let clock = clock + i as u64;
+                let time = (clock - pressed) as f32 / sample_rate as f32;
+                if voice.pitch != target {
+                    let distance = target - voice.pitch;
+                    voice.pitch = if distance.abs() <= glide_step {
+                        target
+                    } else {
+                        voice.pitch + glide_step.copysign(distance)
+                    };
+                    phase_step = pitch_to_freq(voice.pitch) * self.bend / sample_rate as f32;
+                }
+                let (mut left, mut right) = (0., 0.);
+                for (phase, copy) in voice.phases.iter_mut().zip(copies) {
+                    let value = (*phase * 2f32 * PI).sin();
+                    *phase = (*phase + phase_step * copy.ratio).fract();
+                    left += value * copy.left;
+                    right += value * copy.right;
+                }
+                voice.pressure_gain += (pressure_gain - voice.pressure_gain) * pressure_smoothing;
+                let mut amplitude = norm_vel * level * gain * voice.pressure_gain;
+                // fade in to avoid pop
+                amplitude *= (time * 1000.).min(1.);
+                // fade out
+                if let Some(released) = released {
+                    let released_time = (clock - released) as f32 / sample_rate as f32;
+                    amplitude *= (1. - released_time * 1000.).max(0.);
+                }
+                let [left_shaper, right_shaper] = &mut voice.shapers;
+                frame[0] += left_shaper.process(&shaper, sample_rate, left) * amplitude;
+                frame[1] += right_shaper.process(&shaper, sample_rate, right) * amplitude;
+            }
+            if let Some(released) = released {
+                if (clock + frames - released) as f32 / sample_rate as f32 >= 0.001 {
+                    voice.event = None;
+                    for shaper in voice.shapers.iter_mut() {
+                        shaper.reset();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Multitimbral, with a part for each midi channel.
 pub struct Synth {
     clock: u64,
     midi_events: MidiChannel,
 
-    note_event: Option<NoteEvent>,
-    params: Arc<Params>,
+    parts: Vec<Part>,
+    /// one part's stereo output at a time, allocated up front for the audio thread
+    part_buffer: Vec<f32>,
+    active_notes: Arc<ActiveNotes>,
+    /// the ui only locks this briefly to edit the mappings
+    midi_map: Arc<Mutex<MidiMap>>,
+    /// the ui only locks this to swap in edited zones
+    zones: Arc<Mutex<Zones>>,
+    /// copied from `zones` at the start of each block the ui doesn't have them locked
+    current_zones: Zones,
+    transport: Transport,
+    /// where the transport was for the last block
+    transport_state: TransportState,
+    arpeggiator: Arpeggiator,
+    sequencer: Sequencer,
+    /// frame offset and message of each note the arpeggiator and sequencer play in the current block
+    events: Vec<(usize, MidiMessage<'static>)>,
+    /// presets are looked up and prepared elsewhere since that allocates
+    programs: Arc<Programs>,
+    /// where prepared programs put their effects
+    effect_chain: Option<EffectChain>,
+}
+
+// derived clone would lose the room reserved for the events, and this is cloned for each stream
+impl Clone for Synth {
+    fn clone(&self) -> Self {
+        let mut events = Vec::with_capacity(MAX_EVENTS);
+        events.extend_from_slice(&self.events);
+        Self {
+            clock: self.clock,
+            midi_events: self.midi_events.clone(),
+            parts: self.parts.clone(),
+            part_buffer: self.part_buffer.clone(),
+            active_notes: self.active_notes.clone(),
+            midi_map: self.midi_map.clone(),
+            zones: self.zones.clone(),
+            current_zones: self.current_zones.clone(),
+            transport: self.transport.clone(),
+            transport_state: self.transport_state,
+            arpeggiator: self.arpeggiator.clone(),
+            sequencer: self.sequencer.clone(),
+            events,
+            programs: self.programs.clone(),
+            effect_chain: self.effect_chain.clone(),
+        }
+    }
 }
 
 impl Synth {
@@ -35,92 +770,324 @@ impl Synth {
         Self {
             clock: 0,
             midi_events,
-            note_event: None,
-            params: Arc::new(Params {
-                gain: 1f32.into(),
-            }),
+            parts: (0..NUM_PARTS).map(|_| Part::new()).collect(),
+            part_buffer: vec![0.; PART_BUFFER_FRAMES * 2],
+            active_notes: Arc::new(ActiveNotes::new()),
+            midi_map: Arc::new(Mutex::new(MidiMap::new())),
+            zones: Arc::new(Mutex::new(Zones::new())),
+            current_zones: Zones::new(),
+            transport: Transport::new(),
+            transport_state: TransportState::default(),
+            arpeggiator: Arpeggiator::new(),
+            sequencer: Sequencer::new(),
+            events: Vec::with_capacity(MAX_EVENTS),
+            programs: Arc::new(Programs::new()),
+            effect_chain: None,
         }
     }
 
-    pub fn get_params(&self) -> Arc<Params> {
-        self.params.clone()
+    /// The sound of each part, by channel index.
+    pub fn get_params(&self) -> Vec<Arc<Params>> {
+        self.parts.iter().map(|part| part.params.clone()).collect()
+    }
+
+    /// The mix settings of each part, by channel index.
+    pub fn get_mixer_params(&self) -> Vec<Arc<MixerParams>> {
+        self.parts.iter().map(|part| part.mixer.clone()).collect()
+    }
+
+    pub fn get_midi_map(&self) -> Arc<Mutex<MidiMap>> {
+        self.midi_map.clone()
+    }
+
+    pub fn get_zones(&self) -> Arc<Mutex<Zones>> {
+        self.zones.clone()
+    }
+
+    pub fn get_transport_params(&self) -> Arc<TransportParams> {
+        self.transport.get_params()
+    }
+
+    /// Where the transport sends midi clock when it's the master.
+    pub fn set_clock_output(&mut self, output: Arc<MidiQueue>) {
+        self.transport.set_clock_output(output);
+    }
+
+    pub fn get_arp_params(&self) -> Arc<ArpParams> {
+        self.arpeggiator.get_params()
+    }
+
+    pub fn get_sequencer_params(&self) -> Arc<SequencerParams> {
+        self.sequencer.get_params()
+    }
+
+    pub fn get_patterns(&self) -> Arc<Mutex<PatternsEditor>> {
+        self.sequencer.get_patterns()
+    }
+
+    pub fn get_programs(&self) -> Arc<Programs> {
+        self.programs.clone()
+    }
+
+    /// The chain the synth plays through, for programs to switch the effects of.
+    pub fn set_effect_chain(&mut self, chain: EffectChain) {
+        self.effect_chain = Some(chain);
+    }
+
+    pub fn get_active_notes(&self) -> Arc<ActiveNotes> {
+        self.active_notes.clone()
+    }
+
+    fn handle_message(&mut self, message: MidiMessage<'static>) {
+        let channel = match message.channel() {
+            Some(channel) => channel,
+            None => return,
+        };
+        let clock = self.clock;
+        // notes, aftertouch, the pedal and pitch bend go through the zones, everything else to the channel's own part
+        let zones = &self.current_zones;
+        let zone_parts = zones.parts(channel);
+        let index = channel.index() as usize;
+        let parts = &mut self.parts;
+        match message {
+            // note on with zero velocity is another way to say note off
+            MidiMessage::NoteOn(_, key, velocity) if u8::from(velocity) > 0 => {
+                for (part, transpose, level) in zones.notes(channel, key.into(), velocity.into()) {
+                    if let Some(part) = parts.get_mut(part) {
+                        part.note_on(clock, key, velocity, transpose, level);
+                    }
+                }
+            }
+            MidiMessage::NoteOn(_, key, _) | MidiMessage::NoteOff(_, key, _) => {
+                for part in zone_parts {
+                    if let Some(part) = parts.get_mut(part) {
+                        part.note_off(clock, key);
+                    }
+                }
+            }
+            MidiMessage::PolyphonicKeyPressure(_, key, pressure) => {
+                for part in zone_parts {
+                    if let Some(part) = parts.get_mut(part) {
+                        part.aftertouch(key, pressure);
+                    }
+                }
+            }
+            MidiMessage::ControlChange(_, function, value) => {
+                match function {
+                    wmidi::ControlFunction::DAMPER_PEDAL => {
+                        for part in zone_parts {
+                            if let Some(part) = parts.get_mut(part) {
+                                part.set_sustain(clock, u8::from(value) >= 64);
+                            }
+                        }
+                    }
+                    wmidi::ControlFunction::BANK_SELECT => parts[index].bank_msb = value.into(),
+                    wmidi::ControlFunction::BANK_SELECT_LSB => parts[index].bank_lsb = value.into(),
+                    _ => {}
+                }
+                // the ui only holds the map for a moment, a controller moving meanwhile can be dropped
+                if let Some(mut midi_map) = self.midi_map.try_lock() {
+                    let parts = &*parts;
+                    midi_map.control_change(
+                        |part| {
+                            parts
+                                .get(part)
+                                .into_iter()
+                                .flat_map(|part| part.params.all())
+                        },
+                        channel,
+                        function.into(),
+                        value.into(),
+                    );
+                }
+            }
+            MidiMessage::ProgramChange(_, program) => {
+                self.programs.changes[index].store(Some(ProgramChange {
+                    bank_msb: parts[index].bank_msb,
+                    bank_lsb: parts[index].bank_lsb,
+                    program: program.into(),
+                }));
+            }
+            MidiMessage::PitchBendChange(_, bend) => {
+                // centered on 0x2000
+                let amount = (u16::from(bend) as f32 - 8192.) / 8192.;
+                let ratio = 2f32.powf(amount * PITCH_BEND_SEMITONES / 12.);
+                for part in zone_parts {
+                    if let Some(part) = parts.get_mut(part) {
+                        part.bend = ratio;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Switches to the next prepared program, all of it in the same block.
+    /// If the ui has the effects locked it tries again next block instead of waiting.
+    fn switch_program(&mut self) {
+        if self.programs.prepared.is_empty() {
+            return;
+        }
+        let mut slots = match self.effect_chain.as_ref().map(EffectChain::try_lock) {
+            Some(None) => return,
+            slots => slots.flatten(),
+        };
+        let mut program = match self.programs.prepared.pop() {
+            Some(program) => program,
+            None => return,
+        };
+        if let Some(loaded) = program.patterns.as_mut() {
+            self.sequencer.swap_patterns(loaded);
+        }
+        if let (Some(slots), Some(loaded)) = (slots.as_mut(), program.slots.as_mut()) {
+            std::mem::swap(&mut **slots, loaded);
+        }
+        if let Some(part) = self.parts.get(program.part) {
+            for (param, value) in part.params.all().iter().zip(program.values.iter()) {
+                if let Some(value) = value {
+                    param.set(*value);
+                }
+            }
+        }
+        self.programs.switched.fetch_add(1);
+        let _ignore = self.programs.retired.push(program);
+    }
+
+    /// Shows the keys held on any channel.
+    fn publish_active_notes(&self) {
+        let mut notes = [0u8; 128];
+        let events = self
+            .parts
+            .iter()
+            .flat_map(|part| part.voices.iter())
+            .filter_map(|voice| voice.event.as_ref());
+        for event in events {
+            if event.released.is_none() {
+                let sustained = if event.sustained { SUSTAINED_BIT } else { 0 };
+                let note = &mut notes[u8::from(event.key) as usize];
+                *note = (*note).max(u8::from(event.velocity) | sustained);
+            }
+        }
+        for (cell, value) in self.active_notes.notes.iter().zip(notes.iter()) {
+            cell.store(*value);
+        }
+    }
+
+    /// Mixes the parts into `output` from the current clock on.
+    fn render(&mut self, sample_rate: u32, channels: usize, output: &mut [f32]) {
+        let frames = output.len() / channels;
+        let any_solo = self.parts.iter().any(|part| part.mixer.solo.is_on());
+        for part in self.parts.iter_mut() {
+            let audible = !part.mixer.mute.is_on() && (!any_solo || part.mixer.solo.is_on());
+            let (left, right) = part.mixer.gains();
+            let mono = (left + right) / 2.;
+            let mut clock = self.clock;
+            for block in output.chunks_mut(PART_BUFFER_FRAMES * channels) {
+                let block_frames = block.len() / channels;
+                let buffer = &mut self.part_buffer[..block_frames * 2];
+                // silent parts still play so their voices finish as usual
+                part.play(clock, sample_rate, buffer);
+                clock += block_frames as u64;
+                if !audible {
+                    continue;
+                }
+                for (frame, values) in block.chunks_exact_mut(channels).zip(buffer.chunks_exact(2))
+                {
+                    let middle = (values[0] + values[1]) / 2.;
+                    for (channel, sample) in frame.iter_mut().enumerate() {
+                        *sample += match channel {
+                            _ if channels == 1 => middle * mono,
+                            0 => values[0] * left,
+                            1 => values[1] * right,
+                            // anything past stereo gets the middle
+                            _ => middle * mono,
+                        };
+                    }
+                }
+            }
+        }
+        self.clock += frames as u64;
     }
 }
 
 pub trait SynthPlayer {
     fn play(&mut self, sample_rate: u32, channels: usize, output: &mut [f32]);
+
+    /// Where the transport was for the last block played, for effects that sync to it.
+    fn transport(&self) -> TransportState {
+        TransportState::default()
+    }
 }
 
 impl SynthPlayer for Synth {
     fn play(&mut self, sample_rate: u32, channels: usize, output: &mut [f32]) {
-        // pump midi messages
-        for message in self.midi_events.try_iter() {
-            match message {
-                wmidi::MidiMessage::NoteOn(_, note, velocity) => {
-                    self.note_event = Some(NoteEvent {
-                        note,
-                        velocity,
-                        pressed: self.clock,
-                        released: None,
-                    });
-                }
-                wmidi::MidiMessage::NoteOff(_, note, _) => {
-                    if let Some(NoteEvent {
-                        note: held_note,
-                        ref mut released,
-                        ..
-                    }) = self.note_event
-                    {
-                        if note == held_note {
-                            *released = Some(self.clock);
-                        }
-                    }
+        self.switch_program();
+        if let Some(zones) = self.zones.try_lock() {
+            self.current_zones.copy_from(&zones);
+        }
+        // pump midi messages, through the transport and the arpeggiator
+        while let Ok(message) = self.midi_events.try_recv() {
+            if let Some(message) = self.transport.process(message) {
+                self.sequencer.record(&self.transport_state, &message);
+                if let Some(message) = self.arpeggiator.process(message) {
+                    self.handle_message(message);
                 }
-                _ => {}
+            }
+        }
+        let frames = output.len() / channels;
+        let transport = self.transport.run(sample_rate, frames);
+        self.transport_state = transport;
+        let events = &mut self.events;
+        events.clear();
+        let mut push = |offset, message| {
+            if events.len() < MAX_EVENTS {
+                events.push((offset, message));
+            }
+        };
+        self.arpeggiator
+            .run(&transport, sample_rate, frames, &mut push);
+        self.sequencer.run(&transport, frames, &mut push);
+        // each source is in order already, a stable sort keeps note offs before note ons at the same time
+        // and insertion sort does that without allocating
+        for i in 1..events.len() {
+            let mut j = i;
+            while j > 0 && events[j - 1].0 > events[j].0 {
+                events.swap(j - 1, j);
+                j -= 1;
             }
         }
 
-        // produce sound
-        if let Some(NoteEvent {
-            note,
-            velocity,
-            pressed,
-            released,
-        }) = self.note_event
-        {
-            let gain = self.params.gain.load();
-            let norm_vel = (u8::from(velocity) - u8::from(wmidi::U7::MIN)) as f32
-                / (u8::from(wmidi::U7::MAX) - u8::from(wmidi::U7::MIN)) as f32;
-            let freq = note.to_freq_f32();
-            for frame in output.chunks_exact_mut(channels) {
-                let time = (self.clock - pressed) as f32 / sample_rate as f32;
-                let mut value = (time * freq * 2f32 * PI).sin();
-                value *= norm_vel;
-                value *= gain;
-                // fade in to avoid pop
-                value *= (time * 1000.).min(1.);
-                // fade out
-                if let Some(released) = released {
-                    let released_time = (self.clock - released) as f32 / sample_rate as f32;
-                    value *= (1. - released_time * 1000.).max(0.);
-                }
-                // TODO also avoid popping when switching between notes
-                for sample in frame.iter_mut() {
-                    *sample = value;
-                }
-                self.clock += 1;
+        // produce sound, stopping at each of those notes so it lands on the right sample
+        output.fill(0f32);
+        let mut start = 0;
+        for index in 0..self.events.len() {
+            let (offset, message) = self.events[index].clone();
+            if offset > start {
+                self.render(
+                    sample_rate,
+                    channels,
+                    &mut output[start * channels..offset * channels],
+                );
+                start = offset;
             }
-        } else {
-            output.fill(0f32);
-            self.clock += (output.len() / channels) as u64;
+            self.handle_message(message);
         }
+        self.render(sample_rate, channels, &mut output[start * channels..]);
+        self.publish_active_notes();
+    }
+
+    fn transport(&self) -> TransportState {
+        self.transport_state
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{Synth, SynthPlayer};
+    use super::{NoteState, PreparedProgram, Synth, SynthPlayer};
+    use crate::effects::{EffectChain, EffectKind};
+    use crate::patch::Patch;
     use crossbeam::channel;
+    use wmidi::MidiMessage;
 
     #[test]
     fn silence() {
@@ -130,4 +1097,270 @@ mod test {
         synth.play(48000, 2, &mut data);
         assert_eq!([0f32; 512], data);
     }
+
+    #[test]
+    fn chord() {
+        let (tx, rx) = channel::bounded(8);
+        let mut synth = Synth::new(rx);
+        let velocity = wmidi::Velocity::from_u8_lossy(100);
+        for note in [wmidi::Note::C4, wmidi::Note::E4, wmidi::Note::G4].iter() {
+            tx.send(MidiMessage::NoteOn(wmidi::Channel::Ch1, *note, velocity))
+                .unwrap();
+        }
+        let mut data = [0f32; 512];
+        synth.play(48000, 2, &mut data);
+        let playing = |synth: &Synth| {
+            synth.parts[0]
+                .voices
+                .iter()
+                .filter(|v| v.event.is_some())
+                .count()
+        };
+        assert_eq!(3, playing(&synth));
+        assert!(data.iter().any(|v| *v > 1.));
+        // releasing one note leaves the others playing
+        tx.send(MidiMessage::NoteOff(
+            wmidi::Channel::Ch1,
+            wmidi::Note::E4,
+            velocity,
+        ))
+        .unwrap();
+        synth.play(48000, 2, &mut data);
+        assert_eq!(2, playing(&synth));
+    }
+
+    #[test]
+    fn sustain_pedal() {
+        let (tx, rx) = channel::bounded(8);
+        let mut synth = Synth::new(rx);
+        let notes = synth.get_active_notes();
+        let channel = wmidi::Channel::Ch1;
+        let pedal = |value| {
+            MidiMessage::ControlChange(
+                channel,
+                wmidi::ControlFunction::DAMPER_PEDAL,
+                wmidi::U7::from_u8_lossy(value),
+            )
+        };
+        let velocity = wmidi::Velocity::from_u8_lossy(100);
+        tx.send(pedal(127)).unwrap();
+        tx.send(MidiMessage::NoteOn(channel, wmidi::Note::C4, velocity))
+            .unwrap();
+        tx.send(MidiMessage::NoteOff(channel, wmidi::Note::C4, velocity))
+            .unwrap();
+        let mut data = [0f32; 512];
+        synth.play(48000, 2, &mut data);
+        assert_eq!(
+            Some(NoteState {
+                velocity: 100,
+                sustained: true
+            }),
+            notes.get(wmidi::Note::C4)
+        );
+        tx.send(pedal(0)).unwrap();
+        synth.play(48000, 2, &mut data);
+        assert_eq!(None, notes.get(wmidi::Note::C4));
+    }
+
+    #[test]
+    fn parts() {
+        let (tx, rx) = channel::bounded(8);
+        let mut synth = Synth::new(rx);
+        let mixer = synth.get_mixer_params();
+        let velocity = wmidi::Velocity::from_u8_lossy(127);
+        let channel = wmidi::Channel::Ch2;
+        tx.send(MidiMessage::NoteOn(channel, wmidi::Note::A4, velocity))
+            .unwrap();
+        mixer[1].pan.set(-1.);
+        let mut data = [0f32; 512];
+        synth.play(48000, 2, &mut data);
+        assert!(synth.parts[0].voices.iter().all(|v| v.event.is_none()));
+        assert!(data.chunks(2).any(|frame| frame[0] > 0.5));
+        assert!(data.chunks(2).all(|frame| frame[1] == 0.));
+
+        // soloing another part silences this one
+        mixer[0].solo.set(1.);
+        synth.play(48000, 2, &mut data);
+        assert!(data.iter().all(|v| *v == 0.));
+        mixer[0].solo.set(0.);
+
+        // the note off finds a transposed note by its key
+        mixer[1].transpose.set(12.);
+        tx.send(MidiMessage::NoteOn(channel, wmidi::Note::A3, velocity))
+            .unwrap();
+        synth.play(48000, 2, &mut data);
+        let a3 = |synth: &Synth| {
+            synth.parts[1]
+                .voices
+                .iter()
+                .filter_map(|v| v.event.as_ref())
+                .filter(|e| e.key == wmidi::Note::A3 && e.released.is_none())
+                .map(|e| e.note)
+                .next()
+        };
+        assert_eq!(Some(wmidi::Note::A4), a3(&synth));
+        tx.send(MidiMessage::NoteOff(channel, wmidi::Note::A3, velocity))
+            .unwrap();
+        synth.play(48000, 2, &mut data);
+        assert_eq!(None, a3(&synth));
+    }
+
+    #[test]
+    fn mono_legato_and_glide() {
+        let (tx, rx) = channel::bounded(8);
+        let mut synth = Synth::new(rx);
+        let params = &synth.get_params()[0];
+        params.mode.set_text("legato").unwrap();
+        params.glide.set(100.);
+        let channel = wmidi::Channel::Ch1;
+        let velocity = wmidi::Velocity::from_u8_lossy(100);
+        let on = |note| MidiMessage::NoteOn(channel, note, velocity);
+        let off = |note| MidiMessage::NoteOff(channel, note, velocity);
+        // 10 ms blocks
+        let mut data = [0f32; 480];
+        let voice = |synth: &Synth| {
+            let voice = &synth.parts[0].voices[0];
+            (
+                voice
+                    .event
+                    .as_ref()
+                    .map(|event| (event.note, event.pressed)),
+                voice.pitch,
+            )
+        };
+
+        // nothing to glide from at first
+        tx.send(on(wmidi::Note::C4)).unwrap();
+        synth.play(48000, 1, &mut data);
+        assert_eq!((Some((wmidi::Note::C4, 0)), 60.), voice(&synth));
+
+        // legato doesn't retrigger, and glides an octave in 100 ms
+        tx.send(on(wmidi::Note::C5)).unwrap();
+        for _ in 0..5 {
+            synth.play(48000, 1, &mut data);
+        }
+        let (event, pitch) = voice(&synth);
+        assert_eq!(Some((wmidi::Note::C5, 0)), event);
+        assert!((pitch - 66.).abs() < 0.01);
+        assert!(synth.parts[0].voices[1..]
+            .iter()
+            .all(|voice| voice.event.is_none()));
+
+        // letting go goes back to the key still held
+        tx.send(off(wmidi::Note::C5)).unwrap();
+        synth.play(48000, 1, &mut data);
+        assert_eq!(Some((wmidi::Note::C4, 0)), voice(&synth).0);
+
+        // mono retriggers
+        params.mode.set_text("mono").unwrap();
+        tx.send(on(wmidi::Note::E4)).unwrap();
+        synth.play(48000, 1, &mut data);
+        assert_eq!(Some((wmidi::Note::E4, 3360)), voice(&synth).0);
+        tx.send(off(wmidi::Note::E4)).unwrap();
+        tx.send(off(wmidi::Note::C4)).unwrap();
+        synth.play(48000, 1, &mut data);
+        assert_eq!(None, voice(&synth).0);
+    }
+
+    #[test]
+    fn unison_spread() {
+        let (tx, rx) = channel::bounded(8);
+        let mut synth = Synth::new(rx);
+        let params = &synth.get_params()[0];
+        let channel = wmidi::Channel::Ch1;
+        let velocity = wmidi::Velocity::from_u8_lossy(100);
+        let mut data = [0f32; 960];
+        let peak = |data: &[f32], channel: usize| {
+            data.chunks(2)
+                .fold(0f32, |peak, frame| peak.max(frame[channel].abs()))
+        };
+
+        // a single copy stays in the middle
+        tx.send(MidiMessage::NoteOn(channel, wmidi::Note::A4, velocity))
+            .unwrap();
+        synth.play(48000, 2, &mut data);
+        assert!(peak(&data, 0) > 0.5);
+        assert!(data.chunks(2).all(|frame| frame[0] == frame[1]));
+
+        // copies spread out, with the loudness about the same
+        params.unison.set_text("7").unwrap();
+        tx.send(MidiMessage::NoteOn(channel, wmidi::Note::C4, velocity))
+            .unwrap();
+        synth.play(48000, 2, &mut data);
+        assert!(data.chunks(2).any(|frame| frame[0] != frame[1]));
+        assert!(peak(&data, 0) < 3.);
+        assert!(peak(&data, 1) < 3.);
+
+        // unless the spread is off
+        params.spread.set(0.);
+        synth.play(48000, 2, &mut data);
+        assert!(data.chunks(2).all(|frame| frame[0] == frame[1]));
+    }
+
+    #[test]
+    fn aftertouch() {
+        let (tx, rx) = channel::bounded(8);
+        let mut synth = Synth::new(rx);
+        let channel = wmidi::Channel::Ch1;
+        let velocity = wmidi::Velocity::from_u8_lossy(64);
+        let peak = |data: &[f32]| data.iter().fold(0f32, |peak, v| peak.max(v.abs()));
+        tx.send(MidiMessage::NoteOn(channel, wmidi::Note::A4, velocity))
+            .unwrap();
+        tx.send(MidiMessage::NoteOn(channel, wmidi::Note::C4, velocity))
+            .unwrap();
+        let mut data = [0f32; 960];
+        synth.play(48000, 1, &mut data);
+        let before = peak(&data);
+
+        // pressing into one key makes only that one louder
+        tx.send(MidiMessage::PolyphonicKeyPressure(
+            channel,
+            wmidi::Note::A4,
+            wmidi::U7::MAX,
+        ))
+        .unwrap();
+        synth.play(48000, 1, &mut data);
+        synth.play(48000, 1, &mut data);
+        let pressures: Vec<_> = synth.parts[0]
+            .voices
+            .iter()
+            .filter(|voice| voice.event.is_some())
+            .map(|voice| voice.pressure)
+            .collect();
+        assert_eq!(vec![1., 0.], pressures);
+        assert!(peak(&data) > 1.2 * before);
+    }
+
+    #[test]
+    fn program_switches_between_blocks() {
+        let (_tx, rx) = channel::bounded(1);
+        let mut synth = Synth::new(rx);
+        let chain = EffectChain::new();
+        synth.set_effect_chain(chain.clone());
+        let programs = synth.get_programs();
+        let params = synth.get_params();
+        let patch: Patch = "[synth]\ngain = 0.5\n\n[reverb]\nslot mix = 0.25\n"
+            .parse()
+            .unwrap();
+        let patterns = synth.get_patterns();
+        let program =
+            PreparedProgram::new(0, &params[0], &patch, Some(&mut patterns.lock())).unwrap();
+        assert!(programs.prepared.push(program).is_ok());
+        assert_eq!(1., params[0].gain.get());
+
+        // waits for the ui to let go of the effects
+        let locked = chain.try_lock();
+        let mut data = [0f32; 64];
+        synth.play(48000, 2, &mut data);
+        assert_eq!(1., params[0].gain.get());
+        drop(locked);
+        synth.play(48000, 2, &mut data);
+        assert_eq!(0.5, params[0].gain.get());
+        let states = chain.states();
+        assert_eq!(1, states.len());
+        assert_eq!(EffectKind::Reverb, states[0].kind);
+        assert_eq!(0.25, states[0].mix.load());
+        assert_eq!(1, programs.switched.load());
+        assert_eq!(1, programs.retired.len());
+    }
 }
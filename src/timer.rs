@@ -24,8 +24,53 @@ cfg_if::cfg_if! {
                 win.set_timeout_with_callback_and_timeout_and_arguments_0(rcf.borrow().as_ref().unwrap().as_ref().unchecked_ref(), delay.num_milliseconds() as i32).unwrap();
             }
         }
+
+        /// Calls back every `period` until dropped.
+        pub struct Interval {
+            _closure: Closure<dyn Fn()>,
+            handle: i32,
+        }
+
+        impl Interval {
+            pub fn new<T: Fn() + 'static>(period: &Duration, callback: T) -> Self {
+                let win = web_sys::window().unwrap();
+                let f = Closure::wrap(Box::new(callback) as Box<dyn Fn()>);
+                let handle = win.set_interval_with_callback_and_timeout_and_arguments_0(f.as_ref().unchecked_ref(), period.num_milliseconds() as i32).unwrap();
+                Self {
+                    _closure: f,
+                    handle,
+                }
+            }
+        }
+
+        impl Drop for Interval {
+            fn drop(&mut self) {
+                web_sys::window().unwrap().clear_interval_with_handle(self.handle);
+            }
+        }
+
+        /// Wall clock time since it was created.
+        pub struct Stopwatch {
+            start: f64,
+        }
+
+        impl Stopwatch {
+            pub fn new() -> Self {
+                Self { start: now_milliseconds() }
+            }
+
+            pub fn seconds(&self) -> f64 {
+                (now_milliseconds() - self.start) / 1000.
+            }
+        }
+
+        fn now_milliseconds() -> f64 {
+            web_sys::window().unwrap().performance().unwrap().now()
+        }
     } else {
-        use std::thread;
+        use crossbeam::channel::{self, Sender};
+        use std::thread::{self, JoinHandle};
+        use std::time::Instant;
         // TODO use a proper timer implementation instead. use the Timer crate?
         pub struct Timer;
         impl Timer {
@@ -41,5 +86,50 @@ cfg_if::cfg_if! {
                 });
             }
         }
+
+        /// Calls back every `period` until dropped, on a thread of its own.
+        pub struct Interval {
+            quitter: Sender<()>,
+            join_handle: Option<JoinHandle<()>>,
+        }
+
+        impl Interval {
+            pub fn new<T: Fn() + Send + 'static>(period: &Duration, callback: T) -> Self {
+                let period = period.to_std().unwrap();
+                let (tx, rx) = channel::bounded(1);
+                let join_handle = Some(thread::spawn(move || {
+                    while rx.try_recv().is_err() {
+                        callback();
+                        thread::sleep(period);
+                    }
+                }));
+                Self {
+                    quitter: tx,
+                    join_handle,
+                }
+            }
+        }
+
+        impl Drop for Interval {
+            fn drop(&mut self) {
+                self.quitter.send(()).unwrap();
+                self.join_handle.take().unwrap().join().unwrap();
+            }
+        }
+
+        /// Wall clock time since it was created.
+        pub struct Stopwatch {
+            start: Instant,
+        }
+
+        impl Stopwatch {
+            pub fn new() -> Self {
+                Self { start: Instant::now() }
+            }
+
+            pub fn seconds(&self) -> f64 {
+                self.start.elapsed().as_secs_f64()
+            }
+        }
     }
 }
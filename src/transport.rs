@@ -0,0 +1,458 @@
+use crate::midi::MidiQueue;
+use crate::param::{Param, ParamSet};
+use crossbeam::atomic::AtomicCell;
+use std::{convert::TryFrom, sync::Arc};
+use wmidi::MidiMessage;
+
+pub const TICKS_PER_QUARTER: f64 = 24.;
+/// song position pointers count in 16ths
+const TICKS_PER_SONG_POSITION: u64 = 6;
+/// how much of each new midi clock measurement goes into the tempo estimate
+const CLOCK_SMOOTHING: f64 = 0.1;
+const DEFAULT_TEMPO: f64 = 120.;
+
+const SYNC_NAMES: &[&str] = &["internal", "midi clock"];
+const TIME_SIGNATURE_NAMES: &[&str] = &["2/4", "3/4", "4/4", "5/4", "6/8", "7/8", "9/8", "12/8"];
+const TIME_SIGNATURES: &[(u32, u32)] = &[
+    (2, 4),
+    (3, 4),
+    (4, 4),
+    (5, 4),
+    (6, 8),
+    (7, 8),
+    (9, 8),
+    (12, 8),
+];
+const COUNT_IN_NAMES: &[&str] = &["off", "1 bar", "2 bars"];
+
+/// Where the transport is in the current block, for everything that follows it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransportState {
+    /// in beats per minute, measured from the midi clock when following it
+    pub tempo: f64,
+    pub playing: bool,
+    /// in quarter notes since the start of the song, at the start of the block
+    pub position: f64,
+    /// and at the end of it, which can fall short of the tempo when waiting for the midi clock
+    pub end: f64,
+    /// beats per bar and the note value of a beat
+    pub time_signature: (u32, u32),
+    /// playing the bars before where recording starts
+    pub count_in: bool,
+    /// recording is armed and any count in is over
+    pub recording: bool,
+}
+
+impl Default for TransportState {
+    fn default() -> Self {
+        Self {
+            tempo: DEFAULT_TEMPO,
+            playing: false,
+            position: 0.,
+            end: 0.,
+            time_signature: (4, 4),
+            count_in: false,
+            recording: false,
+        }
+    }
+}
+
+impl TransportState {
+    /// Quarter notes per sample at the tempo, whether playing or not.
+    pub fn quarters_per_sample(&self, sample_rate: u32) -> f64 {
+        self.tempo / 60. / sample_rate as f64
+    }
+
+    /// In quarter notes.
+    pub fn beat_length(&self) -> f64 {
+        4. / self.time_signature.1 as f64
+    }
+
+    /// In quarter notes.
+    pub fn bar_length(&self) -> f64 {
+        self.time_signature.0 as f64 * self.beat_length()
+    }
+
+    /// How many steps of `steps_per_quarter` make a bar, for finding the bars by counting steps.
+    pub fn steps_per_bar(&self, steps_per_quarter: f64) -> u64 {
+        (self.bar_length() * steps_per_quarter).round().max(1.) as u64
+    }
+}
+
+pub struct TransportParams {
+    pub playing: Param,
+    pub tempo: Param,
+    pub sync: Param,
+    /// send midi clock when running on the internal tempo
+    pub send_clock: Param,
+    pub time_signature: Param,
+    pub record: Param,
+    /// bars played before recording starts
+    pub count_in: Param,
+    /// a position in quarter notes for the audio thread to jump to
+    pub locate: AtomicCell<Option<f64>>,
+    /// published by the audio thread for the ui
+    pub state: AtomicCell<TransportState>,
+}
+
+impl TransportParams {
+    fn new() -> Self {
+        Self {
+            playing: Param::toggle("play", false),
+            tempo: Param::new("tempo", "bpm", DEFAULT_TEMPO as f32, 20f32..=300f32),
+            sync: Param::choice("sync", 0, SYNC_NAMES),
+            send_clock: Param::toggle("send clock", false),
+            time_signature: Param::choice("time signature", 2, TIME_SIGNATURE_NAMES),
+            record: Param::toggle("record", false),
+            count_in: Param::choice("count in", 0, COUNT_IN_NAMES),
+            locate: AtomicCell::new(None),
+            state: AtomicCell::new(TransportState::default()),
+        }
+    }
+
+    pub fn clock_sync(&self) -> bool {
+        self.sync.get_index() == 1
+    }
+}
+
+impl ParamSet for TransportParams {
+    fn params(&self) -> Vec<&Param> {
+        vec![
+            &self.playing,
+            &self.tempo,
+            &self.sync,
+            &self.send_clock,
+            &self.time_signature,
+            &self.record,
+            &self.count_in,
+        ]
+    }
+}
+
+/// Tempo and song position, owned by the audio thread.
+///
+/// Either runs on its own tempo, optionally sending midi clock, or follows incoming midi clock.
+#[derive(Clone)]
+pub struct Transport {
+    params: Arc<TransportParams>,
+    clock_output: Option<Arc<MidiQueue>>,
+    playing: bool,
+    /// in quarter notes
+    position: f64,
+    /// in samples, for measuring the midi clock
+    clock: u64,
+    /// in midi clock ticks, where the next one received puts the song position
+    next_tick: u64,
+    /// and where the last one did, none until the first one after starting
+    last_tick: Option<u64>,
+    pending_ticks: u64,
+    last_tick_clock: Option<u64>,
+    /// smoothed samples per midi clock tick, 0 until measured
+    tick_interval: f64,
+    /// midi clock ticks sent, fractional so the next one can be found
+    sent_ticks: f64,
+    /// song position to announce with the first tick at or after it, held back through any count in
+    clock_start: Option<f64>,
+    /// where it started playing, so the count in before it is left out of recording
+    record_from: f64,
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(TransportParams::new()),
+            clock_output: None,
+            playing: false,
+            position: 0.,
+            clock: 0,
+            next_tick: 0,
+            last_tick: None,
+            pending_ticks: 0,
+            last_tick_clock: None,
+            tick_interval: 0.,
+            sent_ticks: 0.,
+            clock_start: None,
+            record_from: 0.,
+        }
+    }
+
+    pub fn get_params(&self) -> Arc<TransportParams> {
+        self.params.clone()
+    }
+
+    pub fn set_clock_output(&mut self, output: Arc<MidiQueue>) {
+        self.clock_output = Some(output);
+    }
+
+    /// `time` is in seconds of audio rendered.
+    fn send(&self, message: MidiMessage<'static>, time: f64) {
+        if let Some(output) = &self.clock_output {
+            output.push(&message, time);
+        }
+    }
+
+    fn send_start(&self, position: f64, time: f64) {
+        if position == 0. {
+            self.send(MidiMessage::Start, time);
+        } else {
+            let sixteenths = (position * 4.).round() as u16;
+            if let Ok(position) = wmidi::U14::try_from(sixteenths) {
+                self.send(MidiMessage::SongPositionPointer(position), time);
+            }
+            self.send(MidiMessage::Continue, time);
+        }
+    }
+
+    fn locate(&mut self, position: f64) {
+        self.position = position.max(0.);
+        self.next_tick = (self.position * TICKS_PER_QUARTER).round() as u64;
+        self.last_tick = None;
+        self.sent_ticks = self.position * TICKS_PER_QUARTER;
+        self.record_from = self.position;
+    }
+
+    /// Takes the midi clock, start, stop, continue and song position. Everything else is passed on.
+    pub fn process(&mut self, message: MidiMessage<'static>) -> Option<MidiMessage<'static>> {
+        // consumed either way, the parts have no use for them
+        let follow = self.params.clock_sync();
+        match message {
+            MidiMessage::TimingClock => {
+                if follow {
+                    self.pending_ticks += 1;
+                    // the song position only moves while playing
+                    if self.playing {
+                        self.last_tick = Some(self.next_tick);
+                        self.next_tick += 1;
+                    }
+                }
+                None
+            }
+            MidiMessage::Start => {
+                if follow {
+                    self.locate(0.);
+                    self.set_playing(true);
+                }
+                None
+            }
+            MidiMessage::Continue => {
+                if follow {
+                    // the first tick after continuing is where it stopped
+                    self.last_tick = None;
+                    self.set_playing(true);
+                }
+                None
+            }
+            MidiMessage::Stop => {
+                if follow {
+                    self.set_playing(false);
+                }
+                None
+            }
+            MidiMessage::SongPositionPointer(position) => {
+                if follow {
+                    let ticks = u16::from(position) as u64 * TICKS_PER_SONG_POSITION;
+                    self.locate(ticks as f64 / TICKS_PER_QUARTER);
+                }
+                None
+            }
+            _ => Some(message),
+        }
+    }
+
+    fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+        // so the play button shows what the midi clock is doing
+        self.params.playing.set(if playing { 1. } else { 0. });
+    }
+
+    /// Moves on by `frames`, returning where the transport is for them.
+    /// Called from the audio thread, so doesn't allocate.
+    pub fn run(&mut self, sample_rate: u32, frames: usize) -> TransportState {
+        let start_clock = self.clock;
+        self.clock += frames as u64;
+        if let Some(position) = self.params.locate.take() {
+            self.locate(position);
+        }
+        let follow = self.params.clock_sync();
+        let time_signature = TIME_SIGNATURES[self.params.time_signature.get_index()];
+        let mut limit = f64::INFINITY;
+        let tempo = if follow {
+            if self.pending_ticks > 0 {
+                if let Some(last) = self.last_tick_clock {
+                    // ticks only arrive once per block, so measure over all of them
+                    let interval = (start_clock - last) as f64 / self.pending_ticks as f64;
+                    self.tick_interval = if self.tick_interval > 0. {
+                        self.tick_interval + CLOCK_SMOOTHING * (interval - self.tick_interval)
+                    } else {
+                        interval
+                    };
+                }
+                self.last_tick_clock = Some(start_clock);
+                self.pending_ticks = 0;
+            }
+            if self.playing {
+                // catch up with the clock, and don't run ahead of the next tick
+                limit = match self.last_tick {
+                    Some(tick) => {
+                        self.position = self.position.max(tick as f64 / TICKS_PER_QUARTER);
+                        (tick + 1) as f64 / TICKS_PER_QUARTER
+                    }
+                    None => self.position,
+                };
+            }
+            if self.tick_interval > 0. {
+                60. * sample_rate as f64 / (self.tick_interval * TICKS_PER_QUARTER)
+            } else {
+                self.params.tempo.get() as f64
+            }
+        } else {
+            let tempo = self.params.tempo.get() as f64;
+            let playing = self.params.playing.is_on();
+            if playing != self.playing {
+                self.playing = playing;
+                if playing {
+                    self.record_from = self.position;
+                    self.clock_start = Some(self.position);
+                    if self.params.record.is_on() {
+                        // the count in plays the bars before, which are before the song at the start
+                        let bars = self.params.count_in.get_index() as f64;
+                        let (beats, note_value) = time_signature;
+                        self.position -= bars * beats as f64 * 4. / note_value as f64;
+                    }
+                } else {
+                    if self.params.send_clock.is_on() && self.clock_start.is_none() {
+                        self.send(MidiMessage::Stop, start_clock as f64 / sample_rate as f64);
+                    }
+                    self.clock_start = None;
+                }
+                self.sent_ticks = self.position * TICKS_PER_QUARTER;
+            }
+            // keeps going while stopped so whatever follows knows the tempo.
+            // each tick is queued with where it falls in the block, for the writer to space them out
+            let ticks_per_frame = tempo / 60. * TICKS_PER_QUARTER / sample_rate as f64;
+            let end = self.sent_ticks + frames as f64 * ticks_per_frame;
+            if self.params.send_clock.is_on() {
+                let first = self.sent_ticks.ceil().max(0.) as u64;
+                for tick in first..(end.ceil().max(0.) as u64) {
+                    let offset = (tick as f64 - self.sent_ticks) / ticks_per_frame;
+                    let time = (start_clock as f64 + offset) / sample_rate as f64;
+                    if let Some(start) = self.clock_start {
+                        if tick as f64 >= start * TICKS_PER_QUARTER {
+                            self.send_start(start, time);
+                            self.clock_start = None;
+                        }
+                    }
+                    self.send(MidiMessage::TimingClock, time);
+                }
+            } else {
+                // turning it on halfway through shouldn't announce a stale position
+                self.clock_start = None;
+            }
+            self.sent_ticks = end;
+            tempo
+        };
+
+        let start = self.position;
+        if self.playing {
+            self.position =
+                (start + frames as f64 * tempo / 60. / sample_rate as f64).min(limit.max(start));
+        }
+        let count_in = self.playing && !follow && start < self.record_from;
+        let state = TransportState {
+            tempo,
+            playing: self.playing,
+            position: start,
+            end: self.position,
+            time_signature,
+            count_in,
+            recording: self.playing && self.params.record.is_on() && !count_in,
+        };
+        self.params.state.store(state);
+        state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Transport;
+    use crate::midi::MidiQueue;
+    use std::{convert::TryFrom, sync::Arc};
+    use wmidi::{MidiMessage, U14};
+
+    #[test]
+    fn follow_midi_clock() {
+        let mut transport = Transport::new();
+        let params = transport.get_params();
+        params.sync.set(1.);
+        let process = |transport: &mut Transport, message| {
+            assert!(transport.process(message).is_none());
+        };
+        // 3 16ths in, then ticks every 1000 samples with some jitter, which is 120 bpm at 48k
+        process(
+            &mut transport,
+            MidiMessage::SongPositionPointer(U14::try_from(3).unwrap()),
+        );
+        process(&mut transport, MidiMessage::Continue);
+        assert!(params.playing.is_on());
+        for block in 0..100 {
+            process(&mut transport, MidiMessage::TimingClock);
+            transport.run(48000, [900, 1100][block % 2]);
+        }
+        process(&mut transport, MidiMessage::TimingClock);
+        let state = transport.run(48000, 1000);
+        assert!((state.tempo - 120.).abs() < 2.);
+        // the first tick after continuing is at the song position, 18 ticks in, and 100 more since
+        assert!((state.position - 118. / 24.).abs() < 1e-9);
+        // never runs ahead of the next tick
+        assert!(state.end <= 119. / 24.);
+
+        process(&mut transport, MidiMessage::Stop);
+        let state = transport.run(48000, 1000);
+        assert!(!state.playing);
+        assert_eq!(state.position, state.end);
+    }
+
+    #[test]
+    fn count_in_before_recording() {
+        let mut transport = Transport::new();
+        let params = transport.get_params();
+        params.record.set(1.);
+        params.count_in.set(1.);
+        params.time_signature.set_text("3/4").unwrap();
+        params.playing.set(1.);
+        // a bar of 3/4 at 120 bpm is 72000 samples at 48k
+        let state = transport.run(48000, 36000);
+        assert_eq!(-3., state.position);
+        assert!(state.count_in && !state.recording);
+        transport.run(48000, 36000);
+        let state = transport.run(48000, 1000);
+        assert_eq!(0., state.position);
+        assert!(!state.count_in && state.recording);
+    }
+
+    #[test]
+    fn clock_ticks_spaced_within_blocks() {
+        let mut transport = Transport::new();
+        let queue = Arc::new(MidiQueue::new());
+        transport.set_clock_output(queue.clone());
+        transport.get_params().send_clock.set(1.);
+        // a tick every 1000 samples at 120 bpm and 48k, so a 2048 frame block has 2 or 3 of them
+        for _ in 0..4 {
+            transport.run(48000, 2048);
+        }
+        let mut times = vec![];
+        while let Some(message) = queue.pop() {
+            assert_eq!(message.bytes(), [0xf8]);
+            times.push(message.time);
+        }
+        assert_eq!(9, times.len());
+        for (tick, time) in times.iter().enumerate() {
+            assert!(
+                (time - tick as f64 / 48.).abs() < 1e-9,
+                "{} at {}",
+                time,
+                tick
+            );
+        }
+    }
+}
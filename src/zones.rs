@@ -0,0 +1,288 @@
+use anyhow::{anyhow, bail, Result};
+use std::{fmt, str::FromStr};
+
+/// more than anyone would split a keyboard into, zones always have room for this many so the
+/// synth's copy never has to grow
+pub const MAX_ZONES: usize = 64;
+
+/// A key and velocity range of one midi channel, played by a part.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Zone {
+    pub channel: wmidi::Channel,
+    /// index of the part that plays it
+    pub part: usize,
+    pub low_key: u8,
+    pub high_key: u8,
+    pub low_velocity: u8,
+    pub high_velocity: u8,
+    /// in semitones, on top of the part's own transpose
+    pub transpose: i8,
+    /// keys over which the level ramps up from the inner edges of the range, for crossfading splits
+    pub key_fade: u8,
+    /// same for velocity, for crossfading velocity layers
+    pub velocity_fade: u8,
+}
+
+/// Level ramping up from the edges of `low..=high` over `fade` steps.
+/// Edges at the ends of the midi range don't fade since nothing lies beyond them.
+fn edge_fade(value: u8, low: u8, high: u8, fade: u8, min: u8) -> f32 {
+    let steps = fade as f32 + 1.;
+    let mut level = 1f32;
+    if low > min {
+        level = level.min((value - low + 1) as f32 / steps);
+    }
+    if high < 127 {
+        level = level.min((high - value + 1) as f32 / steps);
+    }
+    level
+}
+
+impl Zone {
+    /// All keys and velocities on `channel`.
+    pub fn new(channel: wmidi::Channel, part: usize) -> Self {
+        Self {
+            channel,
+            part,
+            low_key: 0,
+            high_key: 127,
+            low_velocity: 1,
+            high_velocity: 127,
+            transpose: 0,
+            key_fade: 0,
+            velocity_fade: 0,
+        }
+    }
+
+    fn contains(&self, key: u8, velocity: u8) -> bool {
+        (self.low_key..=self.high_key).contains(&key)
+            && (self.low_velocity..=self.high_velocity).contains(&velocity)
+    }
+
+    fn level(&self, key: u8, velocity: u8) -> f32 {
+        edge_fade(key, self.low_key, self.high_key, self.key_fade, 0)
+            * edge_fade(
+                velocity,
+                self.low_velocity,
+                self.high_velocity,
+                self.velocity_fade,
+                1,
+            )
+    }
+}
+
+/// Splits and layers, shared between the ui and the synth.
+///
+/// Channels without zones play their own part.
+///
+/// ```text
+/// channel 1 part 2 keys 0 59 velocities 1 127 transpose 12 key_fade 0 velocity_fade 0
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct Zones {
+    zones: Vec<Zone>,
+}
+
+impl Default for Zones {
+    fn default() -> Self {
+        Self {
+            zones: Vec::with_capacity(MAX_ZONES),
+        }
+    }
+}
+
+// derived clone would only have room for the zones there are, and the synth is cloned for each stream
+impl Clone for Zones {
+    fn clone(&self) -> Self {
+        let mut zones = Self::new();
+        zones.copy_from(self);
+        zones
+    }
+}
+
+impl Zones {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copies `other` over these without allocating, for the audio thread.
+    /// Only the first `MAX_ZONES` are kept.
+    pub fn copy_from(&mut self, other: &Zones) {
+        self.zones.clear();
+        self.zones
+            .extend_from_slice(&other.zones[..other.zones.len().min(MAX_ZONES)]);
+    }
+
+    pub fn zones_mut(&mut self) -> &mut Vec<Zone> {
+        &mut self.zones
+    }
+
+    fn own_part(&self, channel: wmidi::Channel) -> Option<usize> {
+        if self.zones.iter().any(|zone| zone.channel == channel) {
+            None
+        } else {
+            Some(channel.index() as usize)
+        }
+    }
+
+    /// Part, transpose and level for each zone a note falls in. Doesn't allocate.
+    pub fn notes(
+        &self,
+        channel: wmidi::Channel,
+        key: u8,
+        velocity: u8,
+    ) -> impl Iterator<Item = (usize, i8, f32)> + '_ {
+        self.zones
+            .iter()
+            .filter(move |zone| zone.channel == channel && zone.contains(key, velocity))
+            .map(move |zone| (zone.part, zone.transpose, zone.level(key, velocity)))
+            .chain(self.own_part(channel).map(|part| (part, 0, 1.)))
+    }
+
+    /// Every part a channel plays, for note offs and controllers that should follow the notes.
+    pub fn parts(&self, channel: wmidi::Channel) -> impl Iterator<Item = usize> + '_ {
+        self.zones
+            .iter()
+            .filter(move |zone| zone.channel == channel)
+            .map(|zone| zone.part)
+            .chain(self.own_part(channel))
+    }
+}
+
+impl fmt::Display for Zones {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for zone in &self.zones {
+            writeln!(
+                f,
+                "channel {} part {} keys {} {} velocities {} {} transpose {} key_fade {} velocity_fade {}",
+                zone.channel.number(),
+                zone.part + 1,
+                zone.low_key,
+                zone.high_key,
+                zone.low_velocity,
+                zone.high_velocity,
+                zone.transpose,
+                zone.key_fade,
+                zone.velocity_fade
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Zone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut zone = Zone::new(wmidi::Channel::Ch1, 0);
+        let mut words = s.split_whitespace();
+        while let Some(key) = words.next() {
+            // ranges take two numbers, everything else one
+            let count = match key {
+                "keys" | "velocities" => 2,
+                _ => 1,
+            };
+            let mut values = [0i32; 2];
+            for value in values.iter_mut().take(count) {
+                let word = words
+                    .next()
+                    .ok_or_else(|| anyhow!("missing value in zone: {}", s))?;
+                *value = word
+                    .parse()
+                    .map_err(|_| anyhow!("bad value for {}: {}", key, word))?;
+            }
+            let bad_value = || anyhow!("bad value for {} in zone: {}", key, s);
+            let midi = |value: i32| {
+                if (0..128).contains(&value) {
+                    Ok(value as u8)
+                } else {
+                    Err(bad_value())
+                }
+            };
+            match key {
+                "channel" => {
+                    zone.channel = wmidi::Channel::from_index((values[0] - 1) as u8)
+                        .map_err(|_| bad_value())?
+                }
+                "part" => {
+                    if !(1..=crate::synth::NUM_PARTS as i32).contains(&values[0]) {
+                        return Err(bad_value());
+                    }
+                    zone.part = values[0] as usize - 1;
+                }
+                "keys" => {
+                    zone.low_key = midi(values[0])?;
+                    zone.high_key = midi(values[1])?;
+                }
+                "velocities" => {
+                    zone.low_velocity = midi(values[0])?;
+                    zone.high_velocity = midi(values[1])?;
+                }
+                "transpose" => zone.transpose = values[0].clamp(-48, 48) as i8,
+                "key_fade" => zone.key_fade = midi(values[0])?,
+                "velocity_fade" => zone.velocity_fade = midi(values[0])?,
+                // newer versions may know more
+                _ => {}
+            }
+        }
+        if zone.low_key > zone.high_key || zone.low_velocity > zone.high_velocity {
+            bail!("empty zone: {}", s);
+        }
+        Ok(zone)
+    }
+}
+
+impl FromStr for Zones {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut zones = Zones::new();
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if zones.zones.len() == MAX_ZONES {
+                bail!("more than {} zones", MAX_ZONES);
+            }
+            zones.zones.push(line.parse()?);
+        }
+        Ok(zones)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Zone, Zones, MAX_ZONES};
+
+    #[test]
+    fn split_and_layer() {
+        let mut zones = Zones::new();
+        let channel = wmidi::Channel::Ch1;
+        // bass below C3 an octave down, pad above fading in over 4 keys
+        let mut bass = Zone::new(channel, 1);
+        bass.high_key = 47;
+        bass.transpose = -12;
+        let mut pad = Zone::new(channel, 2);
+        pad.low_key = 48;
+        pad.key_fade = 3;
+        zones.zones_mut().push(bass);
+        zones.zones_mut().push(pad);
+
+        let notes = |key, velocity| zones.notes(channel, key, velocity).collect::<Vec<_>>();
+        assert_eq!(vec![(1, -12, 1.)], notes(40, 100));
+        assert_eq!(vec![(2, 0, 0.25)], notes(48, 100));
+        assert_eq!(vec![(2, 0, 1.)], notes(60, 100));
+        // other channels still play their own part
+        assert_eq!(
+            vec![(3, 0, 1.)],
+            zones
+                .notes(wmidi::Channel::Ch4, 60, 100)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec![1, 2], zones.parts(channel).collect::<Vec<_>>());
+
+        let loaded: Zones = zones.to_string().parse().unwrap();
+        assert_eq!(zones, loaded);
+
+        // the synth's copy is cloned for each stream and still mustn't grow
+        let copy = loaded.clone();
+        assert_eq!(copy, zones);
+        assert!(copy.zones.capacity() >= MAX_ZONES);
+    }
+}